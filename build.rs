@@ -0,0 +1,66 @@
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+
+fn main() {
+    // Put the linker fragment in the search path of the linker, so memory.x can include it
+    let out = PathBuf::from(env::var_os("OUT_DIR").unwrap());
+    fs::write(
+        out.join("panic_persist.x"),
+        include_bytes!("panic_persist.x"),
+    )
+    .unwrap();
+    println!("cargo:rustc-link-search={}", out.display());
+
+    // The `mclass` and `thumb-mode` target features are not visible to `cfg` on stable Rust, so
+    // the kind of ARM core is told from the name of the target instead
+    let target = env::var("TARGET").unwrap();
+    println!("cargo:rustc-check-cfg=cfg(armv6m)");
+    println!("cargo:rustc-check-cfg=cfg(armv8m)");
+    println!("cargo:rustc-check-cfg=cfg(cortex_m)");
+    println!("cargo:rustc-check-cfg=cfg(thumb_mode)");
+
+    if ["thumbv6m-", "thumbv7m-", "thumbv7em-", "thumbv8m."]
+        .iter()
+        .any(|prefix| target.starts_with(prefix))
+    {
+        println!("cargo:rustc-cfg=cortex_m");
+    }
+    if target.starts_with("thumbv6m-") {
+        println!("cargo:rustc-cfg=armv6m");
+    }
+    if target.starts_with("thumbv8m.") {
+        println!("cargo:rustc-cfg=armv8m");
+    }
+    if target.starts_with("thumb") {
+        println!("cargo:rustc-cfg=thumb_mode");
+    }
+
+    // The length of the panic region placed in the RTC memory of the ESP32 chips, which must hold
+    // the header and the location block
+    println!("cargo:rerun-if-env-changed=PANIC_PERSIST_RTC_LEN");
+    let rtc = env::var_os("CARGO_FEATURE_ESP32_RTC_FAST").is_some()
+        || env::var_os("CARGO_FEATURE_ESP32_RTC_SLOW").is_some();
+    let rtc_len = match env::var("PANIC_PERSIST_RTC_LEN") {
+        Ok(len) if rtc => len
+            .trim()
+            .parse::<usize>()
+            .ok()
+            .filter(|len| *len >= 32 && len.is_multiple_of(4))
+            .unwrap_or_else(|| {
+                panic!(
+                    "PANIC_PERSIST_RTC_LEN must be a multiple of 4 bytes, of at least 32 bytes, not `{}`",
+                    len
+                )
+            }),
+        _ => 1024,
+    };
+    fs::write(
+        out.join("rtc_len.rs"),
+        format!("const RTC_REGION_LEN: usize = {};\n", rtc_len),
+    )
+    .unwrap();
+
+    println!("cargo:rerun-if-changed=build.rs");
+    println!("cargo:rerun-if-changed=panic_persist.x");
+}
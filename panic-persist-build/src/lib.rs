@@ -0,0 +1,348 @@
+//! Build script helper reserving the region of [`panic-persist`] in a `memory.x` linker script
+//!
+//! Instead of editing `memory.x` by hand to shrink the RAM and declare the `PANDUMP` region, the
+//! build script of the application generates it, from a `memory.in.x` file holding the `MEMORY`
+//! block without the panic region;
+//!
+//! ``` ignore
+//! fn main() {
+//!     // Keep the last 1K of the RAM for the panic messages
+//!     panic_persist_build::reserve_panic_region(1024).unwrap();
+//! }
+//! ```
+//!
+//! The region is taken from the end of the `RAM` region, which is shrunk accordingly, and the
+//! `_panic_dump_start` and `_panic_dump_end` symbols are defined after the `MEMORY` block. The
+//! generated `memory.x` is written to `OUT_DIR`, which is added to the search path of the linker.
+//!
+//! The input file must not be named `memory.x`, otherwise the linker would find it in the package
+//! root before the generated one.
+//!
+//! [`panic-persist`]: https://docs.rs/panic-persist
+
+#![deny(missing_docs)]
+#![deny(warnings)]
+
+use std::env;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+/// Name of the memory region declared for the panic messages
+const PANDUMP: &str = "PANDUMP";
+
+/// Size of the header of the panic region
+const HEADER_LEN: u64 = 16;
+
+/// Size of the location block following the header
+const LOCATION_LEN: u64 = 16;
+
+/// Reserve `len` bytes at the end of the `RAM` region for the panic messages: read `memory.in.x`
+/// from the package root, carve the panic region out of it, and write the result to
+/// `OUT_DIR/memory.x`, which is added to the search path of the linker.
+///
+/// This is meant to be called from a build script.
+pub fn reserve_panic_region(len: u64) -> Result<(), Error> {
+    let root = PathBuf::from(env::var_os("CARGO_MANIFEST_DIR").ok_or(Error::NotInBuildScript)?);
+    let out = PathBuf::from(env::var_os("OUT_DIR").ok_or(Error::NotInBuildScript)?);
+
+    let input = root.join("memory.in.x");
+    let memory_x = fs::read_to_string(&input).map_err(Error::Io)?;
+    fs::write(
+        out.join("memory.x"),
+        carve_panic_region(&memory_x, "RAM", len)?,
+    )
+    .map_err(Error::Io)?;
+
+    println!("cargo:rustc-link-search={}", out.display());
+    println!("cargo:rerun-if-changed={}", input.display());
+
+    Ok(())
+}
+
+/// Take `len` bytes from the end of the `ram` region of `memory_x`, declare them as the `PANDUMP`
+/// region, and define the `_panic_dump_start` and `_panic_dump_end` symbols after the `MEMORY`
+/// block. Returns the updated linker script.
+///
+/// The origin and length of the `ram` region must be plain numbers, with an optional `K` or `M`
+/// suffix, and `len` must be a multiple of 4 bytes, at least large enough to hold the header and
+/// the location block.
+pub fn carve_panic_region(memory_x: &str, ram: &str, len: u64) -> Result<String, Error> {
+    if len < HEADER_LEN + LOCATION_LEN || !len.is_multiple_of(4) {
+        return Err(Error::InvalidLength(len));
+    }
+
+    let (start, end) = memory_block(memory_x).ok_or(Error::NoMemoryBlock)?;
+    let block = &memory_x[start..end];
+
+    let mut lines = Vec::new();
+    let mut found = false;
+
+    for line in block.split_inclusive('\n') {
+        let region = match parse_region(line) {
+            Some(region) => region,
+            None => {
+                lines.push(line.to_string());
+                continue;
+            }
+        };
+
+        if region.name == PANDUMP {
+            return Err(Error::AlreadyReserved);
+        }
+        if region.name != ram || found {
+            lines.push(line.to_string());
+            continue;
+        }
+        found = true;
+
+        let origin = parse_number(region.origin)?;
+        let length = parse_number(region.length)?;
+        if length <= len {
+            return Err(Error::RamTooSmall);
+        }
+
+        let indent = &line[..line.len() - line.trim_start().len()];
+        lines.push(format!(
+            "{}{} : ORIGIN = {:#010X}, LENGTH = {}\n",
+            indent,
+            region.declaration,
+            origin,
+            format_length(length - len)
+        ));
+        lines.push(format!(
+            "{}{} : ORIGIN = {:#010X}, LENGTH = {}\n",
+            indent,
+            PANDUMP,
+            origin + length - len,
+            format_length(len)
+        ));
+    }
+
+    if !found {
+        return Err(Error::NoRamRegion(ram.to_string()));
+    }
+
+    let mut out = String::with_capacity(memory_x.len() + 256);
+    out.push_str(&memory_x[..start]);
+    out.extend(lines);
+    out.push_str(&memory_x[end..]);
+    if !out.ends_with('\n') {
+        out.push('\n');
+    }
+    out.push_str("\n_panic_dump_start = ORIGIN(PANDUMP);\n");
+    out.push_str("_panic_dump_end   = ORIGIN(PANDUMP) + LENGTH(PANDUMP);\n");
+
+    Ok(out)
+}
+
+/// The errors of [`reserve_panic_region`] and [`carve_panic_region`]
+#[derive(Debug)]
+pub enum Error {
+    /// `CARGO_MANIFEST_DIR` or `OUT_DIR` is missing, because this was not called from a build
+    /// script
+    NotInBuildScript,
+    /// The linker script could not be read or written
+    Io(io::Error),
+    /// The linker script has no `MEMORY` block
+    NoMemoryBlock,
+    /// The `MEMORY` block has no region of this name
+    NoRamRegion(String),
+    /// The `MEMORY` block already declares the `PANDUMP` region
+    AlreadyReserved,
+    /// The origin or length of the RAM region is not a plain number
+    UnsupportedExpression(String),
+    /// The panic region is not a multiple of 4 bytes, or can't hold the header and the location
+    /// block
+    InvalidLength(u64),
+    /// The RAM region is not larger than the panic region
+    RamTooSmall,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::NotInBuildScript => write!(f, "not called from a build script"),
+            Error::Io(err) => write!(f, "failed to access the linker script: {}", err),
+            Error::NoMemoryBlock => write!(f, "the linker script has no MEMORY block"),
+            Error::NoRamRegion(name) => write!(f, "the MEMORY block has no {} region", name),
+            Error::AlreadyReserved => write!(f, "the MEMORY block already declares {}", PANDUMP),
+            Error::UnsupportedExpression(expr) => {
+                write!(f, "`{}` is not a plain number", expr)
+            }
+            Error::InvalidLength(len) => write!(
+                f,
+                "the panic region of {} bytes is not a multiple of 4 bytes, or can't hold the {} byte header and the {} byte location",
+                len, HEADER_LEN, LOCATION_LEN
+            ),
+            Error::RamTooSmall => write!(f, "the RAM region is not larger than the panic region"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// A region declared in the `MEMORY` block
+struct Region<'a> {
+    /// The name of the region, along with its attributes
+    declaration: &'a str,
+    name: &'a str,
+    origin: &'a str,
+    length: &'a str,
+}
+
+/// The bounds of the content of the `MEMORY` block, between its braces
+fn memory_block(script: &str) -> Option<(usize, usize)> {
+    let keyword = script.find("MEMORY")?;
+    let start = keyword + script[keyword..].find('{')? + 1;
+    let end = start + script[start..].find('}')?;
+
+    Some((start, end))
+}
+
+/// Parse the declaration of a region, such as `RAM (rwx) : ORIGIN = 0x20000000, LENGTH = 64K`
+fn parse_region(line: &str) -> Option<Region<'_>> {
+    // Comments are left out
+    let line = match line.find("/*") {
+        Some(comment) => &line[..comment],
+        None => line,
+    };
+
+    let (declaration, attributes) = line.split_once(':')?;
+    let declaration = declaration.trim();
+    let name = declaration
+        .split(|c: char| c == '(' || c.is_whitespace())
+        .next()?;
+    if name.is_empty() {
+        return None;
+    }
+
+    let mut origin = None;
+    let mut length = None;
+
+    for attribute in attributes.split(',') {
+        let (key, value) = attribute.split_once('=')?;
+
+        match key.trim() {
+            "ORIGIN" | "org" | "o" => origin = Some(value.trim()),
+            "LENGTH" | "len" | "l" => length = Some(value.trim()),
+            _ => return None,
+        }
+    }
+
+    Some(Region {
+        declaration,
+        name,
+        origin: origin?,
+        length: length?,
+    })
+}
+
+/// Parse a number, in hexadecimal or decimal, with an optional `K` or `M` suffix
+fn parse_number(expr: &str) -> Result<u64, Error> {
+    let unsupported = || Error::UnsupportedExpression(expr.to_string());
+
+    let (digits, multiplier) = match expr.strip_suffix(|c| c == 'K' || c == 'k') {
+        Some(digits) => (digits, 1024),
+        None => match expr.strip_suffix(|c| c == 'M' || c == 'm') {
+            Some(digits) => (digits, 1024 * 1024),
+            None => (expr, 1),
+        },
+    };
+
+    let value = match digits
+        .strip_prefix("0x")
+        .or_else(|| digits.strip_prefix("0X"))
+    {
+        Some(hex) => u64::from_str_radix(hex, 16),
+        None => digits.parse(),
+    }
+    .map_err(|_| unsupported())?;
+
+    value.checked_mul(multiplier).ok_or_else(unsupported)
+}
+
+/// Format a length, in K when it is a whole number of them
+fn format_length(len: u64) -> String {
+    if len.is_multiple_of(1024) {
+        format!("{}K", len / 1024)
+    } else {
+        len.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MEMORY_X: &str = "\
+MEMORY
+{
+  FLASH : ORIGIN = 0x08000000, LENGTH = 256K
+  RAM (rwx) : ORIGIN = 0x20000000, LENGTH = 0x10000 /* SRAM1 */
+}
+";
+
+    #[test]
+    fn carve_end_of_ram() {
+        assert_eq!(
+            carve_panic_region(MEMORY_X, "RAM", 1024).unwrap(),
+            "\
+MEMORY
+{
+  FLASH : ORIGIN = 0x08000000, LENGTH = 256K
+  RAM (rwx) : ORIGIN = 0x20000000, LENGTH = 63K
+  PANDUMP : ORIGIN = 0x2000FC00, LENGTH = 1K
+}
+
+_panic_dump_start = ORIGIN(PANDUMP);
+_panic_dump_end   = ORIGIN(PANDUMP) + LENGTH(PANDUMP);
+"
+        );
+    }
+
+    #[test]
+    fn carve_other_region() {
+        let carved = carve_panic_region(MEMORY_X, "FLASH", 100).unwrap();
+
+        assert!(carved.contains("  FLASH : ORIGIN = 0x08000000, LENGTH = 262044\n"));
+        assert!(carved.contains("  PANDUMP : ORIGIN = 0x0803FF9C, LENGTH = 100\n"));
+    }
+
+    #[test]
+    fn carve_errors() {
+        assert!(matches!(
+            carve_panic_region(MEMORY_X, "RAM", 16),
+            Err(Error::InvalidLength(16))
+        ));
+        assert!(matches!(
+            carve_panic_region(MEMORY_X, "RAM", 1022),
+            Err(Error::InvalidLength(1022))
+        ));
+        assert!(matches!(
+            carve_panic_region(MEMORY_X, "RAM", 64 * 1024),
+            Err(Error::RamTooSmall)
+        ));
+        assert!(matches!(
+            carve_panic_region(MEMORY_X, "SRAM", 1024),
+            Err(Error::NoRamRegion(name)) if name == "SRAM"
+        ));
+        assert!(matches!(
+            carve_panic_region("SECTIONS {}", "RAM", 1024),
+            Err(Error::NoMemoryBlock)
+        ));
+
+        let carved = carve_panic_region(MEMORY_X, "RAM", 1024).unwrap();
+        assert!(matches!(
+            carve_panic_region(&carved, "RAM", 1024),
+            Err(Error::AlreadyReserved)
+        ));
+
+        let memory_x = "MEMORY { RAM : ORIGIN = 0x20000000, LENGTH = 64K - 1K\n}";
+        assert!(matches!(
+            carve_panic_region(memory_x, "RAM", 1024),
+            Err(Error::UnsupportedExpression(expr)) if expr == "64K - 1K"
+        ));
+    }
+}
@@ -0,0 +1,10 @@
+/* Linker symbols of the panic-persist region, which is the PANDUMP memory region. Include this
+   file after the MEMORY block of memory.x, which must declare PANDUMP:
+
+     INCLUDE panic_persist.x
+*/
+
+_panic_dump_start = ORIGIN(PANDUMP);
+_panic_dump_end   = ORIGIN(PANDUMP) + LENGTH(PANDUMP);
+
+ASSERT(LENGTH(PANDUMP) >= 32, "panic-persist: the PANDUMP region can't hold the 16 byte header and the 16 byte location");
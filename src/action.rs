@@ -0,0 +1,141 @@
+//! Action taken by the panic handler once the panic was persisted
+
+use core::sync::atomic::{AtomicPtr, AtomicU8, Ordering};
+
+/// What the panic handler does once the panic was persisted, as set with [`set_panic_action`]
+#[derive(Clone, Copy, Debug)]
+pub enum PanicAction {
+    /// Reset the device, so the message is retrieved on the next boot. This is the default
+    Reset,
+    /// Spin forever with interrupts disabled, leaving the device as it was when it panicked, such
+    /// as for a debugger to attach to it
+    Halt,
+    /// Execute a breakpoint instruction, which stops the core in the attached debugger, then spin
+    /// forever as with [`PanicAction::Halt`]. Without a debugger, the breakpoint faults on
+    /// Cortex-M devices, so this is meant for bring-up. On the architectures this crate can't
+    /// execute a breakpoint on, such as MSP430 and Xtensa, it only spins
+    Breakpoint,
+    /// Execute a breakpoint instruction as with [`PanicAction::Breakpoint`] if a debugger is
+    /// attached, and reset the device otherwise, so the devices in the field behave as with
+    /// [`PanicAction::Reset`]. Whether a debugger is attached is read from the `C_DEBUGEN` bit of
+    /// the `DHCSR` register on Cortex-M, which the software of some Cortex-M0 and M0+ cores can't
+    /// access, so this is not meant for them. The device is always reset on the other
+    /// architectures
+    BreakpointIfDebugged,
+    /// Call a function of the application, which never returns, such as to reset the device
+    /// through a watchdog, or to enter a safe state and wait for an operator
+    Custom(fn() -> !),
+}
+
+/// Marks `PanicAction::Reset` in `ACTION`
+const RESET: u8 = 0;
+
+/// Marks `PanicAction::Halt` in `ACTION`
+const HALT: u8 = 1;
+
+/// Marks `PanicAction::Breakpoint` in `ACTION`
+const BREAKPOINT: u8 = 2;
+
+/// Marks `PanicAction::Custom` in `ACTION`, whose function is in `CUSTOM`
+const CUSTOM_ACTION: u8 = 3;
+
+/// Marks `PanicAction::BreakpointIfDebugged` in `ACTION`
+const BREAKPOINT_IF_DEBUGGED: u8 = 4;
+
+/// The action set with `set_panic_action`
+static ACTION: AtomicU8 = AtomicU8::new(RESET);
+
+/// The function of `PanicAction::Custom`, if it was set
+static CUSTOM: AtomicPtr<()> = AtomicPtr::new(core::ptr::null_mut());
+
+/// Set what the panic handler does once the panic was persisted, instead of resetting the device,
+/// such as to stop at the panic in the debugger during bring-up, and reset the device in
+/// production, with the same panic handler;
+///
+/// ``` ignore
+/// #[cfg(debug_assertions)]
+/// panic_persist::set_panic_action(panic_persist::PanicAction::Breakpoint);
+/// ```
+///
+/// This is meant to be called on boot, before the firmware can panic. With the
+/// `custom-panic-handler` feature, the panic handler of the application decides what happens
+/// instead, and with the `sim` feature, the emulator is exited
+pub fn set_panic_action(action: PanicAction) {
+    let kind = match action {
+        PanicAction::Reset => RESET,
+        PanicAction::Halt => HALT,
+        PanicAction::Breakpoint => BREAKPOINT,
+        PanicAction::BreakpointIfDebugged => BREAKPOINT_IF_DEBUGGED,
+        PanicAction::Custom(f) => {
+            CUSTOM.store(f as *mut (), Ordering::Release);
+            CUSTOM_ACTION
+        }
+    };
+
+    ACTION.store(kind, Ordering::Release);
+}
+
+/// Take the action that was set, once the panic was persisted
+#[cfg(all(
+    not(any(feature = "custom-panic-handler", feature = "sim")),
+    target_os = "none"
+))]
+pub(crate) fn run() -> ! {
+    match ACTION.load(Ordering::Acquire) {
+        HALT => loop {},
+        BREAKPOINT => {
+            breakpoint();
+            loop {}
+        }
+        BREAKPOINT_IF_DEBUGGED if debugger_attached() => {
+            breakpoint();
+            loop {}
+        }
+        CUSTOM_ACTION => {
+            // Only `fn() -> !` pointers are ever stored
+            let f: fn() -> ! = unsafe { core::mem::transmute(CUSTOM.load(Ordering::Acquire)) };
+            f()
+        }
+        _ => crate::arch::reset(),
+    }
+}
+
+/// Execute a breakpoint instruction, on the architectures that have one
+#[cfg(all(
+    not(any(feature = "custom-panic-handler", feature = "sim")),
+    target_os = "none"
+))]
+fn breakpoint() {
+    #[cfg(cortex_m)]
+    cortex_m::asm::bkpt();
+
+    #[cfg(all(target_arch = "arm", not(cortex_m)))]
+    unsafe {
+        core::arch::asm!("bkpt #0", options(nomem, nostack, preserves_flags))
+    }
+
+    #[cfg(target_arch = "aarch64")]
+    unsafe {
+        core::arch::asm!("brk #0", options(nomem, nostack, preserves_flags))
+    }
+
+    #[cfg(any(target_arch = "riscv32", target_arch = "riscv64"))]
+    unsafe {
+        ::riscv::asm::ebreak()
+    }
+}
+
+/// Whether a debugger is attached, as told by the `C_DEBUGEN` bit of `DHCSR` on Cortex-M, which
+/// is not known on the other architectures
+#[cfg(all(
+    not(any(feature = "custom-panic-handler", feature = "sim")),
+    target_os = "none"
+))]
+fn debugger_attached() -> bool {
+    #[cfg(cortex_m)]
+    let attached = cortex_m::peripheral::DCB::is_debugger_attached();
+    #[cfg(not(cortex_m))]
+    let attached = false;
+
+    attached
+}
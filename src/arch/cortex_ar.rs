@@ -0,0 +1,19 @@
+//! ARM Cortex-A and Cortex-R support
+//!
+//! These cores have no architecturally defined way to reset the whole device, so the reset is
+//! performed through the user provided `__panic_persist_reset` hook, see the `custom-reset`
+//! feature.
+
+pub(crate) use super::custom::reset;
+
+/// Disable IRQs and FIQs
+#[cfg(target_arch = "arm")]
+pub(crate) fn disable_interrupts() {
+    unsafe { core::arch::asm!("cpsid if", options(nomem, nostack, preserves_flags)) }
+}
+
+/// Disable IRQs and FIQs
+#[cfg(target_arch = "aarch64")]
+pub(crate) fn disable_interrupts() {
+    unsafe { core::arch::asm!("msr daifset, #3", options(nomem, nostack, preserves_flags)) }
+}
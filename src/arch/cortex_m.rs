@@ -0,0 +1,11 @@
+//! ARM Cortex-M support
+
+/// Disable all interrupts
+pub(crate) fn disable_interrupts() {
+    cortex_m::interrupt::disable();
+}
+
+/// Soft-reset the device through the System Control Block
+pub(crate) fn reset() -> ! {
+    cortex_m::peripheral::SCB::sys_reset();
+}
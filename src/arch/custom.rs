@@ -0,0 +1,18 @@
+//! User provided primitives, enabled by the `custom-reset` feature
+
+extern "Rust" {
+    #[cfg(feature = "custom-reset")]
+    fn __panic_persist_disable_interrupts();
+    fn __panic_persist_reset() -> !;
+}
+
+/// Disable all interrupts through the user provided hook
+#[cfg(feature = "custom-reset")]
+pub(crate) fn disable_interrupts() {
+    unsafe { __panic_persist_disable_interrupts() }
+}
+
+/// Reset the device through the user provided hook
+pub(crate) fn reset() -> ! {
+    unsafe { __panic_persist_reset() }
+}
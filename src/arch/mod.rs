@@ -0,0 +1,72 @@
+//! Architecture specific primitives used by the panic handler
+//!
+//! Each supported architecture provides a way to disable interrupts, so that the panic message
+//! can be written without being interrupted, and a way to soft-reset the device afterwards.
+//! With the `custom-reset` feature, both are provided by the user instead.
+
+#[cfg(any(
+    feature = "custom-reset",
+    all(target_arch = "arm", not(cortex_m)),
+    target_arch = "aarch64"
+))]
+mod custom;
+#[cfg(feature = "custom-reset")]
+pub(crate) use self::custom::{disable_interrupts, reset};
+
+#[cfg(all(not(feature = "custom-reset"), cortex_m))]
+mod cortex_m;
+#[cfg(all(not(feature = "custom-reset"), cortex_m))]
+pub(crate) use self::cortex_m::{disable_interrupts, reset};
+
+#[cfg(all(
+    not(feature = "custom-reset"),
+    any(all(target_arch = "arm", not(cortex_m)), target_arch = "aarch64")
+))]
+mod cortex_ar;
+#[cfg(all(
+    not(feature = "custom-reset"),
+    any(all(target_arch = "arm", not(cortex_m)), target_arch = "aarch64")
+))]
+pub(crate) use self::cortex_ar::{disable_interrupts, reset};
+
+#[cfg(all(
+    not(feature = "custom-reset"),
+    any(target_arch = "riscv32", target_arch = "riscv64")
+))]
+mod riscv;
+#[cfg(all(
+    not(feature = "custom-reset"),
+    any(target_arch = "riscv32", target_arch = "riscv64")
+))]
+pub(crate) use self::riscv::{disable_interrupts, reset};
+
+#[cfg(all(not(feature = "custom-reset"), target_arch = "xtensa"))]
+mod xtensa;
+#[cfg(all(not(feature = "custom-reset"), target_arch = "xtensa"))]
+pub(crate) use self::xtensa::{disable_interrupts, reset};
+
+#[cfg(all(not(feature = "custom-reset"), target_arch = "msp430"))]
+mod msp430;
+#[cfg(all(not(feature = "custom-reset"), target_arch = "msp430"))]
+pub(crate) use self::msp430::{disable_interrupts, reset};
+
+#[cfg(all(
+    not(feature = "custom-reset"),
+    target_arch = "xtensa",
+    not(feature = "esp-hal")
+))]
+compile_error!("panic-persist requires the `esp-hal` feature (or `custom-reset`) on Xtensa targets");
+
+#[cfg(not(any(
+    feature = "custom-reset",
+    target_arch = "arm",
+    target_arch = "aarch64",
+    target_arch = "msp430",
+    target_arch = "riscv32",
+    target_arch = "riscv64",
+    target_arch = "xtensa"
+)))]
+compile_error!(
+    "panic-persist does not support this architecture, enable the `custom-reset` feature \
+     and provide your own reset hooks"
+);
@@ -0,0 +1,19 @@
+//! TI MSP430 support
+
+/// Address of the watchdog control register on the MSP430F5xx/F6xx and FRAM (FR2xx to FR6xx)
+/// families. Older families use a different address, and should use the `custom-reset` feature
+const WDTCTL: *mut u16 = 0x015C as *mut u16;
+
+/// Disable all interrupts
+pub(crate) fn disable_interrupts() {
+    msp430::interrupt::disable();
+}
+
+/// Soft-reset the device
+///
+/// Writing to `WDTCTL` without the `0x5A` password triggers a power up clear (PUC).
+pub(crate) fn reset() -> ! {
+    unsafe { WDTCTL.write_volatile(0x0000) };
+
+    loop {}
+}
@@ -0,0 +1,26 @@
+//! RISC-V support, including the ESP32-C3/C6 family through `esp-hal`
+
+/// Disable all interrupts on the current hart
+pub(crate) fn disable_interrupts() {
+    ::riscv::interrupt::disable();
+}
+
+/// Restart the program
+///
+/// RISC-V has no architecturally defined reset, so this jumps back to the `_start` entry point
+/// (as provided by `riscv-rt`), re-running the normal program initialization. The panic region
+/// is outside of `.bss` and `.data`, so the persisted message survives.
+///
+/// This is only a soft restart: the peripherals, the interrupt controller, the CSRs that
+/// `riscv-rt` does not initialize and the other harts are left as they were. A real reset of the
+/// platform is provided with the `custom-reset` feature.
+#[cfg(not(feature = "esp-hal"))]
+pub(crate) fn reset() -> ! {
+    unsafe { core::arch::asm!("la t0, _start", "jr t0", options(noreturn)) }
+}
+
+/// Soft-reset the chip through `esp-hal`, on the RISC-V based ESP32 chips
+#[cfg(feature = "esp-hal")]
+pub(crate) fn reset() -> ! {
+    esp_hal::system::software_reset()
+}
@@ -0,0 +1,11 @@
+//! Xtensa (ESP32, ESP32-S2, ESP32-S3) support, through `esp-hal`
+
+/// Disable all interrupts on the current core
+pub(crate) fn disable_interrupts() {
+    xtensa_lx::interrupt::disable();
+}
+
+/// Soft-reset the chip through `esp-hal`
+pub(crate) fn reset() -> ! {
+    esp_hal::system::software_reset()
+}
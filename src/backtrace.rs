@@ -0,0 +1,152 @@
+//! Backtrace of the panic, from the chain of frame pointers
+
+#[cfg(cortex_m)]
+use core::cell::UnsafeCell;
+#[cfg(cortex_m)]
+use core::sync::atomic::{AtomicBool, Ordering};
+
+#[cfg(all(feature = "backtrace", target_os = "none", not(cortex_m)))]
+compile_error!("The `backtrace` feature is only supported on Cortex-M targets");
+
+/// The most return addresses a backtrace holds
+pub(crate) const MAX_FRAMES: usize = 16;
+
+/// The return addresses of the frames of the panic, as returned by [`Record::backtrace`]
+///
+/// [`Record::backtrace`]: crate::Record::backtrace
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Backtrace {
+    frames: [u32; MAX_FRAMES],
+    len: usize,
+}
+
+impl Backtrace {
+    /// The return addresses, innermost first: the first one returns into the `core::panicking`
+    /// function that called the panic handler. They are stored as found on the stack, so on Thumb
+    /// targets their lowest bit is set
+    pub fn frames(&self) -> &[u32] {
+        &self.frames[..self.len]
+    }
+
+    /// The backtrace stored in a record as `bytes`, which hold whole little endian words
+    pub(crate) fn from_bytes(bytes: &[u8]) -> Self {
+        let mut backtrace = Self {
+            frames: [0; MAX_FRAMES],
+            len: 0,
+        };
+
+        for (frame, chunk) in backtrace.frames.iter_mut().zip(bytes.chunks_exact(4)) {
+            *frame = u32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]);
+            backtrace.len += 1;
+        }
+
+        backtrace
+    }
+
+    /// The backtrace as stored in a record, and the number of bytes used
+    pub(crate) fn to_bytes(self) -> ([u8; MAX_FRAMES * 4], usize) {
+        let mut bytes = [0; MAX_FRAMES * 4];
+
+        for (chunk, frame) in bytes.chunks_exact_mut(4).zip(self.frames()) {
+            chunk.copy_from_slice(&frame.to_le_bytes());
+        }
+
+        (bytes, self.len * 4)
+    }
+}
+
+/// Serializes the return addresses, as returned by [`Backtrace::frames`]
+#[cfg(feature = "serde")]
+impl serde::Serialize for Backtrace {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.frames().serialize(serializer)
+    }
+}
+
+/// The backtrace walked by `capture`, which is only written from panic context
+#[cfg(cortex_m)]
+struct Walked {
+    backtrace: UnsafeCell<Backtrace>,
+    captured: AtomicBool,
+}
+
+// The backtrace is only written by the panic handler, with interrupts disabled
+#[cfg(cortex_m)]
+unsafe impl Sync for Walked {}
+
+#[cfg(cortex_m)]
+static WALKED: Walked = Walked {
+    backtrace: UnsafeCell::new(Backtrace {
+        frames: [0; MAX_FRAMES],
+        len: 0,
+    }),
+    captured: AtomicBool::new(false),
+};
+
+/// Walk the chain of frame pointers from the frame of the panic handler, which this is inlined
+/// into, so `r7` points to its frame record
+#[cfg(cortex_m)]
+#[inline(always)]
+pub(crate) fn capture() {
+    let fp: usize;
+    unsafe {
+        core::arch::asm!("mov {}, r7", out(reg) fp, options(nomem, nostack, preserves_flags))
+    };
+
+    walk(fp);
+}
+
+/// Walk the chain of frame pointers from `fp`. Each frame record is made of the frame pointer of
+/// the caller, followed by the return address.
+///
+/// The walk stops at the first frame pointer that is not aligned, that does not move up the stack,
+/// or that is above the top of the stack, as given by the `_stack_start` symbol of `cortex-m-rt`
+#[cfg(cortex_m)]
+fn walk(mut fp: usize) {
+    extern "C" {
+        static _stack_start: u8;
+    }
+
+    let top = core::ptr::addr_of!(_stack_start) as usize;
+    let mut backtrace = Backtrace {
+        frames: [0; MAX_FRAMES],
+        len: 0,
+    };
+
+    while backtrace.len < MAX_FRAMES
+        && fp.is_multiple_of(4)
+        && fp != 0
+        && fp <= top.saturating_sub(8)
+    {
+        let record = fp as *const usize;
+        let (caller, lr) = unsafe { (record.read_volatile(), record.add(1).read_volatile()) };
+
+        backtrace.frames[backtrace.len] = lr as u32;
+        backtrace.len += 1;
+
+        // The frames of the callers are further up the stack
+        if caller <= fp {
+            break;
+        }
+        fp = caller;
+    }
+
+    unsafe { WALKED.backtrace.get().write_volatile(backtrace) };
+    WALKED.captured.store(true, Ordering::Release);
+}
+
+/// The backtrace walked by the panic handler, if any
+#[cfg(cortex_m)]
+pub(crate) fn captured() -> Option<Backtrace> {
+    if !WALKED.captured.load(Ordering::Acquire) {
+        return None;
+    }
+
+    Some(unsafe { WALKED.backtrace.get().read_volatile() })
+}
+
+/// The backtrace is only walked on Cortex-M targets
+#[cfg(not(cortex_m))]
+pub(crate) fn captured() -> Option<Backtrace> {
+    None
+}
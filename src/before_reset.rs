@@ -0,0 +1,24 @@
+//! Function putting the hardware in a safe state before the panic handler resets the device
+
+use crate::hook::Hook;
+
+/// Puts the hardware in a safe state
+static BEFORE_RESET: Hook = Hook::new();
+
+/// Register the function called by the panic handler once the panic was
+/// persisted, with interrupts disabled, before the device is reset (or before
+/// the action set with `set_panic_action` is taken).
+///
+/// This is meant to put the hardware in a safe state, such as to disable the
+/// PWM outputs driving a motor, or to de-energize relays, which would
+/// otherwise keep their state until the firmware initializes them again.
+pub fn set_before_reset(f: fn()) {
+    BEFORE_RESET.set(f);
+}
+
+/// Call the registered function, if any. The panic handler of the application decides when the
+/// device is reset with the `custom-panic-handler` feature
+#[cfg_attr(feature = "custom-panic-handler", allow(dead_code))]
+pub(crate) fn call() {
+    BEFORE_RESET.call();
+}
@@ -0,0 +1,20 @@
+//! Function preparing the panic region before the panic handler writes to it
+
+use crate::hook::Hook;
+
+/// Prepares the panic region to be written
+static BEFORE_WRITE: Hook = Hook::new();
+
+/// Register the function called by the panic handler before it touches the
+/// panic region, with interrupts disabled.
+///
+/// This is typically needed when the region is in a protected domain, such
+/// as a backup domain whose write protection must be lifted first.
+pub fn set_before_write(f: fn()) {
+    BEFORE_WRITE.set(f);
+}
+
+/// Call the registered function, if any
+pub(crate) fn call() {
+    BEFORE_WRITE.call();
+}
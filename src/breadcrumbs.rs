@@ -0,0 +1,247 @@
+//! Ring of breadcrumbs kept in front of the panic region, which is frozen by the panic handler
+
+use core::fmt::Write;
+
+use crate::region::Region;
+
+/// The number of breadcrumbs the ring holds
+const SLOTS: usize = 8;
+
+/// Size of a slot: the length of the breadcrumb, followed by its text
+const SLOT_LEN: usize = 32;
+
+/// Size of the ring: the state word and the count of the breadcrumbs left so far, followed by the
+/// slots
+pub(crate) const BREADCRUMBS_LEN: usize = 8 + SLOTS * SLOT_LEN;
+
+/// Marks a ring the breadcrumbs are left in
+const ACTIVE: u32 = 0x0FACADE3;
+
+/// Marks a ring frozen by the panic handler
+const FROZEN: u32 = 0x0FACADE4;
+
+/// Leave a breadcrumb, formatted as with `format_args!`, which is kept with the panic record if
+/// the firmware panics later on;
+///
+/// ``` ignore
+/// panic_persist::breadcrumb!("entering dfu");
+/// panic_persist::breadcrumb!("command {}", id);
+/// ```
+///
+/// See [`leave_breadcrumb`] for the details.
+#[macro_export]
+macro_rules! breadcrumb {
+    ($($arg:tt)+) => {
+        $crate::leave_breadcrumb(::core::format_args!($($arg)+))
+    };
+}
+
+/// The words of the ring, if the memory of the region can hold it
+fn words() -> Option<*mut u32> {
+    let ring = Region::breadcrumbs()?;
+
+    // The words of the ring are accessed with aligned 32 bit reads and writes
+    if !(ring as usize).is_multiple_of(4) {
+        return None;
+    }
+
+    Some(ring.cast::<u32>())
+}
+
+/// The slot at `index`
+fn slot(words: *mut u32, index: u32) -> *mut u8 {
+    words
+        .cast::<u8>()
+        .wrapping_add(8 + (index as usize % SLOTS) * SLOT_LEN)
+}
+
+/// Formats a breadcrumb into a slot, dropping what does not fit
+struct SlotWriter {
+    slot: *mut u8,
+    len: usize,
+}
+
+impl Write for SlotWriter {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        // The breadcrumb is cut at a character boundary
+        let mut len = s.len().min(SLOT_LEN - 1 - self.len);
+        while !s.is_char_boundary(len) {
+            len -= 1;
+        }
+
+        unsafe {
+            core::ptr::copy_nonoverlapping(s.as_ptr(), self.slot.add(1 + self.len), len);
+        }
+        self.len += len;
+
+        Ok(())
+    }
+}
+
+/// Leave a breadcrumb in the ring, which keeps the last 8 of them, so the breadcrumbs left before
+/// a panic are returned by [`get_panic_breadcrumbs`] on the next boot, as a trail of what the
+/// firmware did. Each breadcrumb keeps its first 31 bytes.
+///
+/// It is not meant to be called from several contexts at once, such as from thread mode and from
+/// an interrupt handler, and breadcrumbs are ignored while the ring still holds the ones of the
+/// last panic, until [`clear_panic_breadcrumbs`] is called
+pub fn leave_breadcrumb(args: core::fmt::Arguments<'_>) {
+    let words = match words() {
+        Some(words) => words,
+        None => return,
+    };
+
+    match unsafe { words.read_volatile() } {
+        FROZEN => return,
+        ACTIVE => {}
+        // The ring lost power
+        _ => clear_panic_breadcrumbs(),
+    }
+
+    let count = unsafe { words.add(1).read_volatile() };
+    let slot = slot(words, count);
+
+    // The length is cleared first, so a panic in between never freezes half of a breadcrumb
+    unsafe { slot.write_volatile(0) };
+
+    let mut out = SlotWriter { slot, len: 0 };
+    out.write_fmt(args).ok();
+
+    unsafe {
+        slot.write_volatile(out.len as u8);
+        words.add(1).write_volatile(count.wrapping_add(1));
+    }
+}
+
+/// The breadcrumbs left with [`leave_breadcrumb`] before the firmware panicked, oldest first.
+///
+/// The ring is left as it is, so the breadcrumbs are returned until [`clear_panic_breadcrumbs`] is
+/// called
+pub fn get_panic_breadcrumbs() -> Breadcrumbs {
+    let words = match words() {
+        Some(words) if unsafe { words.read_volatile() } == FROZEN => words,
+        _ => {
+            return Breadcrumbs {
+                words: core::ptr::null_mut(),
+                next: 0,
+                end: 0,
+            }
+        }
+    };
+
+    let end = unsafe { words.add(1).read_volatile() };
+
+    Breadcrumbs {
+        words,
+        next: end.wrapping_sub(end.min(SLOTS as u32)),
+        end,
+    }
+}
+
+/// Clear the breadcrumbs of the ring, and let [`leave_breadcrumb`] leave new ones. This is meant to
+/// be called on boot, once the breadcrumbs of the last panic were read
+pub fn clear_panic_breadcrumbs() {
+    if let Some(words) = words() {
+        unsafe {
+            words.write_volatile(ACTIVE);
+            words.add(1).write_volatile(0);
+        }
+    }
+}
+
+/// Freeze the ring, so the breadcrumbs it holds are kept along with the panic record
+pub(crate) fn freeze() {
+    if let Some(words) = words() {
+        if unsafe { words.read_volatile() } == ACTIVE {
+            unsafe { words.write_volatile(FROZEN) };
+        }
+    }
+}
+
+/// The breadcrumbs left before the last panic, as returned by [`get_panic_breadcrumbs`]
+pub struct Breadcrumbs {
+    words: *mut u32,
+    next: u32,
+    end: u32,
+}
+
+impl Iterator for Breadcrumbs {
+    type Item = &'static str;
+
+    fn next(&mut self) -> Option<&'static str> {
+        while self.next != self.end {
+            let slot = slot(self.words, self.next);
+            self.next = self.next.wrapping_add(1);
+
+            // Breadcrumbs whose length is cleared were being left when the firmware panicked
+            let len = unsafe { slot.read_volatile() } as usize;
+            if len == 0 || len >= SLOT_LEN {
+                continue;
+            }
+
+            let text = unsafe { core::slice::from_raw_parts(slot.add(1), len) };
+            if let Ok(text) = core::str::from_utf8(text) {
+                return Some(text);
+            }
+        }
+
+        None
+    }
+}
+
+#[cfg(all(test, feature = "runtime-region"))]
+mod tests {
+    use super::*;
+    use crate::region::with_test_region;
+
+    #[test]
+    fn last_breadcrumbs_are_frozen_by_a_panic() {
+        with_test_region(1024, |_| {
+            // The ring lost power, so it is cleared by the first breadcrumb
+            for index in 0..10 {
+                crate::breadcrumb!("step {}", index);
+            }
+
+            // The breadcrumbs are only returned once they were frozen
+            assert_eq!(get_panic_breadcrumbs().count(), 0);
+            freeze();
+            let expected = [
+                "step 2", "step 3", "step 4", "step 5", "step 6", "step 7", "step 8", "step 9",
+            ];
+            assert!(get_panic_breadcrumbs().eq(expected.iter().copied()));
+
+            // The breadcrumbs of the panic are kept until they are cleared
+            crate::breadcrumb!("step 10");
+            assert_eq!(get_panic_breadcrumbs().last(), Some("step 9"));
+
+            clear_panic_breadcrumbs();
+            assert_eq!(get_panic_breadcrumbs().count(), 0);
+            crate::breadcrumb!("step 11");
+            freeze();
+            assert!(get_panic_breadcrumbs().eq(["step 11"].iter().copied()));
+        });
+    }
+
+    #[test]
+    fn long_breadcrumb_is_cut_at_a_character_boundary() {
+        // The 31st byte is the first one of the last character
+        const LONG: &str = "xxxxxxxxxxxxxxxxxxxxxxxxxxxxxxé";
+
+        with_test_region(1024, |_| {
+            clear_panic_breadcrumbs();
+            crate::breadcrumb!("{}", LONG);
+            freeze();
+
+            assert_eq!(get_panic_breadcrumbs().next(), Some(&LONG[..30]));
+        });
+    }
+
+    #[test]
+    fn random_content_holds_no_breadcrumbs() {
+        with_test_region(1024, |_| {
+            freeze();
+
+            assert_eq!(get_panic_breadcrumbs().count(), 0);
+        });
+    }
+}
@@ -0,0 +1,355 @@
+//! Validation of the panic region at startup
+
+use core::ops::Range;
+
+use crate::crc::crc32;
+use crate::header::{Header, FORMAT_VERSION};
+use crate::location::{Payload, LOCATION_LEN};
+use crate::region::Region;
+use crate::{record_regions, HEADER_LEN, RECORD_MAGIC};
+
+/// The panic region, as validated by [`check_region`]
+#[derive(Clone, Copy, Debug)]
+pub struct RegionInfo {
+    start: usize,
+    len: usize,
+    max_message_len: usize,
+}
+
+impl RegionInfo {
+    /// The address of the memory holding the region
+    pub fn start(&self) -> usize {
+        self.start
+    }
+
+    /// The length of the memory holding the region
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Whether the memory holding the region is empty, which [`check_region`] never reports
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// The longest message the region of the current core can hold
+    pub fn max_message_len(&self) -> usize {
+        self.max_message_len
+    }
+}
+
+/// The ways the panic region can be misconfigured, as reported by [`check_region`]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RegionError {
+    /// There is no panic region, such as on hosted targets, or before `init_with_region` was
+    /// called with the `runtime-region` feature
+    Unavailable,
+    /// The `_panic_dump_end` linker symbol is before `_panic_dump_start`
+    Reversed {
+        /// The address of `_panic_dump_start`
+        start: usize,
+        /// The address of `_panic_dump_end`
+        end: usize,
+    },
+    /// The region is not aligned to 4 bytes
+    Misaligned {
+        /// The address of the region
+        start: usize,
+    },
+    /// The region of the current core can't hold the header and the location block
+    TooSmall {
+        /// The length of the region of the current core
+        len: usize,
+    },
+    /// The region overlaps a section used by the program, which would overwrite it
+    Overlaps {
+        /// The name of the section, `.data`, `.bss` or `stack`
+        section: &'static str,
+    },
+}
+
+/// Check that the panic region is usable, and where it is. This is meant to be called on boot, as
+/// a misconfigured region does not fail otherwise: no message is ever returned.
+///
+/// The region must be properly ordered, aligned to 4 bytes, and able to hold the header and the
+/// location block. On Cortex-M and RISC-V targets, it must also be outside of the `.data` and
+/// `.bss` sections and of the stack, as given by the `__sdata`, `__edata`, `__sbss`, `__ebss` and
+/// `_stack_start` symbols of `cortex-m-rt` and `riscv-rt`. The stack is bounded by `__sheap` with
+/// `cortex-m-rt`, as it grows down towards the heap, and by `__estack` with `riscv-rt`, so the
+/// region may be placed in the `.uninit` section.
+pub fn check_region() -> Result<RegionInfo, RegionError> {
+    if let Some((start, end)) = Region::symbols() {
+        if end < start {
+            return Err(RegionError::Reversed { start, end });
+        }
+    }
+
+    let memory = Region::memory();
+    let start = memory.start as usize;
+    if memory.start.is_null() {
+        return Err(RegionError::Unavailable);
+    }
+    if !start.is_multiple_of(4) {
+        return Err(RegionError::Misaligned { start });
+    }
+
+    let current = Region::current();
+    // Each record of the ring only takes a slot of the region
+    #[cfg(feature = "panic-ring")]
+    let current = crate::ring::slot_of(current, 0);
+    if current.len < HEADER_LEN + LOCATION_LEN {
+        return Err(RegionError::TooSmall { len: current.len });
+    }
+
+    let region = start..start + memory.len;
+    for (section, range) in sections().iter() {
+        if region.start < range.end && range.start < region.end {
+            return Err(RegionError::Overlaps { section });
+        }
+    }
+
+    Ok(RegionInfo {
+        start,
+        len: memory.len,
+        max_message_len: current.len - HEADER_LEN - LOCATION_LEN,
+    })
+}
+
+/// The state of the panic region, as reported by [`validate_region`]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RegionState {
+    /// The region holds no record: it was never written, the record was already retrieved, or the
+    /// region was wiped (or lost power) since. A region that can't hold the header is empty too,
+    /// which [`check_region`] reports
+    Empty,
+    /// The region holds a valid record, which the `get_panic_message_*` functions return
+    ValidRecord {
+        /// The length of the payload of the record, the location and the extra fields included
+        len: usize,
+    },
+    /// The header of the record is marked, but the length it holds does not fit in the region, or
+    /// the lengths of the location block do not fit in the payload
+    CorruptLength {
+        /// The length of the payload, as stored in the header
+        len: usize,
+    },
+    /// The payload of the record does not match the CRC of its header, as it was overwritten
+    BadChecksum,
+    /// The record was written with another layout of the header, such as by an older or a newer
+    /// version of this crate, which can't be parsed
+    WrongVersion {
+        /// The version of the layout, as stored in the header
+        version: u8,
+    },
+}
+
+impl RegionState {
+    /// How telling the state is, to pick the state of a slot of the region
+    fn rank(&self) -> u8 {
+        match self {
+            RegionState::Empty => 0,
+            RegionState::CorruptLength { .. }
+            | RegionState::BadChecksum
+            | RegionState::WrongVersion { .. } => 1,
+            RegionState::ValidRecord { .. } => 2,
+        }
+    }
+}
+
+/// Tell what the panic region holds, rather than whether it holds a valid record, which is all the
+/// `get_panic_message_*` functions return. This pinpoints why no message is returned: whether the
+/// region is wiped on boot (or misconfigured, which [`check_region`] reports), or whether the
+/// record is corrupted.
+///
+/// The record is left as it is. When the region holds several records, such as with the
+/// `panic-ring` or `rp2040` features, a valid record is reported first, then a corrupted one
+pub fn validate_region() -> RegionState {
+    record_regions()
+        .map(state_of)
+        .max_by_key(RegionState::rank)
+        .unwrap_or(RegionState::Empty)
+}
+
+/// The state of the record of `region`
+fn state_of(region: Region) -> RegionState {
+    if !crate::has_magic(region, RECORD_MAGIC) {
+        return RegionState::Empty;
+    }
+
+    let header = unsafe { Header::read(region.start) };
+    if header.version != FORMAT_VERSION {
+        return RegionState::WrongVersion {
+            version: header.version,
+        };
+    }
+
+    let len = header.len as usize;
+    if len > region.len - HEADER_LEN {
+        return RegionState::CorruptLength { len };
+    }
+
+    let payload = unsafe { core::slice::from_raw_parts(region.start.add(HEADER_LEN), len) };
+    if header.crc != crc32(payload) {
+        return RegionState::BadChecksum;
+    }
+
+    match Payload::parse(payload) {
+        Some(_) => RegionState::ValidRecord { len },
+        None => RegionState::CorruptLength { len },
+    }
+}
+
+/// The sections of the program the region must not overlap, from the symbols of the runtime
+#[cfg(all(
+    target_os = "none",
+    any(
+        cortex_m,
+        all(
+            any(target_arch = "riscv32", target_arch = "riscv64"),
+            not(feature = "esp-hal")
+        )
+    )
+))]
+fn sections() -> [(&'static str, Range<usize>); 3] {
+    extern "C" {
+        static __sdata: u8;
+        static __edata: u8;
+        static __sbss: u8;
+        static __ebss: u8;
+        #[cfg(cortex_m)]
+        static __sheap: u8;
+        #[cfg(not(cortex_m))]
+        static __estack: u8;
+        static _stack_start: u8;
+    }
+
+    let sdata = core::ptr::addr_of!(__sdata) as usize;
+    let edata = core::ptr::addr_of!(__edata) as usize;
+    let sbss = core::ptr::addr_of!(__sbss) as usize;
+    let ebss = core::ptr::addr_of!(__ebss) as usize;
+    let stack_start = core::ptr::addr_of!(_stack_start) as usize;
+
+    // The stack of `cortex-m-rt` grows down from its start towards the heap, which follows the
+    // `.uninit` section the region may be placed in. The heap of `riscv-rt` has a fixed size, and
+    // the stack its own section after it
+    #[cfg(cortex_m)]
+    let stack_end = core::ptr::addr_of!(__sheap) as usize;
+    #[cfg(not(cortex_m))]
+    let stack_end = core::ptr::addr_of!(__estack) as usize;
+
+    [
+        (".data", sdata..edata),
+        (".bss", sbss..ebss),
+        ("stack", stack_end..stack_start),
+    ]
+}
+
+/// The sections of the program are not known for the other runtimes
+#[cfg(not(all(
+    target_os = "none",
+    any(
+        cortex_m,
+        all(
+            any(target_arch = "riscv32", target_arch = "riscv64"),
+            not(feature = "esp-hal")
+        )
+    )
+)))]
+fn sections() -> [(&'static str, Range<usize>); 0] {
+    []
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sink::PanicSink;
+    use crate::RamSink;
+
+    /// Memory holding a region, aligned as the panic region is
+    #[repr(align(4))]
+    struct Memory([u8; 128]);
+
+    impl Memory {
+        /// Memory holding the record of a panic
+        fn with_record() -> Self {
+            let mut memory = Memory([0; 128]);
+            let mut sink = RamSink::in_region(memory.region());
+            sink.location(core::panic::Location::caller());
+            sink.write(b"index out of bounds");
+            sink.finalize();
+
+            memory
+        }
+
+        fn region(&mut self) -> Region {
+            Region {
+                start: self.0.as_mut_ptr(),
+                len: self.0.len(),
+            }
+        }
+    }
+
+    #[test]
+    fn state_of_each_record() {
+        let mut memory = Memory([0; 128]);
+        assert_eq!(state_of(memory.region()), RegionState::Empty);
+
+        let mut memory = Memory::with_record();
+        // The length of the payload follows the magic word and the version, core, flags and
+        // repeats bytes
+        let len =
+            u32::from_le_bytes([memory.0[8], memory.0[9], memory.0[10], memory.0[11]]) as usize;
+        assert_eq!(state_of(memory.region()), RegionState::ValidRecord { len });
+
+        let mut corrupted = Memory::with_record();
+        corrupted.0[HEADER_LEN + 1] ^= 1;
+        assert_eq!(state_of(corrupted.region()), RegionState::BadChecksum);
+
+        let mut other_version = Memory::with_record();
+        other_version.0[4] = FORMAT_VERSION + 1;
+        assert_eq!(
+            state_of(other_version.region()),
+            RegionState::WrongVersion {
+                version: FORMAT_VERSION + 1
+            }
+        );
+
+        let mut too_long = Memory::with_record();
+        too_long.0[8..12].copy_from_slice(&200u32.to_le_bytes());
+        assert_eq!(
+            state_of(too_long.region()),
+            RegionState::CorruptLength { len: 200 }
+        );
+    }
+
+    #[cfg(not(feature = "runtime-region"))]
+    #[test]
+    fn hosted_region_is_unavailable() {
+        assert_eq!(check_region().unwrap_err(), RegionError::Unavailable);
+        assert_eq!(validate_region(), RegionState::Empty);
+    }
+
+    #[cfg(feature = "runtime-region")]
+    #[test]
+    fn runtime_region_is_checked() {
+        use crate::region::{set_runtime_region, with_test_region};
+
+        with_test_region(2048, |start| {
+            let info = check_region().unwrap();
+            assert_eq!(info.start(), start as usize);
+            assert_eq!(info.len(), 2048);
+            assert!(info.max_message_len() > 0);
+
+            set_runtime_region(start.wrapping_add(1), 2047);
+            assert_eq!(
+                check_region().unwrap_err(),
+                RegionError::Misaligned {
+                    start: start as usize + 1
+                }
+            );
+
+            set_runtime_region(start, 4);
+            assert!(matches!(check_region(), Err(RegionError::TooSmall { .. })));
+        });
+    }
+}
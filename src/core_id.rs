@@ -0,0 +1,52 @@
+//! Id of the core writing a record, stored in its header
+
+use core::sync::atomic::{AtomicPtr, Ordering};
+
+/// The source of the core id registered with `set_core_id_source`, if any
+static CORE_ID_SOURCE: AtomicPtr<()> = AtomicPtr::new(core::ptr::null_mut());
+
+/// Register the function returning the id of the core it runs on, which is stored in the header of
+/// every record, and returned by [`Record::core`]. This is meant to be called once at startup,
+/// before any panic can happen.
+///
+/// Without a source, the id is read from the SIO with the `rp2040` feature, and from the Aff0 field
+/// of MPIDR_EL1 on bare metal AArch64. It is 0 otherwise
+///
+/// [`Record::core`]: crate::Record::core
+pub fn set_core_id_source(source: fn() -> u8) {
+    CORE_ID_SOURCE.store(source as *mut (), Ordering::Release);
+}
+
+/// The id of the current core, from the registered source, if any
+pub(crate) fn current() -> u8 {
+    let ptr = CORE_ID_SOURCE.load(Ordering::Acquire);
+
+    if ptr.is_null() {
+        return read();
+    }
+
+    // Only `fn() -> u8` pointers are ever stored
+    let source: fn() -> u8 = unsafe { core::mem::transmute(ptr) };
+    source()
+}
+
+/// The id of the current core, as read from the SIO
+#[cfg(feature = "rp2040")]
+fn read() -> u8 {
+    crate::rp2040::core_id() as u8
+}
+
+/// The id of the current core, as read from the affinity level 0 of MPIDR_EL1
+#[cfg(all(not(feature = "rp2040"), target_arch = "aarch64", target_os = "none"))]
+fn read() -> u8 {
+    let mpidr: u64;
+    unsafe { core::arch::asm!("mrs {}, mpidr_el1", out(reg) mpidr, options(nomem, nostack)) };
+
+    mpidr as u8
+}
+
+/// There is no standard way to tell the cores apart on other targets
+#[cfg(not(any(feature = "rp2040", all(target_arch = "aarch64", target_os = "none"))))]
+fn read() -> u8 {
+    0
+}
@@ -0,0 +1,94 @@
+//! Counts of the persisted panics, kept in front of the panic region
+
+use crate::region::Region;
+
+/// Size of the counter block: two counts, each followed by its complement, so random memory
+/// content after a power cycle is not mistaken for a count
+pub(crate) const COUNTER_LEN: usize = 16;
+
+/// Index of the count of all the persisted panics in the counter block
+const TOTAL: usize = 0;
+
+/// Index of the count of the panics persisted since the last healthy boot in the counter block
+const CONSECUTIVE: usize = 1;
+
+/// The words of the counter block, if the memory of the region can hold it
+fn words() -> Option<*mut u32> {
+    let block = Region::counter()?;
+
+    // The block is accessed with aligned 32 bit reads and writes
+    if !(block as usize).is_multiple_of(4) {
+        return None;
+    }
+
+    Some(block.cast::<u32>())
+}
+
+/// The count at `index` in the counter block, or 0 if it is not valid
+fn read(index: usize) -> u32 {
+    let words = match words() {
+        Some(words) => words,
+        None => return 0,
+    };
+
+    let (count, check) = unsafe {
+        (
+            words.add(2 * index).read_volatile(),
+            words.add(2 * index + 1).read_volatile(),
+        )
+    };
+    match check == !count {
+        true => count,
+        false => 0,
+    }
+}
+
+/// Store `count` at `index` in the counter block
+fn write(index: usize, count: u32) {
+    if let Some(words) = words() {
+        unsafe {
+            words.add(2 * index).write_volatile(count);
+            words.add(2 * index + 1).write_volatile(!count);
+        }
+    }
+}
+
+/// The number of panics persisted since the count was last reset with [`reset_panic_count`], or
+/// since the region lost power. Unlike the panic message, the count is not cleared when it is read.
+///
+/// The count saturates at `u32::MAX`
+pub fn panic_count() -> u32 {
+    read(TOTAL)
+}
+
+/// Reset the count of the persisted panics, as returned by [`panic_count`], to zero
+pub fn reset_panic_count() {
+    write(TOTAL, 0);
+}
+
+/// The number of panics persisted in a row, since the firmware last reported a healthy boot with
+/// [`mark_boot_ok`], or since the region lost power.
+///
+/// The count saturates at `u32::MAX`
+pub fn consecutive_panics() -> u32 {
+    read(CONSECUTIVE)
+}
+
+/// Whether the firmware panicked at least `threshold` times in a row without reaching a healthy
+/// state, in which case it should enter a safe or recovery mode instead of panicking again
+pub fn is_boot_looping(threshold: u32) -> bool {
+    consecutive_panics() >= threshold
+}
+
+/// Report that the firmware reached a healthy state, such as once it has been running for a while,
+/// which resets the count of [`consecutive_panics`] to zero. The count of all the panics, as
+/// returned by [`panic_count`], is kept
+pub fn mark_boot_ok() {
+    write(CONSECUTIVE, 0);
+}
+
+/// Count one more persisted panic
+pub(crate) fn increment() {
+    write(TOTAL, read(TOTAL).saturating_add(1));
+    write(CONSECUTIVE, read(CONSECUTIVE).saturating_add(1));
+}
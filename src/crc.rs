@@ -0,0 +1,58 @@
+//! CRC-32 of the persisted messages, so random memory content is not taken for one
+
+/// Running CRC-32, as used by Ethernet and zlib. It is computed bit by bit, so it does not need a
+/// table in flash
+#[derive(Clone, Copy)]
+pub(crate) struct Crc32(u32);
+
+impl Crc32 {
+    /// The CRC of no bytes yet
+    pub(crate) const fn new() -> Self {
+        Self(!0)
+    }
+
+    /// Add `bytes` to the CRC
+    pub(crate) fn update(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 ^= u32::from(*byte);
+
+            for _ in 0..8 {
+                self.0 = (self.0 >> 1) ^ (0xEDB8_8320 & (self.0 & 1).wrapping_neg());
+            }
+        }
+    }
+
+    /// The CRC of all the bytes added so far
+    pub(crate) fn finish(self) -> u32 {
+        !self.0
+    }
+}
+
+/// The CRC-32 of `bytes`
+pub(crate) fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = Crc32::new();
+    crc.update(bytes);
+    crc.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn check_value() {
+        // The check value of the CRC-32 used by Ethernet and zlib
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+        assert_eq!(crc32(b""), 0);
+    }
+
+    #[test]
+    fn update_in_pieces() {
+        let mut crc = Crc32::new();
+        crc.update(b"1234");
+        crc.update(b"");
+        crc.update(b"56789");
+
+        assert_eq!(crc.finish(), crc32(b"123456789"));
+    }
+}
@@ -0,0 +1,16 @@
+//! Cortex-M7 data cache maintenance
+
+use crate::region::Region;
+
+/// Clean the data cache over `region`, so its content reaches the RAM before a reset. This also
+/// issues the DSB and ISB barriers
+#[cfg(all(cortex_m, not(armv6m)))]
+pub(crate) fn clean(region: Region) {
+    let mut scb = unsafe { cortex_m::Peripherals::steal() }.SCB;
+
+    scb.clean_dcache_by_address(region.start as usize, region.len);
+}
+
+/// There is no data cache to clean on other targets
+#[cfg(not(all(cortex_m, not(armv6m))))]
+pub(crate) fn clean(_region: Region) {}
@@ -0,0 +1,109 @@
+//! Counting the repeats of a panic, instead of persisting it again
+
+use crate::header::Header;
+use crate::region::Region;
+use crate::{has_magic, message_of, RECORD_MAGIC};
+
+/// Whether `region` holds a record that was not read yet, of a panic at `line` and `column` of
+/// `file`
+pub(crate) fn is_repeat(region: Region, line: u32, column: u32, file: &[u8]) -> bool {
+    if line == 0 || !has_magic(region, RECORD_MAGIC) {
+        return false;
+    }
+
+    match message_of(region).and_then(|payload| payload.location) {
+        Some(location) => {
+            location.line() == line
+                && location.column() == column
+                && location.file().as_bytes() == file
+        }
+        None => false,
+    }
+}
+
+/// Count one more repeat of the panic of the record of `region`. Only the word of the header
+/// holding the count is written, at once, and the count is not covered by the CRC of the payload
+pub(crate) fn count_repeat(region: Region) {
+    let mut header = unsafe { Header::read(region.start) };
+    header.repeats = header.repeats.saturating_add(1);
+
+    let bytes = header.to_bytes();
+    let word = [bytes[4], bytes[5], bytes[6], bytes[7]];
+
+    #[cfg(feature = "aligned-writes")]
+    unsafe {
+        region
+            .start
+            .add(4)
+            .cast::<u32>()
+            .write_volatile(u32::from_ne_bytes(word))
+    };
+
+    #[cfg(not(feature = "aligned-writes"))]
+    unsafe {
+        region.start.add(4).cast::<[u8; 4]>().write_unaligned(word)
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use core::panic::Location;
+
+    use super::*;
+    use crate::record::decode;
+    use crate::sink::PanicSink;
+    use crate::RamSink;
+
+    /// Memory holding a region, aligned as the panic region is
+    #[repr(align(4))]
+    struct Memory([u8; 128]);
+
+    impl Memory {
+        fn region(&mut self) -> Region {
+            Region {
+                start: self.0.as_mut_ptr(),
+                len: self.0.len(),
+            }
+        }
+    }
+
+    /// Persist a panic at `location` with `message` to the region of `memory`
+    fn persist(memory: &mut Memory, location: &Location<'_>, message: &[u8]) {
+        let mut sink = RamSink::in_region(memory.region());
+        sink.location(location);
+        sink.write(message);
+        sink.finalize();
+    }
+
+    #[test]
+    fn repeats_of_the_same_panic_are_counted() {
+        let mut memory = Memory([0; 128]);
+        let location = Location::caller();
+        persist(&mut memory, location, b"first");
+        persist(&mut memory, location, b"second");
+        persist(&mut memory, location, b"third");
+
+        let record = decode(&memory.0, RECORD_MAGIC).unwrap();
+        assert_eq!(record.occurrences(), 3);
+        assert_eq!(record.message(), b"first");
+    }
+
+    #[test]
+    fn panic_at_another_location_is_persisted() {
+        let mut memory = Memory([0; 128]);
+        persist(&mut memory, Location::caller(), b"first");
+        persist(&mut memory, Location::caller(), b"second");
+
+        let record = decode(&memory.0, RECORD_MAGIC).unwrap();
+        assert_eq!(record.occurrences(), 1);
+        assert_eq!(record.message(), b"second");
+    }
+
+    #[test]
+    fn panic_without_location_is_never_a_repeat() {
+        let mut memory = Memory([0; 128]);
+        persist(&mut memory, Location::caller(), b"first");
+
+        assert!(!is_repeat(memory.region(), 0, 0, b""));
+    }
+}
@@ -0,0 +1,9 @@
+//! Logging of the panic message through `defmt`, in addition to persisting it
+
+use core::panic::PanicInfo;
+
+/// Log the panic message as an error, and flush the `defmt` transport
+pub(crate) fn write(info: &PanicInfo) {
+    defmt::error!("{}", defmt::Display2Format(info));
+    defmt::flush();
+}
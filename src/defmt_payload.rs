@@ -0,0 +1,91 @@
+//! Persisting the `defmt` frame of a `defmt::panic!`, instead of the rendered panic message
+
+use core::cell::UnsafeCell;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+use crate::sink::PanicSink;
+
+/// The most bytes of an encoded frame that are kept. Longer frames can't be persisted
+const FRAME_LEN: usize = 256;
+
+/// The encoded bytes of the last frame logged through `defmt`
+struct LastFrame {
+    bytes: UnsafeCell<[u8; FRAME_LEN]>,
+    len: AtomicUsize,
+}
+
+// The frame is only written by the global logger, which holds its own lock while a frame is being
+// logged, and read by the panic handler
+unsafe impl Sync for LastFrame {}
+
+static LAST_FRAME: LastFrame = LastFrame {
+    bytes: UnsafeCell::new([0; FRAME_LEN]),
+    len: AtomicUsize::new(0),
+};
+
+/// Whether the last frame is the message of a `defmt::panic!`, as reported by [`defmt_panic`]
+static PANIC_FRAME: AtomicBool = AtomicBool::new(false);
+
+/// Report the start of a frame to persist, which is meant to be called by the `acquire` method of
+/// the global `defmt` logger, before the frame is started. The previous frame is dropped
+pub fn defmt_frame_start() {
+    PANIC_FRAME.store(false, Ordering::Relaxed);
+    LAST_FRAME.len.store(0, Ordering::Relaxed);
+}
+
+/// Add the encoded `bytes` to the current frame, which is meant to be called with every byte the
+/// global `defmt` logger sends on the wire, from `acquire` to `release`. Frames longer than 256
+/// bytes are dropped
+pub fn defmt_frame_write(bytes: &[u8]) {
+    let len = LAST_FRAME.len.load(Ordering::Relaxed);
+
+    // The frame no longer fits, it is dropped, and stays so until the next frame starts
+    let end = len.saturating_add(bytes.len());
+    if end > FRAME_LEN {
+        LAST_FRAME.len.store(usize::MAX, Ordering::Relaxed);
+        return;
+    }
+
+    unsafe {
+        let frame = LAST_FRAME.bytes.get().cast::<u8>();
+        core::ptr::copy_nonoverlapping(bytes.as_ptr(), frame.add(len), bytes.len());
+    }
+    LAST_FRAME.len.store(end, Ordering::Release);
+}
+
+/// Panic, once `defmt::panic!` logged the message, so the last frame is persisted as the message
+/// of the panic. This is meant to be called from the function given to `defmt::panic_handler`
+pub fn defmt_panic() -> ! {
+    PANIC_FRAME.store(true, Ordering::Release);
+    panic!("defmt panic")
+}
+
+/// The encoded frame of the message of the panic, if it was logged through `defmt` and fits
+fn panic_frame() -> Option<&'static [u8]> {
+    if !PANIC_FRAME.load(Ordering::Acquire) {
+        return None;
+    }
+
+    match LAST_FRAME.len.load(Ordering::Acquire) {
+        0 => None,
+        len if len > FRAME_LEN => None,
+        len => Some(unsafe { core::slice::from_raw_parts(LAST_FRAME.bytes.get().cast(), len) }),
+    }
+}
+
+/// Whether the message of the current panic is the encoded frame
+pub(crate) fn pending() -> bool {
+    panic_frame().is_some()
+}
+
+/// Write the encoded frame of the message of the panic to `sink`, if there is one, and tell
+/// whether it was written
+pub(crate) fn write_frame(sink: &mut dyn PanicSink) -> bool {
+    match panic_frame() {
+        Some(frame) => {
+            sink.write(frame);
+            true
+        }
+        None => false,
+    }
+}
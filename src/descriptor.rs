@@ -0,0 +1,140 @@
+//! Description of the panic region, for bootloaders and tools that don't link this crate
+
+use crate::header::FORMAT_VERSION;
+use crate::region::Region;
+use crate::{HEADER_LEN, RECORD_MAGIC};
+
+/// The bytes reserved in front of the records, at the start of the memory holding the region
+const RECORD_OFFSET: usize = {
+    let offset = 0;
+
+    #[cfg(feature = "multicore-lock")]
+    let offset = offset + 4;
+
+    #[cfg(feature = "panic-counter")]
+    let offset = offset + crate::counter::COUNTER_LEN;
+
+    #[cfg(feature = "kv-store")]
+    let offset = offset + crate::kv::KV_LEN;
+
+    #[cfg(feature = "breadcrumbs")]
+    let offset = offset + crate::breadcrumbs::BREADCRUMBS_LEN;
+
+    #[cfg(feature = "log")]
+    let offset = offset + crate::logs::LOGS_LEN;
+
+    #[cfg(feature = "watermark")]
+    let offset = offset + crate::watermark::USAGE_LEN;
+
+    offset
+};
+
+/// The number of equal parts the region is split into, each holding a record
+#[cfg(not(any(feature = "panic-ring", feature = "rp2040")))]
+const SLOTS: usize = 1;
+#[cfg(feature = "panic-ring")]
+const SLOTS: usize = crate::ring::SLOTS;
+#[cfg(all(feature = "rp2040", not(feature = "panic-ring")))]
+const SLOTS: usize = crate::rp2040::CORES;
+
+/// The magic word marking a record, as stored in its header
+#[cfg(feature = "region-symbols")]
+#[no_mangle]
+#[used]
+static PANIC_PERSIST_RECORD_MAGIC: u32 = RECORD_MAGIC;
+
+/// The version of the layout of the records
+#[cfg(feature = "region-symbols")]
+#[no_mangle]
+#[used]
+static PANIC_PERSIST_FORMAT_VERSION: u32 = FORMAT_VERSION as u32;
+
+/// The length of the header of a record
+#[cfg(feature = "region-symbols")]
+#[no_mangle]
+#[used]
+static PANIC_PERSIST_HEADER_LEN: u32 = HEADER_LEN as u32;
+
+/// The bytes reserved in front of the records, at the start of the memory holding the region
+#[cfg(feature = "region-symbols")]
+#[no_mangle]
+#[used]
+static PANIC_PERSIST_RECORD_OFFSET: u32 = RECORD_OFFSET as u32;
+
+/// The number of equal parts the region is split into, each holding a record
+#[cfg(feature = "region-symbols")]
+#[no_mangle]
+#[used]
+static PANIC_PERSIST_SLOTS: u32 = SLOTS as u32;
+
+/// Where the records are, and their layout, as returned by [`region_descriptor`], so a bootloader
+/// or a factory tool compiled separately can find and parse them without linking this crate
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RegionDescriptor {
+    start: usize,
+    len: usize,
+}
+
+impl RegionDescriptor {
+    /// The address of the first record, after what is reserved in front of the records
+    pub fn start(&self) -> usize {
+        self.start
+    }
+
+    /// The length of the part of the memory holding the records, without what is reserved in
+    /// front of them, and at the end of the memory
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Whether the region can't hold anything, such as on hosted targets
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// The magic word marking a record, as stored in its header
+    pub fn record_magic(&self) -> u32 {
+        RECORD_MAGIC
+    }
+
+    /// The version of the layout of the records, as stored in their header
+    pub fn format_version(&self) -> u8 {
+        FORMAT_VERSION
+    }
+
+    /// The length of the header of a record
+    pub fn header_len(&self) -> usize {
+        HEADER_LEN
+    }
+
+    /// The bytes reserved in front of the records, at the start of the memory holding the region,
+    /// such as the claim word of the `multicore-lock` feature
+    pub fn record_offset(&self) -> usize {
+        RECORD_OFFSET
+    }
+
+    /// The number of equal parts the region is split into, each holding a record: the slots of the
+    /// `panic-ring` feature, or the cores of the `rp2040` feature. Each part is the length of the
+    /// region divided by this number, rounded down to a multiple of 4 bytes
+    pub fn slots(&self) -> usize {
+        SLOTS
+    }
+}
+
+/// Where the records are, and their layout. This is meant to be shared with a bootloader or a
+/// factory tool compiled separately, such as at a fixed address, so it can find and parse the
+/// records without linking this crate, or at another version.
+///
+/// With the `region-symbols` feature, the layout is also stored in the
+/// `PANIC_PERSIST_RECORD_MAGIC`, `PANIC_PERSIST_FORMAT_VERSION`, `PANIC_PERSIST_HEADER_LEN`,
+/// `PANIC_PERSIST_RECORD_OFFSET` and `PANIC_PERSIST_SLOTS` symbols, as 32 bit words, so a tool can
+/// read it from the image. The linker drops them if nothing uses them, unless the linker script
+/// keeps them, such as with `KEEP(*(.rodata.PANIC_PERSIST_*))`
+pub fn region_descriptor() -> RegionDescriptor {
+    let region = Region::panic_dump();
+
+    RegionDescriptor {
+        start: region.start as usize,
+        len: region.len,
+    }
+}
@@ -0,0 +1,134 @@
+//! `Storage` driver for I2C EEPROMs, such as the Microchip 24LCxx series
+
+use embedded_hal::i2c::{I2c, Operation};
+use embedded_storage::{ReadStorage, Storage};
+
+/// How many times the EEPROM is polled while it is busy writing a page, which typically takes 5ms,
+/// before giving up
+const POLL_ATTEMPTS: usize = 1000;
+
+/// EEPROMs up to this size (the 24LC16) use a single address byte, with the upper address bits in
+/// the device address
+const SMALL_CAPACITY: usize = 2048;
+
+/// An I2C EEPROM of `capacity` bytes with pages of `page_size` bytes, at the 7 bit `address` (0x50
+/// when the address pins are tied low), for use with [`StorageSink`].
+///
+/// The EEPROM is used from panic context, so the `I2c` must not rely on interrupts, and the bus
+/// must not be in use by the code that panicked.
+///
+/// [`StorageSink`]: crate::StorageSink
+pub struct I2cEeprom<I2C> {
+    i2c: I2C,
+    address: u8,
+    capacity: usize,
+    page_size: usize,
+}
+
+/// Errors of an [`I2cEeprom`]
+#[derive(Debug)]
+pub enum EepromError<E> {
+    /// The I2C transfer failed
+    I2c(E),
+    /// The EEPROM stayed busy for too long after a write
+    Timeout,
+    /// The arguments are out of bounds
+    OutOfBounds,
+}
+
+impl<I2C: I2c> I2cEeprom<I2C> {
+    /// Create the driver of an EEPROM of `capacity` bytes with pages of `page_size` bytes, at
+    /// `address` on `i2c`
+    pub const fn new(i2c: I2C, address: u8, capacity: usize, page_size: usize) -> Self {
+        Self {
+            i2c,
+            address,
+            capacity,
+            page_size,
+        }
+    }
+
+    /// Give back the I2C bus
+    pub fn release(self) -> I2C {
+        self.i2c
+    }
+
+    /// The device address and the address bytes selecting `offset`
+    fn addressed(&self, offset: usize) -> (u8, [u8; 2], usize) {
+        if self.capacity <= SMALL_CAPACITY {
+            let block = ((offset >> 8) & 0x7) as u8;
+            (self.address | block, [offset as u8, 0], 1)
+        } else {
+            (self.address, (offset as u16).to_be_bytes(), 2)
+        }
+    }
+
+    /// Check that `len` bytes at `offset` are within the EEPROM
+    fn check(&self, offset: u32, len: usize) -> Result<usize, EepromError<I2C::Error>> {
+        let offset = offset as usize;
+
+        if len > self.capacity || offset > self.capacity - len {
+            return Err(EepromError::OutOfBounds);
+        }
+
+        Ok(offset)
+    }
+
+    /// Wait for the EEPROM to acknowledge its address again, once it is done writing a page
+    fn wait_idle(&mut self, address: u8, word: &[u8]) -> Result<(), EepromError<I2C::Error>> {
+        for _ in 0..POLL_ATTEMPTS {
+            if self.i2c.write(address, word).is_ok() {
+                return Ok(());
+            }
+        }
+
+        Err(EepromError::Timeout)
+    }
+}
+
+impl<I2C: I2c> ReadStorage for I2cEeprom<I2C> {
+    type Error = EepromError<I2C::Error>;
+
+    fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Self::Error> {
+        let offset = self.check(offset, bytes.len())?;
+
+        // Sequential reads roll over into the next blocks by themselves on small EEPROMs
+        let (address, word, word_len) = self.addressed(offset);
+        self.i2c
+            .write_read(address, &word[..word_len], bytes)
+            .map_err(EepromError::I2c)
+    }
+
+    fn capacity(&self) -> usize {
+        self.capacity
+    }
+}
+
+impl<I2C: I2c> Storage for I2cEeprom<I2C> {
+    fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Self::Error> {
+        let mut offset = self.check(offset, bytes.len())?;
+
+        // Each write stays within a page
+        let mut bytes = bytes;
+        while !bytes.is_empty() {
+            let len = core::cmp::min(self.page_size - offset % self.page_size, bytes.len());
+
+            let (address, word, word_len) = self.addressed(offset);
+            self.i2c
+                .transaction(
+                    address,
+                    &mut [
+                        Operation::Write(&word[..word_len]),
+                        Operation::Write(&bytes[..len]),
+                    ],
+                )
+                .map_err(EepromError::I2c)?;
+            self.wait_idle(address, &word[..word_len])?;
+
+            offset += len;
+            bytes = &bytes[len..];
+        }
+
+        Ok(())
+    }
+}
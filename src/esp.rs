@@ -0,0 +1,33 @@
+//! ESP32 family specific support
+
+use core::mem::MaybeUninit;
+use core::ptr::addr_of_mut;
+
+use crate::region::Region;
+
+// Size of the panic region placed in the RTC memory, `RTC_REGION_LEN`, which is 1K unless set with
+// the `PANIC_PERSIST_RTC_LEN` environment variable at build time
+include!(concat!(env!("OUT_DIR"), "/rtc_len.rs"));
+
+/// The panic region, in the RTC fast memory. `esp-hal` does not initialize this section, so it
+/// keeps its content across resets and deep sleep
+#[cfg(feature = "esp32-rtc-fast")]
+#[link_section = ".rtc_fast.persistent"]
+static mut RTC_PANIC_DUMP: MaybeUninit<[u8; RTC_REGION_LEN]> = MaybeUninit::uninit();
+
+/// The panic region, in the RTC slow memory. `esp-hal` does not initialize this section, so it
+/// keeps its content across resets and deep sleep
+#[cfg(feature = "esp32-rtc-slow")]
+#[link_section = ".rtc_slow.persistent"]
+static mut RTC_PANIC_DUMP: MaybeUninit<[u8; RTC_REGION_LEN]> = MaybeUninit::uninit();
+
+#[cfg(all(feature = "esp32-rtc-fast", feature = "esp32-rtc-slow"))]
+compile_error!("only one of the `esp32-rtc-fast` and `esp32-rtc-slow` features can be enabled");
+
+/// The panic region placed in the RTC memory
+pub(crate) fn rtc_region() -> Region {
+    Region {
+        start: addr_of_mut!(RTC_PANIC_DUMP).cast::<u8>(),
+        len: RTC_REGION_LEN,
+    }
+}
@@ -0,0 +1,39 @@
+//! C functions writing and reading the panic message, for firmware mixing C and Rust code
+
+/// Persist the `len` bytes at `msg` as a message, with the same header and the same fields as a
+/// panic message, as with `persist_message`. This is meant to be called from the C code of the
+/// same image, such as the assert handler of a vendor SDK, or a FreeRTOS hook, before it resets
+/// the device.
+///
+/// # Safety
+///
+/// `msg` must point to `len` readable bytes, unless `len` is zero
+#[no_mangle]
+pub unsafe extern "C" fn panic_persist_report(msg: *const u8, len: usize) {
+    let bytes = match msg.is_null() || len == 0 {
+        true => &[][..],
+        false => unsafe { core::slice::from_raw_parts(msg, len) },
+    };
+
+    crate::persist_with(|out| out.0.write(bytes));
+}
+
+/// Copy the panic message from the last boot, if any, into the `cap` bytes at `buf`, as with
+/// `get_panic_message_into`. Returns the number of bytes that were copied, which are truncated to
+/// `cap` if the message does not fit, or -1 if there is no message.
+///
+/// # Safety
+///
+/// `buf` must point to `cap` writable bytes, unless `cap` is zero
+#[no_mangle]
+pub unsafe extern "C" fn panic_persist_read(buf: *mut u8, cap: usize) -> isize {
+    let buf = match buf.is_null() || cap == 0 {
+        true => &mut [][..],
+        false => unsafe { core::slice::from_raw_parts_mut(buf, cap) },
+    };
+
+    match crate::get_panic_message_into(buf) {
+        Some(len) => len as isize,
+        None => -1,
+    }
+}
@@ -0,0 +1,437 @@
+//! Extra fields of the records, stored between the location of the panic and the message
+
+#[cfg(feature = "zeroize")]
+use core::sync::atomic::AtomicBool;
+use core::sync::atomic::{AtomicPtr, AtomicUsize, Ordering};
+
+#[cfg(feature = "backtrace")]
+use crate::backtrace::Backtrace;
+use crate::heap::{HeapStats, HEAP_STATS_LEN};
+use crate::location::padded_len;
+#[cfg(feature = "registers")]
+use crate::registers::{Registers, REGISTERS_LEN};
+#[cfg(feature = "stack-pointers")]
+use crate::stack_pointers::{StackPointers, STACK_POINTERS_LEN};
+
+/// Tag of the field holding the build id registered with `set_build_id`
+const BUILD_ID: u16 = 1;
+
+/// Tag of the field holding the time of the panic, as returned by the source registered with
+/// `set_time_source`
+const TIMESTAMP: u16 = 2;
+
+/// Tag of the field holding the registers captured on entry of the panic handler
+#[cfg(feature = "registers")]
+const REGISTERS: u16 = 3;
+
+/// Tag of the field holding the return addresses walked from the frame pointers
+#[cfg(feature = "backtrace")]
+const BACKTRACE: u16 = 4;
+
+/// Tag of the field holding the number of the exception the panic happened in
+const EXCEPTION: u16 = 5;
+
+/// Tag of the field holding the stack pointers and CONTROL register
+#[cfg(feature = "stack-pointers")]
+const STACK_POINTERS: u16 = 6;
+
+/// Tag of the empty field marking a message that is a `defmt` frame, rather than text
+#[cfg(feature = "defmt-payload")]
+const DEFMT: u16 = 7;
+
+/// Tag of the empty field marking a message that is a report serialized with `postcard`, rather
+/// than text
+#[cfg(feature = "postcard")]
+const POSTCARD: u16 = 8;
+
+/// Tag of the field holding the context registered with `set_panic_context`
+const CONTEXT: u16 = 9;
+
+/// Tag of the field holding the statistics returned by the source registered with
+/// `set_heap_stats_source`
+const HEAP_STATS: u16 = 10;
+
+/// Tag of the field holding the sequence number of the record in the ring of the `panic-ring`
+/// feature
+#[cfg(feature = "panic-ring")]
+const SEQUENCE: u16 = 11;
+
+/// Size of the tag and the length in front of the data of each field
+const FIELD_HEADER_LEN: usize = 4;
+
+/// The build id registered with `set_build_id`, if any
+static BUILD_ID_PTR: AtomicPtr<u8> = AtomicPtr::new(core::ptr::null_mut());
+static BUILD_ID_LEN: AtomicUsize = AtomicUsize::new(0);
+
+/// The context registered with `set_panic_context`, if any
+static CONTEXT_PTR: AtomicPtr<u8> = AtomicPtr::new(core::ptr::null_mut());
+static CONTEXT_LEN: AtomicUsize = AtomicUsize::new(0);
+
+/// Whether the context registered with `set_panic_context` is left out of the records
+#[cfg(feature = "zeroize")]
+static CONTEXT_SENSITIVE: AtomicBool = AtomicBool::new(false);
+
+/// The time source registered with `set_time_source`, if any
+static TIME_SOURCE: AtomicPtr<()> = AtomicPtr::new(core::ptr::null_mut());
+
+/// Register the id of the firmware, such as its version, the hash of its commit, or the 20 byte GNU
+/// build id, which is then stored in every panic record, and returned by [`Record::build_id`]. This
+/// is meant to be called once at startup, before any panic can happen.
+///
+/// Only the first 65535 bytes of the id are stored
+///
+/// [`Record::build_id`]: crate::Record::build_id
+pub fn set_build_id(id: &'static [u8]) {
+    BUILD_ID_LEN.store(id.len(), Ordering::Release);
+    BUILD_ID_PTR.store(id.as_ptr() as *mut u8, Ordering::Release);
+}
+
+/// Register the context of the application, such as the state of its state machine, the id of the
+/// last command or the peer it is connected to, which is then stored in the record of a panic, and
+/// returned by [`Record::context`]. This is called again whenever the context changes, and with an
+/// empty slice to clear it.
+///
+/// Only the first 65535 bytes of the context are stored
+///
+/// [`Record::context`]: crate::Record::context
+pub fn set_panic_context(context: &'static [u8]) {
+    // The length is cleared first, so the panic handler never sees the new pointer with the old
+    // length, in case this is interrupted by a panic
+    CONTEXT_LEN.store(0, Ordering::Release);
+    CONTEXT_PTR.store(context.as_ptr() as *mut u8, Ordering::Release);
+    CONTEXT_LEN.store(context.len(), Ordering::Release);
+}
+
+/// Mark the context registered with [`set_panic_context`] as sensitive, such as while it holds
+/// user data, so the panic handler leaves it out of the record of a panic, and [`Record::context`]
+/// returns `None`. This is called again with `false` once the context can be stored again
+///
+/// [`Record::context`]: crate::Record::context
+#[cfg(feature = "zeroize")]
+pub fn set_panic_context_sensitive(sensitive: bool) {
+    CONTEXT_SENSITIVE.store(sensitive, Ordering::Release);
+}
+
+/// Register the function returning the current time, such as the ticks since boot or the seconds
+/// of an RTC, which the panic handler calls to stamp the record of a panic with the time it
+/// happened at, as returned by [`Record::timestamp`]. This is meant to be called once at startup,
+/// before any panic can happen.
+///
+/// The function is called from panic context, with interrupts disabled, so it must not wait for
+/// interrupts, or take locks that might be held by the code that panicked
+///
+/// [`Record::timestamp`]: crate::Record::timestamp
+pub fn set_time_source(source: fn() -> u64) {
+    TIME_SOURCE.store(source as *mut (), Ordering::Release);
+}
+
+/// The current time, as returned by the registered source, if any
+fn now() -> Option<u64> {
+    let ptr = TIME_SOURCE.load(Ordering::Acquire);
+
+    if ptr.is_null() {
+        return None;
+    }
+
+    // Only `fn() -> u64` pointers are ever stored
+    let source: fn() -> u64 = unsafe { core::mem::transmute(ptr) };
+    Some(source())
+}
+
+/// The number of the active exception, from the IPSR register, which is 0 in thread mode. The panic
+/// handler runs in the context of the code that panicked, so this is the exception it was in
+#[cfg(cortex_m)]
+fn exception() -> Option<u16> {
+    let ipsr: u32;
+    unsafe {
+        core::arch::asm!("mrs {}, ipsr", out(reg) ipsr, options(nomem, nostack, preserves_flags))
+    };
+
+    Some((ipsr & 0x1ff) as u16)
+}
+
+/// There is no IPSR register on other targets
+#[cfg(not(cortex_m))]
+fn exception() -> Option<u16> {
+    None
+}
+
+/// The extra fields of a record. Each field is stored as a 16 bit tag and a 16 bit length, in
+/// little endian, followed by its data, which is padded with zeros to a multiple of 4 bytes.
+/// Readers skip the fields they don't know of, so fields can be added without changing the layout
+#[derive(Clone, Copy, Default)]
+pub(crate) struct Fields<'a> {
+    pub(crate) build_id: Option<&'a [u8]>,
+    pub(crate) timestamp: Option<u64>,
+    pub(crate) context: Option<&'a [u8]>,
+    pub(crate) exception: Option<u16>,
+    pub(crate) heap_stats: Option<HeapStats>,
+    #[cfg(feature = "registers")]
+    pub(crate) registers: Option<Registers>,
+    #[cfg(feature = "backtrace")]
+    pub(crate) backtrace: Option<Backtrace>,
+    #[cfg(feature = "stack-pointers")]
+    pub(crate) stack_pointers: Option<StackPointers>,
+    #[cfg(feature = "defmt-payload")]
+    pub(crate) defmt: bool,
+    #[cfg(feature = "postcard")]
+    pub(crate) postcard: bool,
+    #[cfg(feature = "panic-ring")]
+    pub(crate) sequence: Option<u32>,
+}
+
+impl Fields<'static> {
+    /// The fields registered with this crate, which are stored in the record of a panic. This calls
+    /// the time source, so it is only called once per record, and once more for its report with the
+    /// `postcard` feature
+    pub(crate) fn current() -> Self {
+        let ptr = BUILD_ID_PTR.load(Ordering::Acquire);
+
+        // Only the length of the registered id is stored along with it
+        let build_id = match ptr.is_null() {
+            true => None,
+            false => Some(unsafe {
+                core::slice::from_raw_parts(ptr, BUILD_ID_LEN.load(Ordering::Acquire))
+            }),
+        };
+
+        // The pointer is always set before the length
+        let context = match CONTEXT_LEN.load(Ordering::Acquire) {
+            0 => None,
+            len => Some(unsafe {
+                core::slice::from_raw_parts(CONTEXT_PTR.load(Ordering::Acquire), len)
+            }),
+        };
+
+        #[cfg(feature = "zeroize")]
+        let context = context.filter(|_| !CONTEXT_SENSITIVE.load(Ordering::Acquire));
+
+        Self {
+            build_id,
+            timestamp: now(),
+            context,
+            exception: exception(),
+            heap_stats: crate::heap::current(),
+            #[cfg(feature = "registers")]
+            registers: crate::registers::captured(),
+            #[cfg(feature = "backtrace")]
+            backtrace: crate::backtrace::captured(),
+            #[cfg(feature = "stack-pointers")]
+            stack_pointers: crate::stack_pointers::read(),
+            #[cfg(feature = "defmt-payload")]
+            defmt: crate::defmt_payload::pending(),
+            #[cfg(feature = "postcard")]
+            postcard: crate::report::pending(),
+            #[cfg(feature = "panic-ring")]
+            sequence: crate::ring::next_sequence(),
+        }
+    }
+}
+
+impl<'a> Fields<'a> {
+    /// The fields encoded in `encoded`, as read from a record. Parsing stops at the first field
+    /// that does not fit
+    pub(crate) fn parse(mut encoded: &'a [u8]) -> Self {
+        let mut fields = Self::default();
+
+        while encoded.len() >= FIELD_HEADER_LEN {
+            let tag = u16::from_le_bytes([encoded[0], encoded[1]]);
+            let len = u16::from_le_bytes([encoded[2], encoded[3]]) as usize;
+            let end = FIELD_HEADER_LEN + padded_len(len);
+            if end > encoded.len() {
+                break;
+            }
+
+            let data = &encoded[FIELD_HEADER_LEN..FIELD_HEADER_LEN + len];
+            match tag {
+                BUILD_ID => fields.build_id = Some(data),
+                TIMESTAMP if len == 8 => {
+                    let mut bytes = [0; 8];
+                    bytes.copy_from_slice(data);
+                    fields.timestamp = Some(u64::from_le_bytes(bytes));
+                }
+                CONTEXT => fields.context = Some(data),
+                EXCEPTION if len == 4 => {
+                    fields.exception = Some(u16::from_le_bytes([data[0], data[1]]));
+                }
+                HEAP_STATS if len == HEAP_STATS_LEN => {
+                    let mut bytes = [0; HEAP_STATS_LEN];
+                    bytes.copy_from_slice(data);
+                    fields.heap_stats = Some(HeapStats::from_bytes(&bytes));
+                }
+                #[cfg(feature = "registers")]
+                REGISTERS if len == REGISTERS_LEN => {
+                    let mut bytes = [0; REGISTERS_LEN];
+                    bytes.copy_from_slice(data);
+                    fields.registers = Some(Registers::from_bytes(&bytes));
+                }
+                #[cfg(feature = "backtrace")]
+                BACKTRACE if len.is_multiple_of(4) => {
+                    fields.backtrace = Some(Backtrace::from_bytes(data))
+                }
+                #[cfg(feature = "stack-pointers")]
+                STACK_POINTERS if len == STACK_POINTERS_LEN => {
+                    let mut bytes = [0; STACK_POINTERS_LEN];
+                    bytes.copy_from_slice(data);
+                    fields.stack_pointers = Some(StackPointers::from_bytes(&bytes));
+                }
+                #[cfg(feature = "defmt-payload")]
+                DEFMT => fields.defmt = true,
+                #[cfg(feature = "postcard")]
+                POSTCARD => fields.postcard = true,
+                #[cfg(feature = "panic-ring")]
+                SEQUENCE if len == 4 => {
+                    fields.sequence =
+                        Some(u32::from_le_bytes([data[0], data[1], data[2], data[3]]));
+                }
+                _ => {}
+            }
+
+            encoded = &encoded[end..];
+        }
+
+        fields
+    }
+
+    /// Call `f` with the tag and the data of each field that is set
+    fn each(&self, mut f: impl FnMut(u16, &[u8])) {
+        if let Some(build_id) = self.build_id {
+            f(BUILD_ID, &build_id[..build_id.len().min(u16::MAX as usize)]);
+        }
+        if let Some(timestamp) = self.timestamp {
+            f(TIMESTAMP, &timestamp.to_le_bytes());
+        }
+        if let Some(context) = self.context {
+            f(CONTEXT, &context[..context.len().min(u16::MAX as usize)]);
+        }
+        if let Some(exception) = self.exception {
+            f(EXCEPTION, &u32::from(exception).to_le_bytes());
+        }
+        if let Some(heap_stats) = self.heap_stats {
+            f(HEAP_STATS, &heap_stats.to_bytes());
+        }
+        #[cfg(feature = "registers")]
+        if let Some(registers) = self.registers {
+            f(REGISTERS, &registers.to_bytes());
+        }
+        #[cfg(feature = "backtrace")]
+        if let Some(backtrace) = self.backtrace {
+            let (bytes, len) = backtrace.to_bytes();
+            f(BACKTRACE, &bytes[..len]);
+        }
+        #[cfg(feature = "stack-pointers")]
+        if let Some(stack_pointers) = self.stack_pointers {
+            f(STACK_POINTERS, &stack_pointers.to_bytes());
+        }
+        #[cfg(feature = "defmt-payload")]
+        if self.defmt {
+            f(DEFMT, &[]);
+        }
+        #[cfg(feature = "postcard")]
+        if self.postcard {
+            f(POSTCARD, &[]);
+        }
+        #[cfg(feature = "panic-ring")]
+        if let Some(sequence) = self.sequence {
+            f(SEQUENCE, &sequence.to_le_bytes());
+        }
+    }
+
+    /// The length of the encoded fields, which is a multiple of 4 bytes
+    pub(crate) fn encoded_len(&self) -> usize {
+        let mut len = 0;
+        self.each(|_, data| len += FIELD_HEADER_LEN + padded_len(data.len()));
+
+        len
+    }
+
+    /// Give the encoded fields to `out`, in pieces
+    pub(crate) fn encode(&self, mut out: impl FnMut(&[u8])) {
+        self.each(|tag, data| {
+            let tag = tag.to_le_bytes();
+            let len = (data.len() as u16).to_le_bytes();
+
+            out(&[tag[0], tag[1], len[0], len[1]]);
+            out(data);
+            out(&[0; 3][..padded_len(data.len()) - data.len()]);
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Encode `fields` into `bytes`, and return their length
+    fn encode(fields: &Fields<'_>, bytes: &mut [u8; 64]) -> usize {
+        let mut len = 0;
+        fields.encode(|piece| {
+            bytes[len..len + piece.len()].copy_from_slice(piece);
+            len += piece.len();
+        });
+
+        len
+    }
+
+    #[test]
+    fn encode_then_parse() {
+        let fields = Fields {
+            build_id: Some(b"v1.2.3"),
+            timestamp: Some(0x0123_4567_89AB_CDEF),
+            context: Some(b"idle"),
+            exception: Some(15),
+            ..Fields::default()
+        };
+        let mut bytes = [0; 64];
+        let len = encode(&fields, &mut bytes);
+        assert_eq!(len, fields.encoded_len());
+        assert!(len.is_multiple_of(4));
+
+        let parsed = Fields::parse(&bytes[..len]);
+        assert_eq!(parsed.build_id, Some(&b"v1.2.3"[..]));
+        assert_eq!(parsed.timestamp, Some(0x0123_4567_89AB_CDEF));
+        assert_eq!(parsed.context, Some(&b"idle"[..]));
+        assert_eq!(parsed.exception, Some(15));
+        assert!(parsed.heap_stats.is_none());
+    }
+
+    #[test]
+    fn no_fields_are_empty() {
+        assert_eq!(Fields::default().encoded_len(), 0);
+
+        let parsed = Fields::parse(&[]);
+        assert!(parsed.build_id.is_none() && parsed.timestamp.is_none());
+    }
+
+    #[test]
+    fn unknown_and_malformed_fields_are_skipped() {
+        let bytes = [
+            // Unknown tag, 2 bytes, padded
+            0xFF, 0x7F, 2, 0, 1, 2, 0, 0, //
+            // Timestamp of the wrong length
+            2, 0, 4, 0, 1, 2, 3, 4, //
+            // Build id
+            1, 0, 1, 0, 0xB1, 0, 0, 0,
+        ];
+
+        let parsed = Fields::parse(&bytes);
+        assert_eq!(parsed.timestamp, None);
+        assert_eq!(parsed.build_id, Some(&[0xB1][..]));
+    }
+
+    #[test]
+    fn parsing_stops_at_a_field_that_does_not_fit() {
+        let fields = Fields {
+            timestamp: Some(42),
+            build_id: Some(b"v1"),
+            ..Fields::default()
+        };
+        let mut bytes = [0; 64];
+        let len = encode(&fields, &mut bytes);
+
+        // The build id comes first, and the timestamp is cut
+        let parsed = Fields::parse(&bytes[..len - 1]);
+        assert_eq!(parsed.build_id, Some(&b"v1"[..]));
+        assert_eq!(parsed.timestamp, None);
+    }
+}
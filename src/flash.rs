@@ -0,0 +1,594 @@
+//! Sink persisting the panic message to a reserved area of a `NorFlash`, such as a page of the
+//! internal flash
+
+use core::cmp::min;
+use core::panic::Location;
+
+use embedded_storage::nor_flash::{NorFlash, ReadNorFlash};
+
+use crate::crc::{crc32, Crc32};
+use crate::fields::Fields;
+use crate::header::{Header, FORMAT_VERSION};
+use crate::location::{write_prefix, Block, LOCATION_LEN};
+use crate::record::{decode, Record};
+use crate::sink::{PanicSink, WriteStatus};
+use crate::{HEADER_LEN, RECORD_MAGIC};
+
+/// Offset of the sequence number of the record, after the usual header
+const SEQUENCE_OFFSET: usize = HEADER_LEN;
+
+/// Size of the header of a record in flash: the usual header, followed by a 32 bit sequence number
+const FLASH_HEADER_LEN: usize = HEADER_LEN + 4;
+
+/// The slots of `len` bytes each, at `offset` in the flash, which the panic messages are written to
+/// in turn, so repeated panics do not wear out the same flash cells, and older messages can still
+/// be read until they are overwritten.
+///
+/// `offset` and `len` must be multiples of the erase size of the flash, so each slot is erased on
+/// its own, and the slots must not be used for anything else.
+#[derive(Clone, Copy)]
+pub struct FlashSlots {
+    offset: u32,
+    len: u32,
+    count: u32,
+}
+
+impl FlashSlots {
+    /// `count` slots of `len` bytes each, starting at `offset`
+    pub const fn new(offset: u32, len: u32, count: u32) -> Self {
+        Self { offset, len, count }
+    }
+
+    /// The offset of slot `slot`
+    fn slot_offset(&self, slot: u32) -> u32 {
+        self.offset + slot * self.len
+    }
+}
+
+/// A [`PanicSink`] writing the panic message to the area of `flash` starting at `offset`, with the
+/// same header as in the RAM region, followed by a 32 bit sequence number. The message is buffered
+/// in the sink, which holds records of up to `N` bytes (header included), and written once it is
+/// complete.
+///
+/// `offset` must be aligned to the erase size of the flash, and the area of `N` bytes (rounded up
+/// to the erase size) must not be used for anything else. The area is only erased at panic time if
+/// it still holds a record, so it should be read (and erased) on boot with
+/// [`get_flash_panic_message`]. The read size of the flash must be at most 4 bytes.
+///
+/// With [`FlashSink::with_slots`], the messages are written to several slots in turn instead.
+pub struct FlashSink<F, const N: usize> {
+    flash: F,
+    slots: FlashSlots,
+    buf: [u8; N],
+    /// Bytes of the payload buffered so far, the location block included
+    len: usize,
+    /// Whether the location block was buffered
+    started: bool,
+    /// Whether bytes of the message were dropped, as they did not fit in the buffer
+    truncated: bool,
+    /// Whether the record could not be written to the flash
+    failed: bool,
+}
+
+impl<F: NorFlash, const N: usize> FlashSink<F, N> {
+    /// Create a sink writing to the area of `flash` starting at `offset`
+    pub const fn new(flash: F, offset: u32) -> Self {
+        let len = round_up(N, F::ERASE_SIZE) as u32;
+
+        Self::with_slots(flash, FlashSlots::new(offset, len, 1))
+    }
+
+    /// Create a sink writing to `slots` of `flash` in turn, after the slot holding the message with
+    /// the highest sequence number. The slots must be at least `N` bytes long
+    pub const fn with_slots(flash: F, slots: FlashSlots) -> Self {
+        Self {
+            flash,
+            slots,
+            buf: [0xFF; N],
+            len: 0,
+            started: false,
+            truncated: false,
+            failed: false,
+        }
+    }
+
+    /// Give back the flash
+    pub fn release(self) -> F {
+        self.flash
+    }
+
+    /// Start the payload with the location block of a panic at `line` and `column` of `file`, and
+    /// with the fields returned by `fields`, unless it was already started. The fields and the file
+    /// name are left out if they do not fit in the buffer
+    fn begin<'f>(
+        &mut self,
+        line: u32,
+        column: u32,
+        file: &[u8],
+        fields: impl FnOnce() -> Fields<'f>,
+    ) {
+        if self.started {
+            return;
+        }
+        self.started = true;
+
+        let max_len = Self::capacity().saturating_sub(FLASH_HEADER_LEN);
+        let fields = fields();
+        write_prefix(line, column, file, &fields, max_len, |bytes| {
+            self.append(bytes)
+        });
+    }
+
+    /// Append `bytes` to the payload. Bytes that don't fit are dropped
+    fn append(&mut self, bytes: &[u8]) {
+        // The buffer must at least be able to hold the header
+        let start = FLASH_HEADER_LEN + self.len;
+        if Self::capacity() < start {
+            return;
+        }
+        let len = min(Self::capacity() - start, bytes.len());
+        self.truncated |= len < bytes.len();
+
+        self.buf[start..start + len].copy_from_slice(&bytes[..len]);
+        self.len += len;
+    }
+
+    /// The part of the buffer that can be written with whole flash words
+    fn capacity() -> usize {
+        N - N % F::WRITE_SIZE
+    }
+
+    /// The slot to write the message to, and its sequence number: the one after the slot holding
+    /// the message with the highest sequence number
+    fn next_slot(&mut self) -> (u32, u32) {
+        let mut newest = None;
+
+        for slot in 0..self.slots.count {
+            let header = read_header(&mut self.flash, self.slots.slot_offset(slot));
+
+            if let Some((_, sequence, _)) = header {
+                match newest {
+                    Some((_, newest_sequence)) if newest_sequence >= sequence => {}
+                    _ => newest = Some((slot, sequence)),
+                }
+            }
+        }
+
+        match newest {
+            Some((slot, sequence)) => ((slot + 1) % self.slots.count, sequence.wrapping_add(1)),
+            None => (0, 0),
+        }
+    }
+}
+
+impl<F: NorFlash, const N: usize> PanicSink for FlashSink<F, N> {
+    fn location(&mut self, location: &Location<'_>) {
+        self.begin(
+            location.line(),
+            location.column(),
+            location.file().as_bytes(),
+            Fields::current,
+        );
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        self.begin(0, 0, &[], Fields::current);
+        self.append(bytes);
+    }
+
+    fn finalize(&mut self) {
+        self.begin(0, 0, &[], Fields::current);
+        if Self::capacity() < FLASH_HEADER_LEN
+            || self.slots.count == 0
+            || (self.slots.len as usize) < Self::capacity()
+        {
+            self.failed = true;
+            return;
+        }
+
+        let (slot, sequence) = self.next_slot();
+        let offset = self.slots.slot_offset(slot);
+
+        let crc = crc32(&self.buf[FLASH_HEADER_LEN..FLASH_HEADER_LEN + self.len]);
+        let header = Header::new(RECORD_MAGIC, self.len as u32, crc).truncated(self.truncated);
+        self.buf[..HEADER_LEN].copy_from_slice(&header.to_bytes());
+        self.buf[SEQUENCE_OFFSET..FLASH_HEADER_LEN].copy_from_slice(&sequence.to_le_bytes());
+
+        // Erase the slot if it still holds an older record. Errors are only reported by the
+        // status of the sink
+        let mut header = [0; FLASH_HEADER_LEN];
+        let blank =
+            self.flash.read(offset, &mut header).is_ok() && header == [0xFF; FLASH_HEADER_LEN];
+        if !blank {
+            self.failed = self.flash.erase(offset, offset + self.slots.len).is_err();
+        }
+
+        // The rest of the buffer is left erased, so the record can be padded to whole words
+        let record_len = round_up(FLASH_HEADER_LEN + self.len, F::WRITE_SIZE);
+        self.failed |= self.flash.write(offset, &self.buf[..record_len]).is_err();
+    }
+
+    fn status(&self) -> WriteStatus {
+        WriteStatus {
+            written: self.len,
+            truncated: self.truncated,
+            failed: self.failed,
+        }
+    }
+}
+
+/// Get the panic message persisted by a [`FlashSink`] to the area of `flash` starting at `offset`,
+/// if any, by copying it into `buf`. Returns the bytes that were copied, which are truncated to
+/// the length of `buf` if the message does not fit. The location of the panic is returned along
+/// with the message by [`get_flash_panic_record`].
+///
+/// The read size of the flash must be at most 4 bytes. If a message existed, the area holding it
+/// is erased, so this function will only return the value once (subsequent calls will return None)
+pub fn get_flash_panic_message<'a, F: NorFlash>(
+    flash: &mut F,
+    offset: u32,
+    buf: &'a mut [u8],
+) -> Option<&'a [u8]> {
+    let (len, _, crc) = read_header(flash, offset)?;
+    if !crc_matches(flash, offset, len, crc) {
+        return None;
+    }
+    let copied = read_message(flash, offset, len, buf)?;
+
+    let end = offset + round_up(FLASH_HEADER_LEN + len, F::ERASE_SIZE) as u32;
+    flash.erase(offset, end).ok()?;
+
+    Some(&buf[..copied])
+}
+
+/// Get the panic message persisted by a [`FlashSink`] to slot `slot` of `slots`, if any, along
+/// with its sequence number, by copying it into `buf`. Returns the bytes that were copied, which
+/// are truncated to the length of `buf` if the message does not fit. The location of the panic is
+/// returned along with the message by [`get_flash_panic_record_in_slot`].
+///
+/// The message is left in place, so it can be read again until it is overwritten by a later panic.
+/// The message with the highest sequence number is the last one.
+pub fn get_flash_panic_message_in_slot<'a, F: ReadNorFlash>(
+    flash: &mut F,
+    slots: FlashSlots,
+    slot: u32,
+    buf: &'a mut [u8],
+) -> Option<(u32, &'a [u8])> {
+    if slot >= slots.count {
+        return None;
+    }
+
+    let offset = slots.slot_offset(slot);
+    let (len, sequence, crc) = read_header(flash, offset)?;
+    if FLASH_HEADER_LEN + len > slots.len as usize || !crc_matches(flash, offset, len, crc) {
+        return None;
+    }
+
+    let copied = read_message(flash, offset, len, buf)?;
+
+    Some((sequence, &buf[..copied]))
+}
+
+/// Get the panic record persisted by a [`FlashSink`] to the area of `flash` starting at `offset`,
+/// if any, with the location of the panic and the extra fields, by copying it into `buf`. The
+/// whole record must fit in `buf`, which is at most `N` bytes for a `FlashSink<F, N>`.
+///
+/// The read size of the flash must be at most 4 bytes. If a record existed, the area holding it is
+/// erased once it was copied, so this function will only return it once (subsequent calls will
+/// return None)
+pub fn get_flash_panic_record<'a, F: NorFlash>(
+    flash: &mut F,
+    offset: u32,
+    buf: &'a mut [u8],
+) -> Option<Record<'a>> {
+    let (len, _, _) = read_header(flash, offset)?;
+    let record = copy_record(flash, offset, len, buf)?;
+
+    let end = offset + round_up(FLASH_HEADER_LEN + len, F::ERASE_SIZE) as u32;
+    flash.erase(offset, end).ok()?;
+
+    Some(record)
+}
+
+/// Get the panic record persisted by a [`FlashSink`] to slot `slot` of `slots`, if any, along
+/// with its sequence number, by copying it into `buf`. The whole record must fit in `buf`, which
+/// is at most `N` bytes for a `FlashSink<F, N>`.
+///
+/// The record is left in place, so it can be read again until it is overwritten by a later panic.
+pub fn get_flash_panic_record_in_slot<'a, F: ReadNorFlash>(
+    flash: &mut F,
+    slots: FlashSlots,
+    slot: u32,
+    buf: &'a mut [u8],
+) -> Option<(u32, Record<'a>)> {
+    if slot >= slots.count {
+        return None;
+    }
+
+    let offset = slots.slot_offset(slot);
+    let (len, sequence, _) = read_header(flash, offset)?;
+    if FLASH_HEADER_LEN + len > slots.len as usize {
+        return None;
+    }
+
+    Some((sequence, copy_record(flash, offset, len, buf)?))
+}
+
+/// Read the header of the record at `offset`, and return the length of its message, its sequence
+/// number and its CRC, if it holds one
+fn read_header<F: ReadNorFlash>(flash: &mut F, offset: u32) -> Option<(usize, u32, u32)> {
+    let mut header = [0; FLASH_HEADER_LEN];
+    flash.read(offset, &mut header).ok()?;
+
+    let mut bytes = [0; HEADER_LEN];
+    bytes.copy_from_slice(&header[..SEQUENCE_OFFSET]);
+    let record = Header::from_bytes(bytes);
+
+    // The record must have been written with the layout of this version of the crate
+    if record.magic != RECORD_MAGIC || record.version != FORMAT_VERSION {
+        return None;
+    }
+
+    let len = record.len as usize;
+    let record_len = FLASH_HEADER_LEN.checked_add(len)?;
+    if offset as usize + record_len > flash.capacity() {
+        return None;
+    }
+
+    let mut sequence = [0; 4];
+    sequence.copy_from_slice(&header[SEQUENCE_OFFSET..]);
+
+    Some((len, u32::from_le_bytes(sequence), record.crc))
+}
+
+/// Whether the message of `len` bytes of the record at `offset` matches `crc`. The message is read
+/// in chunks, so it is checked even if it does not fit in the buffer of the caller
+fn crc_matches<F: ReadNorFlash>(flash: &mut F, offset: u32, len: usize, crc: u32) -> bool {
+    let mut chunk = [0; 16];
+    let mut message_crc = Crc32::new();
+    let mut at = 0;
+
+    while at < len {
+        // Reads are made of whole read words, which the chunk is a multiple of
+        let chunk_len = min(len - at, chunk.len());
+        let read_len = round_up(chunk_len, F::READ_SIZE);
+        let start = offset + (FLASH_HEADER_LEN + at) as u32;
+        if flash.read(start, &mut chunk[..read_len]).is_err() {
+            return false;
+        }
+
+        message_crc.update(&chunk[..chunk_len]);
+        at += chunk_len;
+    }
+
+    message_crc.finish() == crc
+}
+
+/// Copy the text of the message of the payload of `len` bytes of the record at `offset` into
+/// `buf`, and return how many bytes were copied
+fn read_message<F: ReadNorFlash>(
+    flash: &mut F,
+    offset: u32,
+    len: usize,
+    buf: &mut [u8],
+) -> Option<usize> {
+    // The text follows the location block, the file name and the extra fields, which are
+    // multiples of 4 bytes
+    let mut block = [0; LOCATION_LEN];
+    let payload = offset + FLASH_HEADER_LEN as u32;
+    flash.read(payload, &mut block).ok()?;
+    let text_start = Block::parse(block)?.text_offset()?;
+    let len = len.checked_sub(text_start)?;
+
+    // Reads are made of whole read words, rounding up if the buffer allows it
+    let copied = min(len, buf.len());
+    let read_len = match round_up(copied, F::READ_SIZE) {
+        read_len if read_len <= buf.len() => read_len,
+        _ => copied - copied % F::READ_SIZE,
+    };
+    flash
+        .read(payload + text_start as u32, &mut buf[..read_len])
+        .ok()?;
+
+    Some(min(copied, read_len))
+}
+
+/// Copy the record at `offset`, with a payload of `len` bytes, into `buf` without its sequence
+/// number, as the RAM region holds it, and decode it. The payload is checked against the CRC of
+/// the header
+fn copy_record<'a, F: ReadNorFlash>(
+    flash: &mut F,
+    offset: u32,
+    len: usize,
+    buf: &'a mut [u8],
+) -> Option<Record<'a>> {
+    // Reads are made of whole read words, and the header is a multiple of 4 bytes
+    let bytes = buf.get_mut(..HEADER_LEN + round_up(len, F::READ_SIZE))?;
+    flash.read(offset, &mut bytes[..HEADER_LEN]).ok()?;
+    flash
+        .read(offset + FLASH_HEADER_LEN as u32, &mut bytes[HEADER_LEN..])
+        .ok()?;
+
+    decode(bytes, RECORD_MAGIC).ok()
+}
+
+/// Round `len` up to a multiple of `size`
+const fn round_up(len: usize, size: usize) -> usize {
+    len.div_ceil(size) * size
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use embedded_storage::nor_flash::{ErrorType, NorFlashErrorKind};
+
+    /// Size of the erase pages of the test flash
+    const PAGE: usize = 256;
+
+    /// A flash held in memory, with the constraints of a NOR flash: writes only clear bits, and
+    /// must be made of whole, aligned words
+    struct TestFlash {
+        bytes: [u8; 4 * PAGE],
+    }
+
+    impl TestFlash {
+        fn new() -> Self {
+            Self {
+                bytes: [0xFF; 4 * PAGE],
+            }
+        }
+    }
+
+    impl ErrorType for TestFlash {
+        type Error = NorFlashErrorKind;
+    }
+
+    impl ReadNorFlash for TestFlash {
+        const READ_SIZE: usize = 1;
+
+        fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Self::Error> {
+            let offset = offset as usize;
+            let stored = self
+                .bytes
+                .get(offset..offset + bytes.len())
+                .ok_or(NorFlashErrorKind::OutOfBounds)?;
+            bytes.copy_from_slice(stored);
+
+            Ok(())
+        }
+
+        fn capacity(&self) -> usize {
+            self.bytes.len()
+        }
+    }
+
+    impl NorFlash for TestFlash {
+        const WRITE_SIZE: usize = 4;
+        const ERASE_SIZE: usize = PAGE;
+
+        fn erase(&mut self, from: u32, to: u32) -> Result<(), Self::Error> {
+            let (from, to) = (from as usize, to as usize);
+            if !from.is_multiple_of(PAGE) || !to.is_multiple_of(PAGE) {
+                return Err(NorFlashErrorKind::NotAligned);
+            }
+            self.bytes
+                .get_mut(from..to)
+                .ok_or(NorFlashErrorKind::OutOfBounds)?
+                .fill(0xFF);
+
+            Ok(())
+        }
+
+        fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Self::Error> {
+            let offset = offset as usize;
+            if !offset.is_multiple_of(Self::WRITE_SIZE)
+                || !bytes.len().is_multiple_of(Self::WRITE_SIZE)
+            {
+                return Err(NorFlashErrorKind::NotAligned);
+            }
+            let stored = self
+                .bytes
+                .get_mut(offset..offset + bytes.len())
+                .ok_or(NorFlashErrorKind::OutOfBounds)?;
+            for (stored, byte) in stored.iter_mut().zip(bytes) {
+                *stored &= byte;
+            }
+
+            Ok(())
+        }
+    }
+
+    /// Persist a panic at the location of the caller with `message` to `sink`
+    #[track_caller]
+    fn persist<const N: usize>(sink: &mut FlashSink<TestFlash, N>, message: &[u8]) {
+        sink.location(Location::caller());
+        sink.write(message);
+        sink.finalize();
+    }
+
+    #[test]
+    fn record_round_trip() {
+        let mut sink = FlashSink::<_, 128>::new(TestFlash::new(), PAGE as u32);
+        let line = line!() + 1;
+        persist(&mut sink, b"index out of bounds");
+        let status = sink.status();
+        assert!(!status.failed && !status.truncated);
+
+        let mut flash = sink.release();
+        let mut buf = [0; 128];
+        let record = get_flash_panic_record(&mut flash, PAGE as u32, &mut buf).unwrap();
+        let location = record.location().unwrap();
+        assert_eq!(location.file(), file!());
+        assert_eq!(location.line(), line);
+        assert_eq!(record.message(), b"index out of bounds");
+        assert!(!record.was_truncated());
+
+        // The page was erased
+        assert!(get_flash_panic_record(&mut flash, PAGE as u32, &mut buf).is_none());
+        assert!(flash.bytes[PAGE..2 * PAGE].iter().all(|&byte| byte == 0xFF));
+    }
+
+    #[test]
+    fn message_round_trip() {
+        let mut sink = FlashSink::<_, 128>::new(TestFlash::new(), 0);
+        persist(&mut sink, b"index out of bounds");
+
+        let mut flash = sink.release();
+        let mut buf = [0; 5];
+        assert_eq!(
+            get_flash_panic_message(&mut flash, 0, &mut buf),
+            Some(&b"index"[..])
+        );
+        assert_eq!(get_flash_panic_message(&mut flash, 0, &mut buf), None);
+    }
+
+    #[test]
+    fn long_message_is_truncated() {
+        let mut sink = FlashSink::<_, 64>::new(TestFlash::new(), 0);
+        persist(&mut sink, &[b'x'; 64]);
+        assert!(sink.status().truncated);
+
+        let mut buf = [0; 64];
+        let record = get_flash_panic_record(&mut sink.release(), 0, &mut buf).unwrap();
+        assert!(record.was_truncated());
+        assert!(record.message().iter().all(|&byte| byte == b'x'));
+    }
+
+    #[test]
+    fn corrupted_record_is_ignored() {
+        let mut sink = FlashSink::<_, 128>::new(TestFlash::new(), 0);
+        persist(&mut sink, b"index out of bounds");
+
+        let mut flash = sink.release();
+        flash.bytes[FLASH_HEADER_LEN + 1] = 0;
+        let mut buf = [0; 128];
+        assert!(get_flash_panic_record(&mut flash, 0, &mut buf).is_none());
+    }
+
+    #[test]
+    fn slots_are_written_in_turn() {
+        let slots = FlashSlots::new(0, PAGE as u32, 3);
+        let mut flash = TestFlash::new();
+
+        for message in [&b"one"[..], b"two", b"three", b"four"] {
+            let mut sink = FlashSink::<_, 128>::with_slots(flash, slots);
+            persist(&mut sink, message);
+            assert!(!sink.status().failed);
+            flash = sink.release();
+        }
+
+        // The fourth message overwrote the first one, and the others are left in place
+        let mut buf = [0; 128];
+        for (slot, sequence, message) in [(0, 3, &b"four"[..]), (1, 1, b"two"), (2, 2, b"three")] {
+            let (stored, record) =
+                get_flash_panic_record_in_slot(&mut flash, slots, slot, &mut buf).unwrap();
+            assert_eq!(stored, sequence);
+            assert_eq!(record.message(), message);
+
+            let (stored, text) =
+                get_flash_panic_message_in_slot(&mut flash, slots, slot, &mut buf).unwrap();
+            assert_eq!((stored, text), (sequence, message));
+        }
+        assert!(get_flash_panic_record_in_slot(&mut flash, slots, 3, &mut buf).is_none());
+    }
+}
@@ -0,0 +1,186 @@
+//! `Storage` drivers for SPI and I2C FRAMs, such as the Fujitsu MB85RS and MB85RC series
+
+use embedded_hal::i2c::{self, I2c};
+use embedded_hal::spi::{self, SpiDevice};
+use embedded_storage::{ReadStorage, Storage};
+
+/// Command setting the write enable latch of a SPI FRAM, needed before each write
+const WRITE_ENABLE: u8 = 0x06;
+
+/// Command reading data from a SPI FRAM
+const READ: u8 = 0x03;
+
+/// Command writing data to a SPI FRAM
+const WRITE: u8 = 0x02;
+
+/// SPI FRAMs up to this size use 16 bit addresses, and larger ones 24 bit addresses
+const SPI_SMALL_CAPACITY: usize = 64 * 1024;
+
+/// I2C FRAMs up to this size (the MB85RC16) use a single address byte, with the upper address bits
+/// in the device address
+const I2C_SMALL_CAPACITY: usize = 2048;
+
+/// Errors of a [`SpiFram`] or an [`I2cFram`]
+#[derive(Debug)]
+pub enum FramError<E> {
+    /// The bus transfer failed
+    Bus(E),
+    /// The arguments are out of bounds
+    OutOfBounds,
+}
+
+/// Check that `len` bytes at `offset` are within a FRAM of `capacity` bytes
+fn check<E>(capacity: usize, offset: u32, len: usize) -> Result<usize, FramError<E>> {
+    let offset = offset as usize;
+
+    if len > capacity || offset > capacity - len {
+        return Err(FramError::OutOfBounds);
+    }
+
+    Ok(offset)
+}
+
+/// A SPI FRAM of `capacity` bytes, for use with [`StorageSink`]. FRAMs are written byte by byte,
+/// without erasing or waiting, and with an almost unlimited endurance.
+///
+/// The FRAM is used from panic context, so the `SpiDevice` must not rely on interrupts, and the
+/// bus must not be in use by the code that panicked.
+///
+/// [`StorageSink`]: crate::StorageSink
+pub struct SpiFram<SPI> {
+    spi: SPI,
+    capacity: usize,
+}
+
+impl<SPI: SpiDevice> SpiFram<SPI> {
+    /// Create the driver of a FRAM of `capacity` bytes on `spi`
+    pub const fn new(spi: SPI, capacity: usize) -> Self {
+        Self { spi, capacity }
+    }
+
+    /// Give back the SPI device
+    pub fn release(self) -> SPI {
+        self.spi
+    }
+
+    /// `command`, followed by the address of `offset`
+    fn header(&self, command: u8, offset: usize) -> ([u8; 4], usize) {
+        let [_, a2, a1, a0] = (offset as u32).to_be_bytes();
+
+        if self.capacity <= SPI_SMALL_CAPACITY {
+            ([command, a1, a0, 0], 3)
+        } else {
+            ([command, a2, a1, a0], 4)
+        }
+    }
+}
+
+impl<SPI: SpiDevice> ReadStorage for SpiFram<SPI> {
+    type Error = FramError<SPI::Error>;
+
+    fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Self::Error> {
+        let offset = check(self.capacity, offset, bytes.len())?;
+
+        let (header, header_len) = self.header(READ, offset);
+        self.spi
+            .transaction(&mut [
+                spi::Operation::Write(&header[..header_len]),
+                spi::Operation::Read(bytes),
+            ])
+            .map_err(FramError::Bus)
+    }
+
+    fn capacity(&self) -> usize {
+        self.capacity
+    }
+}
+
+impl<SPI: SpiDevice> Storage for SpiFram<SPI> {
+    fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Self::Error> {
+        let offset = check(self.capacity, offset, bytes.len())?;
+
+        let (header, header_len) = self.header(WRITE, offset);
+        self.spi.write(&[WRITE_ENABLE]).map_err(FramError::Bus)?;
+        self.spi
+            .transaction(&mut [
+                spi::Operation::Write(&header[..header_len]),
+                spi::Operation::Write(bytes),
+            ])
+            .map_err(FramError::Bus)
+    }
+}
+
+/// An I2C FRAM of `capacity` bytes, at the 7 bit `address` (0x50 when the address pins are tied
+/// low), for use with [`StorageSink`]. FRAMs are written byte by byte, without erasing or waiting,
+/// and with an almost unlimited endurance.
+///
+/// The FRAM is used from panic context, so the `I2c` must not rely on interrupts, and the bus must
+/// not be in use by the code that panicked.
+///
+/// [`StorageSink`]: crate::StorageSink
+pub struct I2cFram<I2C> {
+    i2c: I2C,
+    address: u8,
+    capacity: usize,
+}
+
+impl<I2C: I2c> I2cFram<I2C> {
+    /// Create the driver of a FRAM of `capacity` bytes, at `address` on `i2c`
+    pub const fn new(i2c: I2C, address: u8, capacity: usize) -> Self {
+        Self {
+            i2c,
+            address,
+            capacity,
+        }
+    }
+
+    /// Give back the I2C bus
+    pub fn release(self) -> I2C {
+        self.i2c
+    }
+
+    /// The device address and the address bytes selecting `offset`
+    fn addressed(&self, offset: usize) -> (u8, [u8; 2], usize) {
+        if self.capacity <= I2C_SMALL_CAPACITY {
+            let block = ((offset >> 8) & 0x7) as u8;
+            (self.address | block, [offset as u8, 0], 1)
+        } else {
+            (self.address, (offset as u16).to_be_bytes(), 2)
+        }
+    }
+}
+
+impl<I2C: I2c> ReadStorage for I2cFram<I2C> {
+    type Error = FramError<I2C::Error>;
+
+    fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Self::Error> {
+        let offset = check(self.capacity, offset, bytes.len())?;
+
+        let (address, word, word_len) = self.addressed(offset);
+        self.i2c
+            .write_read(address, &word[..word_len], bytes)
+            .map_err(FramError::Bus)
+    }
+
+    fn capacity(&self) -> usize {
+        self.capacity
+    }
+}
+
+impl<I2C: I2c> Storage for I2cFram<I2C> {
+    fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Self::Error> {
+        let offset = check(self.capacity, offset, bytes.len())?;
+
+        // There are no pages, the address rolls over by itself
+        let (address, word, word_len) = self.addressed(offset);
+        self.i2c
+            .transaction(
+                address,
+                &mut [
+                    i2c::Operation::Write(&word[..word_len]),
+                    i2c::Operation::Write(bytes),
+                ],
+            )
+            .map_err(FramError::Bus)
+    }
+}
@@ -0,0 +1,232 @@
+//! Header in front of the persisted messages
+
+use core::sync::atomic::{compiler_fence, Ordering};
+
+use crate::HEADER_LEN;
+
+/// Version of the layout of the records, stored in their header. It is increased whenever the
+/// layout changes, so readers refuse the records of a newer layout instead of misparsing them
+pub(crate) const FORMAT_VERSION: u8 = 3;
+
+/// Flag of the header set when the message did not fit, and only part of it was stored
+pub(crate) const TRUNCATED: u8 = 1 << 0;
+
+/// The header of a record, as laid out in memory. All fields are fixed size, aligned to their size
+/// and stored in little endian, whichever core wrote the record, so it can be parsed by a host
+/// tool, or by a core of another architecture or width, from a raw dump of the region
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub(crate) struct Header {
+    /// Magic word marking a persisted panic message
+    pub(crate) magic: u32,
+    /// Layout of the record, which is `FORMAT_VERSION` for the records written by this crate
+    pub(crate) version: u8,
+    /// Id of the core that wrote the record
+    pub(crate) core: u8,
+    /// Flags of the record, such as `TRUNCATED`
+    pub(crate) flags: u8,
+    /// Number of times the panic happened again before the record was read, with the `dedupe`
+    /// feature
+    pub(crate) repeats: u8,
+    /// Length of the message, following the header
+    pub(crate) len: u32,
+    /// CRC-32 of the message
+    pub(crate) crc: u32,
+}
+
+/// Offset of the version in the header
+const VERSION_OFFSET: usize = 4;
+
+/// Offset of the core id in the header
+const CORE_OFFSET: usize = 5;
+
+/// Offset of the flags in the header
+const FLAGS_OFFSET: usize = 6;
+
+/// Offset of the count of repeats in the header
+const REPEATS_OFFSET: usize = 7;
+
+/// Offset of the message length in the header
+const LEN_OFFSET: usize = 8;
+
+/// Offset of the CRC-32 of the message in the header
+const CRC_OFFSET: usize = 12;
+
+impl Header {
+    /// The header of a message of `len` bytes whose CRC is `crc`, marked with `magic`, written by
+    /// the current core
+    pub(crate) fn new(magic: u32, len: u32, crc: u32) -> Self {
+        Self {
+            magic,
+            version: FORMAT_VERSION,
+            core: crate::core_id::current(),
+            flags: 0,
+            repeats: 0,
+            len,
+            crc,
+        }
+    }
+
+    /// The header, with the `TRUNCATED` flag set if `truncated` is
+    pub(crate) fn truncated(mut self, truncated: bool) -> Self {
+        if truncated {
+            self.flags |= TRUNCATED;
+        }
+        self
+    }
+
+    /// Read the header at `start`, which does not need to be aligned
+    ///
+    /// # Safety
+    ///
+    /// `start` must be valid for reads of `HEADER_LEN` bytes
+    pub(crate) unsafe fn read(start: *const u8) -> Self {
+        Self::from_bytes(start.cast::<[u8; HEADER_LEN]>().read_unaligned())
+    }
+
+    /// Read the magic word of the header at `start`, which does not need to be aligned
+    ///
+    /// # Safety
+    ///
+    /// `start` must be valid for reads of 4 bytes
+    pub(crate) unsafe fn read_magic(start: *const u8) -> u32 {
+        u32::from_le_bytes(start.cast::<[u8; 4]>().read_unaligned())
+    }
+
+    /// Write the header at `start`, which does not need to be aligned. The magic word is cleared
+    /// first and written last, so the record is only valid once the rest of the header is written
+    ///
+    /// # Safety
+    ///
+    /// `start` must be valid for writes of `HEADER_LEN` bytes
+    pub(crate) unsafe fn write(self, start: *mut u8) {
+        let bytes = self.to_bytes();
+        let magic = start.cast::<[u8; VERSION_OFFSET]>();
+
+        magic.write_unaligned([0; VERSION_OFFSET]);
+        compiler_fence(Ordering::SeqCst);
+        core::ptr::copy_nonoverlapping(
+            bytes[VERSION_OFFSET..].as_ptr(),
+            start.add(VERSION_OFFSET),
+            HEADER_LEN - VERSION_OFFSET,
+        );
+        compiler_fence(Ordering::SeqCst);
+        magic.write_unaligned([bytes[0], bytes[1], bytes[2], bytes[3]]);
+    }
+
+    /// The header as native endian 32 bit words, which hold the little endian bytes of the header
+    /// once stored, starting with the magic word
+    #[cfg(feature = "aligned-writes")]
+    pub(crate) fn to_words(self) -> [u32; HEADER_LEN / 4] {
+        let bytes = self.to_bytes();
+        let mut words = [0; HEADER_LEN / 4];
+
+        for (word, chunk) in words.iter_mut().zip(bytes.chunks_exact(4)) {
+            *word = u32::from_ne_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]);
+        }
+
+        words
+    }
+
+    /// The header as bytes, starting with the magic word
+    pub(crate) fn to_bytes(self) -> [u8; HEADER_LEN] {
+        let mut bytes = [0; HEADER_LEN];
+
+        bytes[..VERSION_OFFSET].copy_from_slice(&self.magic.to_le_bytes());
+        bytes[VERSION_OFFSET] = self.version;
+        bytes[CORE_OFFSET] = self.core;
+        bytes[FLAGS_OFFSET] = self.flags;
+        bytes[REPEATS_OFFSET] = self.repeats;
+        bytes[LEN_OFFSET..CRC_OFFSET].copy_from_slice(&self.len.to_le_bytes());
+        bytes[CRC_OFFSET..].copy_from_slice(&self.crc.to_le_bytes());
+
+        bytes
+    }
+
+    /// The header held by `bytes`, although maybe not a valid one
+    pub(crate) fn from_bytes(bytes: [u8; HEADER_LEN]) -> Self {
+        let word = |at: usize| {
+            u32::from_le_bytes([bytes[at], bytes[at + 1], bytes[at + 2], bytes[at + 3]])
+        };
+
+        Self {
+            magic: word(0),
+            version: bytes[VERSION_OFFSET],
+            core: bytes[CORE_OFFSET],
+            flags: bytes[FLAGS_OFFSET],
+            repeats: bytes[REPEATS_OFFSET],
+            len: word(LEN_OFFSET),
+            crc: word(CRC_OFFSET),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::MAGIC;
+
+    /// A header with a distinct value in each field
+    fn header() -> Header {
+        Header {
+            magic: MAGIC,
+            version: FORMAT_VERSION,
+            core: 1,
+            flags: TRUNCATED,
+            repeats: 2,
+            len: 0x1234,
+            crc: 0xDEAD_BEEF,
+        }
+    }
+
+    #[test]
+    fn to_bytes_is_little_endian() {
+        assert_eq!(
+            header().to_bytes(),
+            [
+                0xE0,
+                0xAD,
+                0xAC,
+                0x0F,
+                FORMAT_VERSION,
+                1,
+                TRUNCATED,
+                2,
+                0x34,
+                0x12,
+                0,
+                0,
+                0xEF,
+                0xBE,
+                0xAD,
+                0xDE
+            ]
+        );
+    }
+
+    #[test]
+    fn from_bytes_round_trip() {
+        let parsed = Header::from_bytes(header().to_bytes());
+
+        assert_eq!(parsed.magic, MAGIC);
+        assert_eq!(parsed.version, FORMAT_VERSION);
+        assert_eq!(parsed.core, 1);
+        assert_eq!(parsed.flags, TRUNCATED);
+        assert_eq!(parsed.repeats, 2);
+        assert_eq!(parsed.len, 0x1234);
+        assert_eq!(parsed.crc, 0xDEAD_BEEF);
+    }
+
+    #[test]
+    fn write_and_read_unaligned() {
+        let mut bytes = [0; HEADER_LEN + 1];
+        unsafe { header().write(bytes.as_mut_ptr().add(1)) };
+
+        assert_eq!(bytes[1..], header().to_bytes());
+        assert_eq!(
+            unsafe { Header::read(bytes.as_ptr().add(1)) }.to_bytes(),
+            header().to_bytes()
+        );
+        assert_eq!(unsafe { Header::read_magic(bytes.as_ptr().add(1)) }, MAGIC);
+    }
+}
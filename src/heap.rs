@@ -0,0 +1,77 @@
+//! Statistics of the heap allocator, stored in the record of a panic
+
+use core::sync::atomic::{AtomicPtr, Ordering};
+
+/// Size of the statistics, as stored in a record
+pub(crate) const HEAP_STATS_LEN: usize = 3 * 4;
+
+/// The source of the statistics registered with `set_heap_stats_source`, if any
+static HEAP_STATS_SOURCE: AtomicPtr<()> = AtomicPtr::new(core::ptr::null_mut());
+
+/// The statistics of the heap allocator at the time of the panic, as returned by
+/// [`Record::heap_stats`]
+///
+/// [`Record::heap_stats`]: crate::Record::heap_stats
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct HeapStats {
+    /// The number of bytes allocated
+    pub used: u32,
+    /// The number of bytes left
+    pub free: u32,
+    /// The most bytes that were allocated at once
+    pub high_water: u32,
+}
+
+impl HeapStats {
+    /// The statistics as stored in a record, in little endian
+    pub(crate) fn to_bytes(self) -> [u8; HEAP_STATS_LEN] {
+        let mut bytes = [0; HEAP_STATS_LEN];
+
+        for (chunk, word) in bytes
+            .chunks_exact_mut(4)
+            .zip(&[self.used, self.free, self.high_water])
+        {
+            chunk.copy_from_slice(&word.to_le_bytes());
+        }
+
+        bytes
+    }
+
+    /// The statistics stored in a record as `bytes`
+    pub(crate) fn from_bytes(bytes: &[u8; HEAP_STATS_LEN]) -> Self {
+        let word =
+            |i: usize| u32::from_le_bytes([bytes[i], bytes[i + 1], bytes[i + 2], bytes[i + 3]]);
+
+        Self {
+            used: word(0),
+            free: word(4),
+            high_water: word(8),
+        }
+    }
+}
+
+/// Register the function returning the statistics of the heap allocator, which the panic handler
+/// calls to store them in the record of a panic, as returned by [`Record::heap_stats`]. This is
+/// meant to be called once at startup, before any panic can happen.
+///
+/// The function is called from panic context, with interrupts disabled, so it must not allocate,
+/// or take the lock of the allocator, which might be held by the code that panicked
+///
+/// [`Record::heap_stats`]: crate::Record::heap_stats
+pub fn set_heap_stats_source(source: fn() -> HeapStats) {
+    HEAP_STATS_SOURCE.store(source as *mut (), Ordering::Release);
+}
+
+/// The current statistics, as returned by the registered source, if any
+pub(crate) fn current() -> Option<HeapStats> {
+    let ptr = HEAP_STATS_SOURCE.load(Ordering::Acquire);
+
+    if ptr.is_null() {
+        return None;
+    }
+
+    // Only `fn() -> HeapStats` pointers are ever stored
+    let source: fn() -> HeapStats = unsafe { core::mem::transmute(ptr) };
+    Some(source())
+}
@@ -0,0 +1,31 @@
+//! Functions registered at runtime
+
+use core::sync::atomic::{AtomicPtr, Ordering};
+
+/// An optional `fn()` registered at runtime, called from panic context
+pub(crate) struct Hook(AtomicPtr<()>);
+
+impl Hook {
+    /// A hook with no function registered
+    pub(crate) const fn new() -> Self {
+        Self(AtomicPtr::new(core::ptr::null_mut()))
+    }
+
+    /// Register `f`, replacing the previous function if any
+    pub(crate) fn set(&self, f: fn()) {
+        self.0.store(f as *mut (), Ordering::Release);
+    }
+
+    /// Call the registered function, if any. The panic handler calls the exit function of the
+    /// `sim` feature, which is not called with the `custom-panic-handler` feature
+    #[cfg_attr(feature = "custom-panic-handler", allow(dead_code))]
+    pub(crate) fn call(&self) {
+        let ptr = self.0.load(Ordering::Acquire);
+
+        if !ptr.is_null() {
+            // Only `fn()` pointers are ever stored
+            let f: fn() = unsafe { core::mem::transmute(ptr) };
+            f();
+        }
+    }
+}
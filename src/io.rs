@@ -0,0 +1,60 @@
+//! `embedded-io` writers as destinations of the panic message
+
+use core::fmt::Write as _;
+use core::panic::Location;
+
+use embedded_io::Write;
+
+use crate::sink::{Formatter, PanicSink};
+
+/// A [`PanicSink`] writing the panic message to an `embedded-io` writer, such as a UART or a USB
+/// CDC class, with blocking writes. The output is best effort: errors are ignored.
+///
+/// The writer is used from panic context, so it must not wait for interrupts.
+#[repr(transparent)]
+pub struct IoSink<W> {
+    writer: W,
+}
+
+impl<W: Write> IoSink<W> {
+    /// Create a sink writing to `writer`
+    pub const fn new(writer: W) -> Self {
+        Self { writer }
+    }
+
+    /// Give back the writer
+    pub fn release(self) -> W {
+        self.writer
+    }
+}
+
+impl<W: Write> PanicSink for IoSink<W> {
+    fn location(&mut self, location: &Location<'_>) {
+        writeln!(Formatter(self), "panicked at {}:", location).ok();
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        self.writer.write_all(bytes).ok();
+    }
+
+    fn finalize(&mut self) {
+        self.writer.flush().ok();
+    }
+}
+
+/// Write the panic message from the last boot, if any, to `writer`, and return whether there was
+/// one. This is meant to be called on boot, to send the message to a console or a logger.
+///
+/// If a message existed, it is only written once (subsequent calls will return `Ok(false)`), even
+/// if writing it failed.
+pub fn drain_panic_message<W: Write>(writer: &mut W) -> Result<bool, W::Error> {
+    let written = crate::copy_panic_message(|msg| {
+        writer.write_all(msg)?;
+        writer.flush()
+    });
+
+    match written {
+        Some(result) => result.map(|()| true),
+        None => Ok(false),
+    }
+}
@@ -0,0 +1,198 @@
+//! Key-value store kept in front of the panic region, which is frozen by the panic handler
+
+use crate::region::Region;
+
+/// The number of values the store holds
+const SLOTS: usize = 8;
+
+/// Size of a slot: the key, the value, and a check word, so random memory content after a power
+/// cycle is not mistaken for a value
+const SLOT_WORDS: usize = 3;
+
+/// Size of the store: the state word, followed by the slots
+pub(crate) const KV_LEN: usize = 4 + SLOTS * SLOT_WORDS * 4;
+
+/// Marks a store frozen by the panic handler
+const FROZEN: u32 = 0x0FACADE2;
+
+/// The words of the store, if the memory of the region can hold it
+fn words() -> Option<*mut u32> {
+    let store = Region::kv()?;
+
+    // The store is accessed with aligned 32 bit reads and writes
+    if !(store as usize).is_multiple_of(4) {
+        return None;
+    }
+
+    Some(store.cast::<u32>())
+}
+
+/// Whether the store was frozen by a panic
+fn frozen(words: *mut u32) -> bool {
+    unsafe { words.read_volatile() == FROZEN }
+}
+
+/// The words of the slot at `index`
+fn slot(words: *mut u32, index: usize) -> *mut u32 {
+    words.wrapping_add(1 + index * SLOT_WORDS)
+}
+
+/// The key and the value held by `slot`, if it holds a valid one
+fn read(slot: *mut u32) -> Option<(u8, u32)> {
+    let (key, value, check) = unsafe {
+        (
+            slot.read_volatile(),
+            slot.add(1).read_volatile(),
+            slot.add(2).read_volatile(),
+        )
+    };
+
+    match key <= u8::MAX as u32 && check == !(key ^ value) {
+        true => Some((key as u8, value)),
+        false => None,
+    }
+}
+
+/// Store `value` under `key`, so it is kept with the panic record if the firmware panics later
+/// on, and returned by [`get_panic_kv`] on the next boot. This is meant for structured
+/// breadcrumbs, such as the battery level, the temperature or a sequence number.
+///
+/// The store holds 8 values: once all of them are taken, values under new keys are dropped. It
+/// is not meant to be written from several contexts at once, such as from thread mode and from an
+/// interrupt handler, and writes are ignored while it still holds the values of the last panic,
+/// until [`clear_panic_kv`] is called
+pub fn persist_kv(key: u8, value: u32) {
+    let words = match words() {
+        Some(words) if !frozen(words) => words,
+        _ => return,
+    };
+
+    // The slot already holding the key, or else the first free one
+    let slot = (0..SLOTS)
+        .map(|index| slot(words, index))
+        .find(|&slot| matches!(read(slot), Some((stored, _)) if stored == key))
+        .or_else(|| {
+            (0..SLOTS)
+                .map(|index| slot(words, index))
+                .find(|&slot| read(slot).is_none())
+        });
+
+    if let Some(slot) = slot {
+        let key = u32::from(key);
+
+        // The slot is invalidated first, so a panic in between never freezes half of a value
+        unsafe {
+            slot.add(2).write_volatile(0);
+            slot.write_volatile(key);
+            slot.add(1).write_volatile(value);
+            slot.add(2).write_volatile(!(key ^ value));
+        }
+    }
+}
+
+/// The value stored under `key` with [`persist_kv`] when the firmware panicked, if any.
+///
+/// The store is left as it is, so the values are returned until [`clear_panic_kv`] is called
+pub fn get_panic_kv(key: u8) -> Option<u32> {
+    let words = words()?;
+    if !frozen(words) {
+        return None;
+    }
+
+    (0..SLOTS).find_map(|index| match read(slot(words, index))? {
+        (stored, value) if stored == key => Some(value),
+        _ => None,
+    })
+}
+
+/// Clear the values of the store, and let [`persist_kv`] store new ones. This is meant to be called
+/// on boot, once the values of the last panic were read, so the values of an earlier run are not
+/// kept with the next panic
+pub fn clear_panic_kv() {
+    if let Some(words) = words() {
+        unsafe {
+            words.write_volatile(0);
+            for index in 0..SLOTS {
+                slot(words, index).add(2).write_volatile(0);
+                slot(words, index).write_volatile(u32::MAX);
+            }
+        }
+    }
+}
+
+/// Freeze the store, so the values it holds are kept along with the panic record
+pub(crate) fn freeze() {
+    if let Some(words) = words() {
+        unsafe { words.write_volatile(FROZEN) };
+    }
+}
+
+#[cfg(all(test, feature = "runtime-region"))]
+mod tests {
+    use super::*;
+    use crate::region::with_test_region;
+
+    #[test]
+    fn values_are_frozen_by_a_panic() {
+        with_test_region(512, |_| {
+            clear_panic_kv();
+            persist_kv(1, 10);
+            persist_kv(2, 20);
+            persist_kv(1, 11);
+
+            // The values are only returned once they were frozen
+            assert_eq!(get_panic_kv(1), None);
+            freeze();
+            assert_eq!(get_panic_kv(1), Some(11));
+            assert_eq!(get_panic_kv(2), Some(20));
+            assert_eq!(get_panic_kv(3), None);
+
+            // The values of the panic are kept until they are cleared
+            persist_kv(1, 12);
+            assert_eq!(get_panic_kv(1), Some(11));
+
+            clear_panic_kv();
+            assert_eq!(get_panic_kv(1), None);
+            persist_kv(1, 12);
+            freeze();
+            assert_eq!(get_panic_kv(1), Some(12));
+            assert_eq!(get_panic_kv(2), None);
+        });
+    }
+
+    #[test]
+    fn random_content_holds_no_values() {
+        with_test_region(512, |_| {
+            freeze();
+
+            assert!((0..=u8::MAX).all(|key| get_panic_kv(key).is_none()));
+        });
+    }
+
+    #[test]
+    fn values_under_new_keys_are_dropped_once_full() {
+        with_test_region(512, |_| {
+            clear_panic_kv();
+            for key in 0..=SLOTS as u8 {
+                persist_kv(key, u32::from(key) * 100);
+            }
+            freeze();
+
+            assert_eq!(
+                get_panic_kv(SLOTS as u8 - 1),
+                Some((SLOTS as u32 - 1) * 100)
+            );
+            assert_eq!(get_panic_kv(SLOTS as u8), None);
+        });
+    }
+
+    #[test]
+    fn region_too_short_holds_no_store() {
+        with_test_region(KV_LEN - 1, |_| {
+            persist_kv(1, 10);
+            freeze();
+
+            assert_eq!(get_panic_kv(1), None);
+        });
+    }
+}
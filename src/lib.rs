@@ -12,15 +12,58 @@
 //! control flow.
 //!
 //! Currently this crate was only tested on ARM Cortex-M architecture but should be easily portable
-//! to other platforms as required.
+//! to other platforms as required. RISC-V targets (`riscv32imc`, `riscv32imac`, ...) are
+//! supported as well, where the "reset" only jumps back to the `_start` entry point (or goes through
+//! `esp-hal` on the ESP32-C3/C6, see the `esp-riscv` feature). The jump is a soft restart, not a
+//! reset: the peripherals, the interrupt controller and the other harts keep their state, so a real
+//! reset of the platform (such as through its watchdog or its power management unit) should be
+//! provided with the `custom-reset` feature in production. The Xtensa based ESP32 chips are
+//! supported too, through the `esp-hal` feature. On Cortex-A and Cortex-R cores,
+//! interrupts are disabled through the CPSR (or DAIF), but the reset must be provided through
+//! the `__panic_persist_reset` hook described in the `custom-reset` feature. MSP430 devices are
+//! reset through the watchdog, using the `WDTCTL` address of the F5xx/F6xx and FRAM families.
+//!
+//! On hosted targets (anything with an operating system, such as when running unit tests of a
+//! mixed workspace on your machine), the panic handler and all architecture specific code are left
+//! out, and no linker symbols are needed. The panic region is empty there, so no message is ever
+//! persisted and the `get_panic_message_*` functions always return `None`.
 //!
 //! ## Usage
 //!
 //! ### Add a section to your linker script
 //!
 //! You will need to reserve a section of RAM to be used to persist messages. This section must be
-//! large enough to hold the 8 byte header, as well as any panic messages you would like to persist.
-//! If there is not suitable space in the section, the panic message will be truncated.
+//! large enough to hold the 16 byte header and the 16 byte location, as well as any panic messages
+//! you would like to persist. The header is made of a 32 bit magic word, the version of its layout,
+//! the id of the core that wrote it, a 32 bit length and the CRC-32 of the payload, all stored in
+//! little endian, so it has the same layout on all targets: a record written by a 32 bit core can
+//! be read by a 64 bit core, or by a host tool from a raw dump of the region. The payload starts
+//! with the location of the panic, whose line is zero when it is unknown, and the extra fields,
+//! followed by the message. Each extra field is a 16 bit tag and a 16 bit length, followed by its
+//! data padded to a multiple of 4 bytes, and readers skip the fields they don't know of;
+//!
+//! | Offset   | Size       | Field                                           |
+//! |----------|------------|-------------------------------------------------|
+//! | 0        | 4          | Magic word, `0x0FACADE0`                        |
+//! | 4        | 1          | Version of the layout, `3`                      |
+//! | 5        | 1          | Id of the core that wrote the record            |
+//! | 6        | 1          | Flags, bit 0 set if the message is truncated    |
+//! | 7        | 1          | Repeats of the panic, with the `dedupe` feature |
+//! | 8        | 4          | Length of the payload                           |
+//! | 12       | 4          | CRC-32 of the payload                           |
+//! | 16       | 4          | Line of the panic                               |
+//! | 20       | 4          | Column of the panic                             |
+//! | 24       | 4          | Length of the file name                         |
+//! | 28       | 4          | Length of the extra fields                      |
+//! | 32       | file_len   | File name, padded with zeros to a multiple of 4 |
+//! | 32 + pad | fields_len | Extra fields, such as the build id              |
+//! | after    | rest       | Message                                         |
+//!
+//! A message that does not match its CRC, such as random memory content after a power cycle, is
+//! never returned, and neither is a record with a newer layout, such as one written by an updated
+//! firmware and read by an older bootloader.
+//! If there is not suitable space in the section, the panic message will be truncated, and the
+//! `was_truncated` method of the records tells so.
 //!
 //! This section should be outside of any other sections, to prevent program initialization from
 //! zeroing or otherwise modifying these sections on boot.
@@ -44,13 +87,38 @@
 //!   /* NOTE K = KiBi = 1024 bytes */
 //!   FLASH : ORIGIN  = 0x00000000, LENGTH = 512K
 //!   RAM : ORIGIN    = 0x20000000, LENGTH = 63K
-//!   PANDUMP: ORIGIN = 0x2000FC00, LENGTH = 1K
+//!   PANDUMP : ORIGIN = 0x2000FC00, LENGTH = 1K
 //! }
 //!
 //! _panic_dump_start = ORIGIN(PANDUMP);
 //! _panic_dump_end   = ORIGIN(PANDUMP) + LENGTH(PANDUMP);
 //! ```
 //!
+//! Instead of defining the symbols by hand, the `panic_persist.x` fragment shipped with this crate can
+//! be included after the `MEMORY` block, which must declare the `PANDUMP` region. Linking fails if it
+//! is missing, or if it can't hold the header and the location;
+//!
+//! ``` ignore
+//! MEMORY
+//! {
+//!   /* ... */
+//!   PANDUMP : ORIGIN = 0x2000FC00, LENGTH = 1K
+//! }
+//!
+//! INCLUDE panic_persist.x
+//! ```
+//!
+//! The `panic-persist-build` crate can also generate `memory.x` from the build script, reserving the
+//! panic region at the end of the RAM, from a `memory.in.x` file holding the `MEMORY` block without
+//! it;
+//!
+//! ``` ignore
+//! // build.rs
+//! fn main() {
+//!     panic_persist_build::reserve_panic_region(1024).unwrap();
+//! }
+//! ```
+//!
 //!
 //! ### Program Usage Example
 //!
@@ -72,6 +140,413 @@
 //! }
 //! ```
 //!
+//! ### Getting the whole record
+//!
+//! Besides the message, a record holds the location of the panic and the fields stored along with it,
+//! such as the build id or the count of repeats. `get_panic_record` returns all of them at once, as a
+//! `Record`, consuming the record as `get_panic_message_bytes` does;
+//!
+//! ``` ignore
+//! if let Some(record) = panic_persist::get_panic_record() {
+//!     if let Some(location) = record.location() {
+//!         defmt::info!("panicked at {}:{}", location.file(), location.line());
+//!     }
+//!     if record.was_truncated() {
+//!         defmt::warn!("the message was truncated");
+//!     }
+//!     board.uart.write(record.message());
+//! }
+//! ```
+//!
+//! `Record` implements `Display` (and `defmt::Format`, with the `defmt` feature), which writes the
+//! message with its invalid UTF-8 sequences replaced, so it can be printed as it is;
+//!
+//! ``` ignore
+//! if let Some(record) = panic_persist::get_panic_record() {
+//!     writeln!(uart, "{}", record).ok();
+//! }
+//! ```
+//!
+//! When the region holds several records, such as the records of both cores with the `rp2040` feature,
+//! `panic_records` returns all of them in turn, so they can be drained at once;
+//!
+//! ``` ignore
+//! for record in panic_persist::panic_records() {
+//!     board.uart.write(record.message());
+//! }
+//! ```
+//!
+//! To group panics by location, such as in telemetry, `get_panic_location` returns the location of
+//! the panic of the record, without parsing the message, and leaves the record in place.
+//!
+//! ### Peeking at the message
+//!
+//! `get_panic_message_bytes` consumes the message, so it is only returned once. To inspect it early
+//! on boot, such as to decide whether to enter a safe mode, and let a later part of the firmware
+//! consume it, `peek_panic_message_bytes` returns the message while leaving it in place;
+//!
+//! ``` ignore
+//! if let Some(msg) = panic_persist::peek_panic_message_bytes() {
+//!     if msg.starts_with(b"flash") {
+//!         enter_safe_mode();
+//!     }
+//! }
+//!
+//! // ...later on, once the network is up
+//! if let Some(msg) = panic_persist::get_panic_message_bytes() {
+//!     upload(msg);
+//! }
+//! ```
+//!
+//! Once the message was handled, such as uploaded, `clear_panic_message` clears it without reading it
+//! again, so the application decides when the record is considered handled. It only clears the header
+//! of the record, while `wipe_panic_message` overwrites the whole region with zeroes.
+//!
+//! To only branch on whether the firmware panicked, such as early on boot, `has_panic_message` reads
+//! the magic word of the region, without checking the rest of the record or clearing anything.
+//!
+//! Rather than borrowing the region, `get_panic_message_into` copies the message into a buffer of the
+//! application, and returns the number of bytes that were copied.
+//!
+//! The length of the message, whether it was truncated and the counts of the panics are returned by
+//! `record_metadata` (and the length alone by `get_panic_message_len`), leaving the record in place,
+//! so the buffer the message is copied into can be allocated before the message is retrieved.
+//!
+//! ### Persisting other messages
+//!
+//! `persist_message` persists a message of the application, without panicking, such as to record a
+//! fatal but handled condition before resetting the device. It is written as a panic message would
+//! be, without a location, and is retrieved the same way on the next boot;
+//!
+//! ``` ignore
+//! panic_persist::persist_message(format_args!("brown-out imminent, {} mV", millivolts));
+//! ```
+//!
+//! To write the message in several parts instead, such as the message, then the registers, then
+//! extra context, a `PersistWriter` streams it into the panic region, as a `core::fmt::Write`. The
+//! message is committed by `finish`, or when the writer is dropped;
+//!
+//! ``` ignore
+//! let mut writer = panic_persist::PersistWriter::new();
+//! write!(writer, "{}", info.message()).ok();
+//! write!(writer, " pc={:#x}", pc).ok();
+//! let status = writer.finish();
+//! ```
+//!
+//! ### Persisting allocation failures
+//!
+//! When an allocation fails, the default handler of the `alloc` crate panics, with the requested
+//! size. `alloc_error` panics with its alignment as well, so the allocation failure is persisted (and
+//! the device reset) as any other panic, with the location of the caller. It is meant to be called
+//! from an `#[alloc_error_handler]`, on nightly, or by an allocator failing an allocation;
+//!
+//! ``` ignore
+//! #[alloc_error_handler]
+//! fn oom(layout: core::alloc::Layout) -> ! {
+//!     panic_persist::alloc_error(layout)
+//! }
+//! ```
+//!
+//! ### Checking the region
+//!
+//! A misconfigured region causes no error, it just never returns a message. `check_region` tells
+//! on boot whether the region is usable: it must be properly ordered, aligned to 4 bytes and able to
+//! hold the header and the location, and, on Cortex-M and RISC-V targets, it must be outside of the
+//! `.data` and `.bss` sections and of the stack, which ends at the start of the heap, so it may be
+//! placed in the `.uninit` section;
+//!
+//! ``` ignore
+//! match panic_persist::check_region() {
+//!     Ok(info) => defmt::info!("{} byte panic messages", info.max_message_len()),
+//!     Err(err) => defmt::error!("unusable panic region: {}", defmt::Debug2Format(&err)),
+//! }
+//! ```
+//!
+//! When no message is ever returned, `validate_region` tells what the region holds, rather than just
+//! whether it holds a valid record: `Empty` when the region is wiped on boot (or the record was
+//! already retrieved), `ValidRecord`, or why the record is rejected, `CorruptLength`, `BadChecksum` or
+//! `WrongVersion`. The record is left as it is;
+//!
+//! ``` ignore
+//! defmt::info!("{}", defmt::Debug2Format(&panic_persist::validate_region()));
+//! ```
+//!
+//! ### Checking that the region survives a reset
+//!
+//! Some bootloaders and startup code wipe the RAM, which loses the panic messages. The persistence
+//! self-test tells whether the region survives a reset: `start_persistence_self_test` fills the
+//! region with a test pattern and resets the device, and `persistence_self_test` tells on the next
+//! boot whether the pattern survived;
+//!
+//! ``` ignore
+//! match panic_persist::persistence_self_test() {
+//!     SelfTest::Survived => defmt::info!("the panic region survives resets"),
+//!     SelfTest::Corrupted { offset } => defmt::error!("the panic region is overwritten at {}", offset),
+//!     // No test was started before this boot (or the region was entirely wiped)
+//!     SelfTest::Missing => {
+//!         if board.button.is_pressed() {
+//!             panic_persist::start_persistence_self_test();
+//!         }
+//!     }
+//! }
+//! ```
+//!
+//! With the `custom-panic-handler` feature, `start_persistence_self_test` does not reset the device,
+//! which must then be reset the same way the custom panic handler does.
+//!
+//! ### Owning the panic region
+//!
+//! Instead of the linker symbols, the panic region can also be a `PanicRegion<N>` static, which owns
+//! its buffer of `N` bytes (header included), so its size is a constant that can be checked at compile
+//! time. It must not be initialized on boot, so it is placed in a `.uninit` section. Messages are
+//! written to it through its sink, and retrieved with its own `get_panic_message_*` functions. With
+//! the `runtime-region` feature, it can instead be installed as the panic region of the crate;
+//!
+//! ``` ignore
+//! #[link_section = ".uninit.PANIC_REGION"]
+//! static PANIC_REGION: PanicRegion<1024> = PanicRegion::new();
+//!
+//! // The region must hold messages of at least 512 bytes
+//! const _: () = assert!(PanicRegion::<1024>::MAX_MESSAGE_LEN >= 512);
+//!
+//! static SINK: StaticCell<RamSink> = StaticCell::new();
+//!
+//! if let Some(msg) = PANIC_REGION.get_panic_message_bytes() {
+//!     board.uart.write(msg);
+//! }
+//!
+//! panic_persist::set_panic_sink(SINK.init(PANIC_REGION.sink()));
+//! ```
+//!
+//! ### Named regions
+//!
+//! When several images, such as a bootloader and an application, use this crate, each of them can own
+//! its own slot with `named_region!`, which declares a module giving access to the region between the
+//! `_panic_dump_<name>_start` and `_panic_dump_<name>_end` linker symbols. Each image writes its
+//! messages to its own region through the sink of its module, and can read the messages of the other
+//! images from theirs;
+//!
+//! ``` ignore
+//! MEMORY
+//! {
+//!   /* ... */
+//!   PANDUMP_BOOT : ORIGIN = 0x2000F800, LENGTH = 1K
+//!   PANDUMP_APP  : ORIGIN = 0x2000FC00, LENGTH = 1K
+//! }
+//!
+//! _panic_dump_bootloader_start = ORIGIN(PANDUMP_BOOT);
+//! _panic_dump_bootloader_end   = ORIGIN(PANDUMP_BOOT) + LENGTH(PANDUMP_BOOT);
+//! _panic_dump_app_start        = ORIGIN(PANDUMP_APP);
+//! _panic_dump_app_end          = ORIGIN(PANDUMP_APP) + LENGTH(PANDUMP_APP);
+//! ```
+//!
+//! ``` ignore
+//! panic_persist::named_region!(bootloader);
+//! panic_persist::named_region!(app);
+//!
+//! static SINK: StaticCell<RamSink> = StaticCell::new();
+//!
+//! // In the application
+//! if let Some(msg) = bootloader::get_panic_message_bytes() {
+//!     board.uart.write(msg);
+//! }
+//!
+//! panic_persist::set_panic_sink(SINK.init(app::sink()));
+//! ```
+//!
+//! ### Reading the region of another image
+//!
+//! When the location of the region of another image is only known at runtime, such as from a
+//! configuration block shared with it, its record can be read with `read_record_at`, which checks the
+//! header as for the region of this image, and leaves the record in place. The location of the panic
+//! is returned by `location`, apart from the message;
+//!
+//! ``` ignore
+//! let record = unsafe { panic_persist::read_record_at(config.pandump_ptr, config.pandump_len) };
+//!
+//! if let Some(record) = record {
+//!     if let Some(location) = record.location() {
+//!         defmt::info!("panicked at {}:{}", location.file(), location.line());
+//!     }
+//!     board.uart.write(record.message());
+//! }
+//! ```
+//!
+//! When only a copy of the region is at hand, such as a dump received from a device by a gateway,
+//! `decode_record` decodes the record from the bytes, with the same checks, and tells why it can't
+//! with a `DecodeError`. It only parses the bytes, so host tools decode records with the same code as
+//! the firmware, including the records of a TrustZone secure image;
+//!
+//! ``` ignore
+//! match panic_persist::decode_record(&dump[descriptor.record_offset()..]) {
+//!     Ok(record) => println!("{}", record),
+//!     Err(error) => println!("no record: {:?}", error),
+//! }
+//! ```
+//!
+//! ### Describing the region to other images
+//!
+//! A bootloader or a factory tool compiled separately, which does not link this crate (or links
+//! another version), needs to know where the records are, and their layout, to find and parse them.
+//! `region_descriptor` returns the address and the length of the part of the region holding the
+//! records, along with the magic word and the version of the header, the length of the header, the
+//! bytes reserved in front of the records by other features, and the number of slots the region is
+//! split into. It can be stored in a configuration block shared with the bootloader;
+//!
+//! ``` ignore
+//! let descriptor = panic_persist::region_descriptor();
+//!
+//! config.pandump_ptr = descriptor.start();
+//! config.pandump_len = descriptor.len();
+//! ```
+//!
+//! ### Identifying the firmware
+//!
+//! Panics collected from a fleet of devices are of little use without knowing which firmware build
+//! produced them. `set_build_id` registers an id, such as the version of the firmware, the hash of
+//! its commit or its 20 byte GNU build id, which is stored in every record, and returned by the
+//! `build_id` method of the records;
+//!
+//! ``` ignore
+//! #[entry]
+//! fn main() -> ! {
+//!     panic_persist::set_build_id(env!("GIT_HASH").as_bytes());
+//!
+//!     // ...
+//! }
+//! ```
+//!
+//! ### Stamping the records with the time
+//!
+//! `set_time_source` registers a function returning the current time, such as the ticks since boot
+//! or the seconds of an RTC, which the panic handler calls to stamp the record with the time of the
+//! panic, returned by the `timestamp` method of the records. It is called from panic context, so it
+//! must not wait for interrupts;
+//!
+//! ``` ignore
+//! fn now() -> u64 {
+//!     monotonic::now().ticks()
+//! }
+//!
+//! panic_persist::set_time_source(now);
+//! ```
+//!
+//! ### Attaching the context of the application
+//!
+//! The panic message alone often lacks the state of the application needed to reproduce the bug.
+//! `set_panic_context` registers a few bytes of context, such as the state of a state machine or the
+//! id of the last command, which are stored in the record of a panic, and returned by the `context`
+//! method of the records. It is called again whenever the context changes;
+//!
+//! ``` ignore
+//! static STATES: [[u8; 1]; 3] = [[0], [1], [2]];
+//!
+//! fn enter(state: State) {
+//!     panic_persist::set_panic_context(&STATES[state as usize]);
+//! }
+//! ```
+//!
+//! ### Recording the state of the heap
+//!
+//! Many panics come from a failed allocation, and the state of the heap is then the missing clue.
+//! `set_heap_stats_source` registers a function returning the statistics of the allocator, which the
+//! panic handler calls to store them in the record, as returned by the `heap_stats` method of the
+//! records. It is called from panic context, so it must not allocate, nor take the lock of the
+//! allocator;
+//!
+//! ``` ignore
+//! fn heap_stats() -> HeapStats {
+//!     HeapStats {
+//!         used: HEAP.used() as u32,
+//!         free: HEAP.free() as u32,
+//!         high_water: HIGH_WATER.load(Ordering::Relaxed),
+//!     }
+//! }
+//!
+//! panic_persist::set_heap_stats_source(heap_stats);
+//! ```
+//!
+//! ### Telling where the panic happened
+//!
+//! On Cortex-M targets, the number of the active exception, from the IPSR register, is stored in
+//! every record, and returned by the `exception` method of the records. It is 0 for panics in thread
+//! mode, and the number of the exception otherwise, where 16 is the first interrupt;
+//!
+//! ``` ignore
+//! match record.exception() {
+//!     Some(0) => defmt::info!("panicked in thread mode"),
+//!     Some(n) if n >= 16 => defmt::info!("panicked in the handler of IRQ {}", n - 16),
+//!     Some(n) => defmt::info!("panicked in exception {}", n),
+//!     None => {}
+//! }
+//! ```
+//!
+//! ### Telling the cores apart
+//!
+//! On multicore chips, the header of each record holds the id of the core that wrote it, returned by
+//! the `core` method of the records. It is read from the SIO with the `rp2040` feature, and from
+//! MPIDR_EL1 on bare metal AArch64. On other chips, `set_core_id_source` registers a function
+//! returning it;
+//!
+//! ``` ignore
+//! fn core_id() -> u8 {
+//!     hal::cpu::current_core_id() as u8
+//! }
+//!
+//! panic_persist::set_core_id_source(core_id);
+//! ```
+//!
+//! ### Diagnosing the reset
+//!
+//! The cause of the reset, as reported by the reset controller of the MCU, tells more about the last
+//! boot once it is matched against the presence of a panic record: a watchdog reset without a record
+//! means the firmware hung, rather than panicked. `diagnose_reset` takes a function reading the cause
+//! of the reset, and returns the diagnosis. It must be called before the record is retrieved, as
+//! `get_panic_message_bytes` clears it;
+//!
+//! ``` ignore
+//! use panic_persist::{ResetCause, ResetDiagnosis};
+//!
+//! let diagnosis = panic_persist::diagnose_reset(|| {
+//!     let csr = rcc.csr.read();
+//!     match () {
+//!         _ if csr.iwdgrstf().bit() => ResetCause::Watchdog,
+//!         _ if csr.borrstf().bit() => ResetCause::Brownout,
+//!         _ if csr.porrstf().bit() => ResetCause::PowerOn,
+//!         _ if csr.sftrstf().bit() => ResetCause::Software,
+//!         _ if csr.pinrstf().bit() => ResetCause::Pin,
+//!         _ => ResetCause::Other,
+//!     }
+//! });
+//!
+//! if diagnosis == ResetDiagnosis::WatchdogNoRecord {
+//!     defmt::warn!("the firmware hung before the last reset");
+//! }
+//! ```
+//!
+//! ### Choosing what happens after the panic
+//!
+//! Once the panic was persisted, the panic handler resets the device by default. `set_panic_action`
+//! replaces the reset with another `PanicAction`: spinning with interrupts disabled, executing a
+//! breakpoint instruction, which stops at the panic in the debugger, or calling a function of the
+//! application, which never returns. This way the same panic handler stops at the panic during
+//! bring-up, and resets the device in production;
+//!
+//! ``` ignore
+//! #[cfg(debug_assertions)]
+//! panic_persist::set_panic_action(panic_persist::PanicAction::Breakpoint);
+//!
+//! #[cfg(not(debug_assertions))]
+//! panic_persist::set_panic_action(panic_persist::PanicAction::Custom(|| {
+//!     // Let the watchdog reset the device
+//!     loop {}
+//! }));
+//! ```
+//!
+//! With `PanicAction::BreakpointIfDebugged`, the breakpoint is only executed if a debugger is
+//! attached, as told by the `C_DEBUGEN` bit of the `DHCSR` register on Cortex-M, and the device is
+//! reset otherwise, so the same firmware stops at the panic on the bench, and resets in the field.
+//!
 //! ## Features
 //!
 //! There are a few optional features, `utf8` and `custom-panic-handler`.
@@ -83,12 +558,45 @@
 //! to validate the UTF-8 string (to ensure it wasn't truncated mid-character), it may
 //! increase code size usage, and is by default off.
 //!
+//! `get_panic_message_utf8` returns the message up to its first invalid UTF-8 sequence, while
+//! `get_panic_message_utf8_lossy` decodes all of it into a buffer, replacing the invalid sequences
+//! with U+FFFD, so as much of the message as possible is returned.
+//!
+//! ### heapless
+//!
+//! This adds `get_panic_message_vec` (and `get_panic_message_string`, along with the `utf8` feature),
+//! which copy the panic message into a `heapless::Vec` (or a `heapless::String`) and consume it, for
+//! the common pattern of stashing the message in a small owned buffer, to send it over a protocol
+//! stack later on. The message is truncated if it does not fit;
+//!
+//! ``` ignore
+//! let msg: Option<heapless::Vec<u8, 128>> = panic_persist::get_panic_message_vec();
+//! ```
+//!
+//! ### ffi
+//!
+//! This feature exports two C functions, so the C code of the same image, such as the assert handler
+//! of a vendor SDK or a FreeRTOS hook, can write and read the panic message, with the same region
+//! and the same format. `panic_persist_report` persists a message, as `persist_message` does, and
+//! `panic_persist_read` copies the message of the last boot into a buffer, as
+//! `get_panic_message_into` does, returning -1 if there is none;
+//!
+//! ```text
+//! void panic_persist_report(const uint8_t *msg, size_t len);
+//! ptrdiff_t panic_persist_read(uint8_t *buf, size_t cap);
+//!
+//! void vApplicationStackOverflowHook(TaskHandle_t task, char *name) {
+//!     panic_persist_report((const uint8_t *)name, strlen(name));
+//!     NVIC_SystemReset();
+//! }
+//! ```
+//!
 //! ### custom-panic-handler
 //!
 //! This disables the panic handler from this library so that any user can implement their own.
 //! To persist panic messages, the function `report_panic_info` is made available;
 //!
-//! ```rust
+//! ``` ignore
 //! // My custom panic implementation
 //! #[panic_handler]
 //! fn panic(info: &PanicInfo) -> ! {
@@ -98,121 +606,1613 @@
 //! }
 //! ```
 //!
+//! It returns a `WriteStatus`, with the number of bytes written, and whether the message was truncated
+//! or the sink failed to store it (such as on a flash error), so the handler can fall back to another
+//! sink, or signal the failure;
+//!
+//! ``` ignore
+//! let status = panic_persist::report_panic_info(info);
+//! if status.failed {
+//!     // Fall back to another sink, or blink an error code...
+//! }
+//! ```
+//!
+//! ### custom-reset
+//!
+//! This replaces the architecture specific code used by the panic handler to disable interrupts
+//! and to reset the device with two functions provided by the user. This allows the panic handler
+//! to be used on any architecture, or to perform a different kind of reset;
+//!
+//! ``` ignore
+//! #[no_mangle]
+//! fn __panic_persist_disable_interrupts() {
+//!     // Disable interrupts...
+//! }
+//!
+//! #[no_mangle]
+//! fn __panic_persist_reset() -> ! {
+//!     // Reset the device...
+//! }
+//! ```
+//!
+//! ### custom-sink
+//!
+//! The panic message is written to a `PanicSink`, which by default is the `RamSink`, storing it in the
+//! panic region. With this feature, the panic handler writes to a sink provided by the user instead,
+//! such as a backup SRAM, an external memory, or a region with a different layout, while still
+//! formatting the message (and the rest of the panic handling) as usual. The sink can also be
+//! registered once at runtime with `set_panic_sink`, without this feature, if it is only created
+//! then;
+//!
+//! ``` ignore
+//! struct MySink;
+//!
+//! impl panic_persist::PanicSink for MySink {
+//!     fn write(&mut self, bytes: &[u8]) {
+//!         // Append the bytes to the message...
+//!     }
+//!
+//!     fn finalize(&mut self) {
+//!         // Commit the message...
+//!     }
+//! }
+//!
+//! #[no_mangle]
+//! fn __panic_persist_sink() -> &'static mut dyn panic_persist::PanicSink {
+//!     static mut SINK: MySink = MySink;
+//!     unsafe { &mut *core::ptr::addr_of_mut!(SINK) }
+//! }
+//! ```
+//!
+//! The message is then only retrieved through your own sink, not with the `get_panic_message_*`
+//! functions. With the `custom-panic-handler` feature, the sink is passed to `report_panic_info_to`
+//! instead.
+//!
+//! To persist the message to several places at once, such as the panic region (which always works)
+//! and an external memory (which survives power loss), the sinks can be combined with a `MultiSink`;
+//!
+//! ``` ignore
+//! static RAM: StaticCell<RamSink> = StaticCell::new();
+//! static FRAM: StaticCell<StorageSink<SpiFram<Spi>>> = StaticCell::new();
+//! static SINK: StaticCell<MultiSink<'static, 2>> = StaticCell::new();
+//!
+//! let sinks: [&mut dyn PanicSink; 2] = [RAM.init(RamSink::new()), FRAM.init(fram_sink)];
+//! panic_persist::set_panic_sink(SINK.init(MultiSink::new(sinks)));
+//! ```
+//!
+//! ### esp-hal
+//!
+//! This uses [`esp-hal`] to reset the chip after a panic, which is required on the Xtensa based
+//! ESP32, ESP32-S2 and ESP32-S3. The chip itself is selected through your own `esp-hal` dependency.
+//!
+//! ### esp-riscv
+//!
+//! This integrates with [`esp-hal`] on the RISC-V based ESP32-C3 and ESP32-C6 (and their siblings):
+//! the chip is reset with `esp_hal::system::software_reset` rather than by jumping back to `_start`,
+//! and the panic region is placed in the RTC fast memory as with the `esp32-rtc-fast` feature. This
+//! works with the `esp-hal` linker scripts as they are, as they already provide the non-initialized
+//! `.rtc_fast.persistent` section. If you use your own linker script instead, it needs the same
+//! section, for example;
+//!
+//! ``` ignore
+//! SECTIONS {
+//!   .rtc_fast.persistent (NOLOAD) : ALIGN(4)
+//!   {
+//!     *(.rtc_fast.persistent .rtc_fast.persistent.*)
+//!   } > RTC_FAST
+//! }
+//! INSERT AFTER .rtc_fast.bss;
+//! ```
+//!
+//! [`esp-hal`]: https://crates.io/crates/esp-hal
+//!
+//! ### before-reset
+//!
+//! Resetting the device straight out of the panic leaves the outputs driving actuators as they were,
+//! until the firmware initializes them again. This feature calls a function registered at startup from
+//! the panic handler, with interrupts disabled, once the panic was persisted and before the device is
+//! reset (or before the action set with `set_panic_action` is taken), to put the hardware in a safe
+//! state;
+//!
+//! ``` ignore
+//! panic_persist::set_before_reset(|| {
+//!     // Disable the motor PWM, de-energize the relays, close the valves...
+//! });
+//! ```
+//!
+//! It is called before the message is mirrored by the `*-mirror` features, and it isn't called by
+//! `report_panic_info`, which does not reset the device.
+//!
+//! ### before-write
+//!
+//! Some regions can't be written until something is unlocked, such as the write protection of a
+//! backup domain. This feature calls a function registered at startup from the panic handler, with
+//! interrupts disabled, before it touches the panic region, and also from `report_panic_info`,
+//! `persist_message` and `PersistWriter`;
+//!
+//! ``` ignore
+//! panic_persist::set_before_write(|| {
+//!     // Set PWR_CR.DBP, so the backup domain can be written...
+//! });
+//! ```
+//!
+//! Unlike the enable functions of the backup RAM backends, it isn't called before reading the message.
+//!
+//! ### samd5x-bkupram / saml21-bkupram
+//!
+//! This persists panic messages to the 8K backup RAM of the SAMD5x/SAME5x (`samd5x-bkupram`) or
+//! SAML21 (`saml21-bkupram`) instead of a region of the main RAM, so no linker symbols are needed, and
+//! messages survive in the backup power domain, as long as it is powered (from a battery, for
+//! example). If the backup RAM needs to be made accessible first, this is done by a function
+//! registered at startup, and also called before reading the message;
+//!
+//! ``` ignore
+//! panic_persist::set_bkupram_enable(|| {
+//!     // Leave the backup RAM in its active state...
+//! });
+//! ```
+//!
+//! ### stm32f4-bkpsram / stm32h7-bkpsram
+//!
+//! This persists panic messages to the 4K backup SRAM of the STM32F2/F4/F7 (`stm32f4-bkpsram`) or
+//! STM32H7 (`stm32h7-bkpsram`) instead of a region of the main RAM, so no linker symbols are needed,
+//! and messages survive as long as the backup domain is powered. The backup SRAM must be made
+//! accessible before it is written to, which is done by a function registered at startup, and also
+//! called before reading the message;
+//!
+//! ``` ignore
+//! panic_persist::set_bkpsram_enable(|| {
+//!     // Enable the backup SRAM clock, and set PWR_CR.DBP...
+//! });
+//! ```
+//!
+//! ### trustzone
+//!
+//! This is meant for the secure image of an ARMv8-M device with TrustZone. Panic messages
+//! persisted by the secure image are marked as such in the header, so they can't be confused
+//! with the ones of the non-secure image, which uses its own region. The secure image can
+//! retrieve the non-secure panic message with `get_nonsecure_panic_message`, for example from
+//! a non-secure callable function, given the location of the non-secure region in its linker
+//! script:
+//!
+//! ``` ignore
+//! _panic_dump_ns_start = ORIGIN(PANDUMP_NS);
+//! _panic_dump_ns_end   = ORIGIN(PANDUMP_NS) + LENGTH(PANDUMP_NS);
+//! ```
+//!
+//! The message is copied out of the non-secure region and checked against its bounds, so the
+//! non-secure image can't corrupt the secure image through it.
+//!
+//! ### nrf52-gpregret
+//!
+//! This sets a configurable bit pattern in the `GPREGRET` and `GPREGRET2` retained registers of
+//! the nRF52 when a panic is persisted, so a bootloader can detect that the previous boot panicked
+//! without knowing the layout of the panic region. The bits are configured at runtime;
+//!
+//! ``` ignore
+//! // Set bit 7 of GPREGRET2 after a panic
+//! panic_persist::set_gpregret_bits(0x00, 0x80);
+//! ```
+//!
+//! ### nrf52-retention
+//!
+//! On the nRF52, RAM sections lose their content in System OFF unless their retention is enabled.
+//! This feature adds `nrf52_retain_region`, which finds the RAM sections holding the panic region
+//! from the linker symbols, and enables their retention, so panic messages survive System OFF and
+//! the pin reset or wakeup leaving it.
+//!
+//! ### multicore-lock
+//!
+//! When several cores share the same panic region, simultaneous panics would interleave their
+//! messages. This feature reserves a claim word in front of the header, at the start of the region,
+//! which the panicking cores atomically claim while they write their record, so only the first one
+//! writes its message: the others wait for it to be written, then reset the device (or take the
+//! action set with `set_panic_action`) without writing theirs. The claim is released once the record
+//! is written, so a panic on a later boot is persisted as usual, and the random content of the claim
+//! word after a power cycle counts as free. The claim word must be 4 byte aligned, as it is accessed
+//! atomically: in a region that is not, the records are written without claiming it. This needs 32
+//! bit compare and swap support, which the Cortex-M0(+) lacks: see the `rp2040` feature instead.
+//!
+//! ### sticky
+//!
+//! By default, `get_panic_message_bytes` consumes the message, so it is gone on the next boot even if
+//! it could not be handled, such as when the upload failed. With this feature, the message is returned
+//! on every call, on this boot and on the next ones, until it is acknowledged;
+//!
+//! ``` ignore
+//! if let Some(msg) = panic_persist::get_panic_message_bytes() {
+//!     if modem.upload(msg).is_ok() {
+//!         panic_persist::acknowledge_panic_message();
+//!     }
+//! }
+//! ```
+//!
+//! A later panic still overwrites the message, unless the `multicore-lock` feature is enabled: the
+//! claim is then only released once the message is acknowledged. The messages of a `PanicRegion`, a
+//! named region or the region of another image are still consumed when they are read.
+//!
+//! ### dedupe
+//!
+//! A firmware stuck in a crash loop panics at the same place again and again. With this feature, a
+//! panic at the same location as the record held by the region, which was not read yet, is not
+//! persisted again: the panic handler only counts one more repeat in the header, and the
+//! `occurrences` method of the records tells how many times the panic happened;
+//!
+//! ``` ignore
+//! if let Some(record) = unsafe { panic_persist::read_record_at(start, len) } {
+//!     defmt::info!("this panic happened {} times", record.occurrences());
+//! }
+//! ```
+//!
+//! The count saturates at 256 occurrences. Only the record of the RAM region is deduplicated, the
+//! other sinks persist every panic.
+//!
+//! ### panic-ring
+//!
+//! A device may panic several times before its records are read, and by default only the last panic
+//! is kept, while the first one is often the root cause. With this feature, the region is split into a
+//! ring of 4 slots, each holding a record, so the last 4 panics are kept: each panic is persisted in the
+//! slot after the newest record, overwriting the oldest one once the ring is full. Each record stores
+//! its sequence number in an extra field, and they are retrieved oldest first, such as with
+//! `panic_records`;
+//!
+//! ``` ignore
+//! for record in panic_persist::panic_records() {
+//!     board.uart.write(record.message());
+//! }
+//! ```
+//!
+//! Each slot takes a quarter of the region, which bounds the length of the messages, as returned by
+//! `check_region`. With the `dedupe` feature, a panic is compared to the newest record. This can't be
+//! used with the `rp2040` feature, which splits the region between the cores.
+//!
+//! ### panic-counter
+//!
+//! This feature reserves a counter block of 16 bytes in front of the header, at the start of the
+//! region (after the claim word of the `multicore-lock` feature), which the panic handler
+//! increments every time it persists a panic. Unlike the panic message, the count is not cleared
+//! when it is read, so `panic_count` tells how many panics happened since the count was last reset
+//! with `reset_panic_count`, or since the region lost power;
+//!
+//! ``` ignore
+//! if panic_persist::panic_count() > 10 {
+//!     defmt::warn!("this device panics a lot");
+//!     panic_persist::reset_panic_count();
+//! }
+//! ```
+//!
+//! It also counts the panics that happened in a row, until the firmware reports that it reached a
+//! healthy state with `mark_boot_ok`, so a firmware that keeps panicking on boot can detect it, and
+//! enter a safe mode instead of looping forever;
+//!
+//! ``` ignore
+//! if panic_persist::is_boot_looping(3) {
+//!     recovery_mode();
+//! }
+//!
+//! // ... once the application has been running for a while
+//! panic_persist::mark_boot_ok();
+//! ```
+//!
+//! ### watermark
+//!
+//! This feature reserves a usage block of 24 bytes in front of the header (after the ring of the
+//! `log` feature), where the panic handler keeps how many bytes of the region the last record used,
+//! the most bytes a record used, and whether a message was ever truncated, as it did not fit. Unlike
+//! the panic message, the usage is not cleared when it is read, so `region_usage` helps sizing the
+//! region, until it is reset with `reset_region_usage`;
+//!
+//! ``` ignore
+//! let usage = panic_persist::region_usage();
+//!
+//! defmt::info!("records used up to {} of {} bytes", usage.high_water, usage.capacity);
+//! if usage.truncated {
+//!     defmt::warn!("a panic message did not fit in the region");
+//! }
+//! ```
+//!
+//! ### kv-store
+//!
+//! This feature reserves a key-value store of 100 bytes in front of the header (after the counter
+//! block of the `panic-counter` feature), where `persist_kv` stores up to 8 values, such as the
+//! battery level, the temperature or a sequence number. The panic handler freezes the store, so the
+//! values it held at the time of the panic are returned by `get_panic_kv` on the next boot, until the
+//! store is cleared with `clear_panic_kv`, which lets `persist_kv` store new values again;
+//!
+//! ``` ignore
+//! const BATTERY: u8 = 0;
+//!
+//! if let Some(level) = panic_persist::get_panic_kv(BATTERY) {
+//!     defmt::info!("the battery was at {}% when the firmware panicked", level);
+//! }
+//! panic_persist::clear_panic_kv();
+//!
+//! // ... later on
+//! panic_persist::persist_kv(BATTERY, battery.level());
+//! ```
+//!
+//! ### breadcrumbs
+//!
+//! This feature reserves a ring of 8 breadcrumbs, of 264 bytes, in front of the header (after the
+//! key-value store of the `kv-store` feature). The `breadcrumb!` macro leaves a short breadcrumb in
+//! the ring, formatted as with `format!` and cut to 31 bytes, so the last ones left before a panic
+//! give a trail of what the firmware did. The panic handler freezes the ring, and
+//! `get_panic_breadcrumbs` returns its breadcrumbs on the next boot, oldest first, until it is cleared
+//! with `clear_panic_breadcrumbs`;
+//!
+//! ``` ignore
+//! for crumb in panic_persist::get_panic_breadcrumbs() {
+//!     defmt::info!("before the panic: {}", crumb);
+//! }
+//! panic_persist::clear_panic_breadcrumbs();
+//!
+//! // ... later on
+//! panic_persist::breadcrumb!("entering dfu");
+//! panic_persist::breadcrumb!("command {}", id);
+//! ```
+//!
+//! ### log
+//!
+//! This feature adds `PanicLogger`, a [`log`] backend keeping the `WARN` and `ERROR` records in a ring
+//! of 8 records, of 520 bytes, in front of the header (after the ring of the `breadcrumbs` feature).
+//! Each record keeps its level and the first 62 bytes of its message, so the last errors logged
+//! before a panic are kept along with the panic record. The panic handler freezes the ring, and
+//! `get_panic_logs` returns its records on the next boot, oldest first, until it is cleared with
+//! `clear_panic_logs`;
+//!
+//! ``` ignore
+//! for (level, text) in panic_persist::get_panic_logs() {
+//!     defmt::info!("before the panic: {} {}", level.as_str(), text);
+//! }
+//! panic_persist::clear_panic_logs();
+//!
+//! log::set_logger(&panic_persist::PanicLogger).ok();
+//! log::set_max_level(log::LevelFilter::Warn);
+//! ```
+//!
+//! To keep another logger, its records can be forwarded to the ring with `persist_log` instead.
+//!
+//! [`log`]: https://crates.io/crates/log
+//!
+//! ### registers
+//!
+//! This feature captures the core registers, `r0` to `r12`, `sp`, `lr`, `pc` and `xPSR`, on entry of
+//! the panic handler (or of `report_panic_info`), and stores them in the record, as returned by the
+//! `registers` method of the records. It is only supported on Cortex-M targets.
+//!
+//! The registers are those of the panic handler, which is called by the panic machinery of `core`:
+//! `r4` to `r11`, which are preserved across calls, mostly hold the values of the code that panicked,
+//! while the scratch registers, `sp`, `lr` and `pc` locate the panic machinery instead;
+//!
+//! ``` ignore
+//! if let Some(registers) = record.registers() {
+//!     defmt::info!("r4: {:#010x}, lr: {:#010x}", registers.r[4], registers.lr);
+//! }
+//! ```
+//!
+//! ### stack-dump
+//!
+//! This feature keeps an area at the end of the panic region (before the scratch area of the
+//! `scratch-area` feature) for a raw dump of the stack of the panic, from the stack pointer on entry
+//! of the panic handler up, so the frames of the code that panicked can be reconstructed on the host
+//! with the ELF file. The number of bytes to dump is given by the `_panic_stack_dump_len` linker
+//! symbol, and the area takes 36 bytes more, for its own header and the stack pointer;
+//!
+//! ``` ignore
+//! _panic_dump_start     = ORIGIN(PANDUMP);
+//! _panic_dump_end       = ORIGIN(PANDUMP) + LENGTH(PANDUMP);
+//! _panic_stack_dump_len = 256;
+//! ```
+//!
+//! The dump stops at the top of the stack, as given by the `_stack_start` symbol of `cortex-m-rt`,
+//! and is retrieved on boot with `get_panic_stack_dump`, which returns the stack pointer along with
+//! the bytes. It is only supported on Cortex-M targets;
+//!
+//! ``` ignore
+//! if let Some(dump) = panic_persist::get_panic_stack_dump() {
+//!     defmt::info!("stack at {:#010x}: {=[u8]:x}", dump.sp(), dump.bytes());
+//! }
+//! ```
+//!
+//! ### backtrace
+//!
+//! This feature walks the chain of frame pointers on entry of the panic handler (or of
+//! `report_panic_info`), and stores up to 16 return addresses in the record, as returned by the
+//! `backtrace` method of the records, so the call stack of the panic can be symbolized on the host
+//! with the ELF file. It is only supported on Cortex-M targets, and the firmware must be built with
+//! frame pointers, or the walk stops early;
+//!
+//! ```text
+//! # .cargo/config.toml
+//! [target.'cfg(all(target_arch = "arm", target_os = "none"))']
+//! rustflags = ["-C", "force-frame-pointers=yes"]
+//! ```
+//!
+//! The walk stops at the top of the stack, as given by the `_stack_start` symbol of `cortex-m-rt`.
+//! The first address returns into the panic machinery of `core`;
+//!
+//! ``` ignore
+//! if let Some(backtrace) = record.backtrace() {
+//!     for frame in backtrace.frames() {
+//!         defmt::info!("{:#010x}", frame);
+//!     }
+//! }
+//! ```
+//!
+//! ### stack-pointers
+//!
+//! This feature reads the main and process stack pointers, along with the CONTROL register, in the
+//! panic handler (or in `report_panic_info`), and stores them in the record, as returned by the
+//! `stack_pointers` method of the records. CONTROL tells which of the stacks the panic happened on,
+//! which matters under an RTOS, where the tasks run on the process stack. It is only supported on
+//! Cortex-M targets;
+//!
+//! ``` ignore
+//! if let Some(sp) = record.stack_pointers() {
+//!     match sp.on_process_stack() {
+//!         true => defmt::info!("panicked in a task, psp: {:#010x}", sp.psp),
+//!         false => defmt::info!("panicked on the main stack, msp: {:#010x}", sp.msp),
+//!     }
+//! }
+//! ```
+//!
+//! ### rescue-region
+//!
+//! Some ROM bootloaders (such as the one of the Ambiq Apollo3, or of some NXP parts) wipe the RAM
+//! on reset. This feature keeps a copy of the panic message in a second region, given by the
+//! `_panic_rescue_start` and `_panic_rescue_end` linker symbols, placed in memory that is left
+//! alone (such as a retained RAM bank), and `get_panic_message_bytes` checks both regions. The
+//! copy is made by the panic handler, and can also be made on boot with `rescue_panic_message`, when
+//! the panic region is wiped after the application started (such as by the startup code of a second
+//! stage bootloader), as early as possible;
+//!
+//! ``` ignore
+//! #[pre_init]
+//! unsafe fn rescue() {
+//!     panic_persist::rescue_panic_message();
+//! }
+//! ```
+//!
+//! ### region-symbols
+//!
+//! This feature stores the layout of the records in the `PANIC_PERSIST_RECORD_MAGIC`,
+//! `PANIC_PERSIST_FORMAT_VERSION`, `PANIC_PERSIST_HEADER_LEN`, `PANIC_PERSIST_RECORD_OFFSET` and
+//! `PANIC_PERSIST_SLOTS` symbols of the image, as 32 bit words, as returned by `region_descriptor`,
+//! so a tool can read it from the image. As nothing uses them, the linker script must keep them;
+//!
+//! ``` ignore
+//! .rodata : {
+//!     KEEP(*(.rodata.PANIC_PERSIST_*))
+//! }
+//! ```
+//!
+//! ### rp2040
+//!
+//! This splits the panic region in two halves, one for each core of the RP2040 (or RP2350), so a
+//! panic on one core does not overwrite the message of the other one. The half used at panic time
+//! is selected through `SIO.CPUID`. `get_panic_message_bytes_with_core` returns the message along
+//! with the core that panicked, and `get_core_panic_message_bytes` the message of a given core.
+//!
+//! ### rp2040-scratch
+//!
+//! This additionally stores a compact summary of the panic (a hash of the file name, the line, and
+//! a panic counter) in the first four watchdog scratch registers of the RP2040, which is retrieved
+//! with `get_scratch_summary`. The last four scratch registers are left alone, as the bootrom uses
+//! them. If you can't spare a RAM region, define `_panic_dump_start` and `_panic_dump_end` to the
+//! same address, so only the summary is kept.
+//!
+//! ### esp32-rtc-fast / esp32-rtc-slow
+//!
+//! This places a panic region, of 1K by default, in the RTC fast (`esp32-rtc-fast`) or RTC slow
+//! (`esp32-rtc-slow`) memory of ESP32 family chips instead of using the linker symbols, so
+//! messages also survive deep sleep. The region is a static in the `.rtc_fast.persistent` (or
+//! `.rtc_slow.persistent`) section of the `esp-hal` linker scripts, which is not initialized on
+//! boot, so no linker script changes are needed. The region is accessed (and the message
+//! retrieved) through its data bus address, as usual with the `get_panic_message_*` functions. Not
+//! all chips have RTC slow memory.
+//!
+//! The length of the region is set in bytes with the `PANIC_PERSIST_RTC_LEN` environment variable
+//! when building, such as in the `[env]` section of `.cargo/config.toml`. It must be a multiple of
+//! 4 bytes, able to hold the header and the location, and fit in the RTC memory along with what
+//! the application places there;
+//!
+//! ``` ignore
+//! [env]
+//! PANIC_PERSIST_RTC_LEN = "4096"
+//! ```
+//!
+//! ### uncached-alias
+//!
+//! On cores with a data cache (such as the Cortex-M7 of the i.MX RT), the panic message may still
+//! be in the cache, rather than in the RAM, when the device is reset. If the panic region can also
+//! be accessed through a non-cacheable alias address, this feature makes all accesses to the region
+//! go through the alias given by the `_panic_dump_alias` linker symbol, while `_panic_dump_start`
+//! and `_panic_dump_end` still define the region (and its length);
+//!
+//! ``` ignore
+//! _panic_dump_alias = ORIGIN(PANDUMP) + 0x10000000;
+//! ```
+//!
+//! ### mpu-guard
+//!
+//! On Cortex-M targets, this feature provides `enable_mpu_guard`, which programs an MPU region over
+//! the panic region, so it can't be accessed during normal operation: stray pointer bugs fault
+//! instead of corrupting, or faking, a panic record. The panic handler disables the MPU region
+//! before writing to the panic region. `report_panic_info`, `persist_message` and `PersistWriter`
+//! disable it as well, and enable it again once the message was written. It must be enabled once
+//! the panic message of the last boot was retrieved;
+//!
+//! ``` ignore
+//! if let Some(msg) = panic_persist::get_panic_message_bytes() {
+//!     board.uart.write(msg);
+//! }
+//!
+//! panic_persist::enable_mpu_guard(&mut core_peripherals.MPU, 7).unwrap();
+//! ```
+//!
+//! On ARMv6-M and ARMv7-M, the length of the panic region must be a power of two, and its start must
+//! be aligned to its length, and it can't be accessed at all. On ARMv8-M, both must be multiples of 32
+//! bytes, and it stays readable by privileged code, as there is no such access permission. With the
+//! `scratch-area` feature, the scratch area is left accessible, and so are the blocks at the front
+//! of the region, which are written during normal operation by the `kv-store`, `breadcrumbs`,
+//! `log`, `panic-counter` and `watermark` features: the guarded part starts after them, so it is
+//! this part that must be aligned.
+//!
+//! ### dcache-clean
+//!
+//! On Cortex-M7 cores with the data cache enabled, this cleans the data cache over the panic region
+//! once the panic is persisted, followed by DSB and ISB barriers, so the message reaches the RAM
+//! before the device is reset rather than being lost in the cache.
+//!
+//! ### aligned-writes
+//!
+//! Some memories, such as ECC protected RAMs, fault or silently corrupt data on writes smaller than
+//! 32 bits. With this feature, the message is buffered so only aligned 32 bit writes are issued to
+//! the panic region, which must then start on a 4 byte boundary. The header is written once the
+//! whole message is.
+//!
+//! ### sim
+//!
+//! When running under a simulator such as QEMU or Renode, for example in integration tests, a soft
+//! reset may lose the content of the emulated RAM or hang the test harness. With this feature, the
+//! panic handler ends the program with a semihosting exit (on ARM and RISC-V), reporting an error,
+//! instead of resetting the device (which is still done on other architectures, or if semihosting is
+//! not supported). On Cortex-M, the semihosting exit is only used when the `C_DEBUGEN` bit of `DHCSR`
+//! reports a debugger, as it would fault otherwise. The harness can then run the binary again with the
+//! same RAM and check the persisted message. The simulation can also be ended in another way, with a function
+//! registered at startup;
+//!
+//! ``` ignore
+//! panic_persist::set_sim_exit(|| {
+//!     // Write to the test device of the simulator...
+//! });
+//! ```
+//!
+//! ### nor-flash
+//!
+//! On chips where no RAM survives a reset, the panic message can be persisted to a reserved area of
+//! the internal flash (or any other flash implementing the [`embedded-storage`] `NorFlash` trait)
+//! instead. The `FlashSink` buffers a message of up to `N` bytes, and writes it to the flash once it is
+//! complete, erasing the area first if it still holds an older message. The sink is registered at
+//! startup, and the message is retrieved on boot with `get_flash_panic_message`, which erases the area
+//! again;
+//!
+//! ``` ignore
+//! static SINK: StaticCell<FlashSink<Flash, 1024>> = StaticCell::new();
+//!
+//! let mut flash = Flash::new(/* ... */);
+//!
+//! let mut buf = [0; 1024];
+//! if let Some(msg) = panic_persist::get_flash_panic_message(&mut flash, PANIC_PAGE, &mut buf) {
+//!     board.uart.write(msg);
+//! }
+//!
+//! panic_persist::set_panic_sink(SINK.init(FlashSink::new(flash, PANIC_PAGE)));
+//! ```
+//!
+//! So that repeated panics do not wear out the same flash cells, the messages can also be written
+//! to several slots in turn, each made of one or more erase pages, with `FlashSink::with_slots`. Each
+//! message gets a sequence number, and the next one goes to the slot after the last message. Older
+//! messages stay readable, with `get_flash_panic_message_in_slot`, until they are overwritten;
+//!
+//! ``` ignore
+//! // 4 slots of 2K, for the 1K records of the sink above
+//! const SLOTS: FlashSlots = FlashSlots::new(0x7_8000, 2048, 4);
+//!
+//! for slot in 0..4 {
+//!     if let Some((sequence, msg)) =
+//!         panic_persist::get_flash_panic_message_in_slot(&mut flash, SLOTS, slot, &mut buf)
+//!     {
+//!         // ...
+//!     }
+//! }
+//!
+//! panic_persist::set_panic_sink(SINK.init(FlashSink::with_slots(flash, SLOTS)));
+//! ```
+//!
+//! The message does not include the location of the panic, which is stored apart from it.
+//! `get_flash_panic_record` and `get_flash_panic_record_in_slot` return the whole `Record`, with the
+//! location and the extra fields, instead of its message, as long as the record fits in `buf`.
+//!
+//! [`embedded-storage`]: https://crates.io/crates/embedded-storage
+//!
+//! ### spi-nor
+//!
+//! This adds `SpiNorFlash`, a `NorFlash` driver for external SPI NOR flashes (such as the W25Q series)
+//! on an [`embedded-hal`] `SpiDevice`, so panic messages can be persisted to a reserved sector of an
+//! external flash with the `FlashSink` of the `nor-flash` feature, which this feature enables;
+//!
+//! ``` ignore
+//! static SINK: StaticCell<FlashSink<SpiNorFlash<Spi>, 1024>> = StaticCell::new();
+//!
+//! // A 16M W25Q128, with the panic message in its last sector
+//! let flash = SpiNorFlash::new(spi, 16 * 1024 * 1024);
+//! panic_persist::set_panic_sink(SINK.init(FlashSink::new(flash, 0xFF_F000)));
+//! ```
+//!
+//! The flash is written from the panic handler, so the `SpiDevice` must work with interrupts
+//! disabled.
+//!
+//! [`embedded-hal`]: https://crates.io/crates/embedded-hal
+//!
+//! ### storage
+//!
+//! This adds `StorageSink`, which persists panic messages to a reserved area of any byte addressable
+//! memory implementing the [`embedded-storage`] `Storage` trait, with the same header as in the RAM
+//! region. The message is written as it comes, and the header last, so a message that was only partly
+//! written is not mistaken for a complete one. It is retrieved on boot with
+//! `get_storage_panic_message`, or along with the location of the panic and the extra fields with
+//! `get_storage_panic_record`.
+//!
+//! ### eeprom
+//!
+//! This adds `I2cEeprom`, a `Storage` driver for I2C EEPROMs (such as the 24LCxx series) on an
+//! [`embedded-hal`] `I2c`, which splits writes into pages and waits for each of them to complete, for
+//! use with the `StorageSink` of the `storage` feature, which this feature enables. This is useful on
+//! devices where the RAM loses its content on every reset;
+//!
+//! ``` ignore
+//! static SINK: StaticCell<StorageSink<I2cEeprom<I2c>>> = StaticCell::new();
+//!
+//! // A 24LC256 (32K, 64 byte pages), with the panic message in its first 1K
+//! let mut eeprom = I2cEeprom::new(i2c, 0x50, 32 * 1024, 64);
+//!
+//! let mut buf = [0; 1016];
+//! if let Some(msg) = panic_persist::get_storage_panic_message(&mut eeprom, 0, &mut buf) {
+//!     board.uart.write(msg);
+//! }
+//!
+//! panic_persist::set_panic_sink(SINK.init(StorageSink::new(eeprom, 0, 1024)));
+//! ```
+//!
+//! ### fram
+//!
+//! This adds `SpiFram` and `I2cFram`, `Storage` drivers for SPI (such as the MB85RS series) and I2C
+//! (such as the MB85RC series) FRAMs, for use with the `StorageSink` of the `storage` feature, which
+//! this feature enables. FRAMs are written byte by byte, without erasing or waiting, and with an almost
+//! unlimited endurance, which makes them a good fit for panic messages. The header is the same as in
+//! the RAM region, and the message is retrieved on boot with `get_storage_panic_message`;
+//!
+//! ``` ignore
+//! static SINK: StaticCell<StorageSink<SpiFram<Spi>>> = StaticCell::new();
+//!
+//! // A MB85RS64 (8K), with the panic message in its first 1K
+//! let fram = SpiFram::new(spi, 8 * 1024);
+//! panic_persist::set_panic_sink(SINK.init(StorageSink::new(fram, 0, 1024)));
+//! ```
+//!
+//! ### sequential-storage
+//!
+//! This adds `QueueSink`, which appends panic messages to a [`sequential-storage`] queue in flash,
+//! keeping a history of the last panics across power cycles, with the wear leveling of the queue.
+//! The oldest messages are dropped once the queue is full. Each message starts with the location of
+//! the panic, as the default panic message does. The history is read on boot with the queue itself,
+//! before handing it over to the sink;
+//!
+//! ``` ignore
+//! static SINK: StaticCell<QueueSink<Flash, NoCache, 256>> = StaticCell::new();
+//!
+//! let mut queue = QueueStorage::new(flash, QueueConfig::new(0x7_0000..0x8_0000), NoCache::new());
+//!
+//! let mut buf = [0; 256];
+//! while let Ok(Some(msg)) = queue.pop(&mut buf).await {
+//!     board.uart.write(msg);
+//! }
+//!
+//! panic_persist::set_panic_sink(SINK.init(QueueSink::new(queue)));
+//! ```
+//!
+//! The flash is written from the panic handler, by polling the futures of the queue until they
+//! complete, so the flash driver must work with interrupts disabled.
+//!
+//! [`sequential-storage`]: https://crates.io/crates/sequential-storage
+//!
+//! ### runtime-region
+//!
+//! When the linker script can't be edited (vendor provided linker scripts, or builds generating
+//! them), this feature replaces the linker symbols with a buffer handed to the crate at startup with
+//! `init_with_region`, which all the functions then use. The buffer must be a static that is not
+//! initialized on boot, such as one in a `.uninit` section, and the same one must be given on every
+//! boot, before the panic message is retrieved. Panics happening before that are not persisted;
+//!
+//! ``` ignore
+//! #[link_section = ".uninit.PANIC_REGION"]
+//! static mut PANIC_REGION: [MaybeUninit<u8>; 1024] = [MaybeUninit::uninit(); 1024];
+//!
+//! panic_persist::init_with_region(unsafe { &mut *core::ptr::addr_of_mut!(PANIC_REGION) });
+//!
+//! if let Some(msg) = panic_persist::get_panic_message_bytes() {
+//!     board.uart.write(msg);
+//! }
+//! ```
+//!
+//! This feature also works on hosted targets, where it can be used to test the code retrieving the
+//! messages.
+//!
+//! ### scratch-area
+//!
+//! This feature keeps the end of the panic region for the application, so the space the panic
+//! messages don't need can hold a few flags that survive resets, without a second retained region.
+//! The length of the scratch area is given by the `_panic_scratch_len` linker symbol, and the panic
+//! messages are kept to the rest of the region;
+//!
+//! ``` ignore
+//! _panic_dump_start  = ORIGIN(PANDUMP);
+//! _panic_dump_end    = ORIGIN(PANDUMP) + LENGTH(PANDUMP);
+//! _panic_scratch_len = 64;
+//! ```
+//!
+//! The scratch area is accessed with `read_scratch` and `write_scratch`, which copy bytes from and to
+//! an offset of the area, and are bounded by its length, as given by `get_scratch_len`;
+//!
+//! ``` ignore
+//! let mut boots = [0; 4];
+//! panic_persist::read_scratch(0, &mut boots);
+//!
+//! let boots = u32::from_le_bytes(boots).wrapping_add(1);
+//! panic_persist::write_scratch(0, &boots.to_le_bytes());
+//! ```
+//!
+//! The scratch area is placed in the memory of the panic region whichever backend holds it, but there
+//! is no scratch area on hosted targets.
+//!
+//! ### embedded-io
+//!
+//! This feature provides `IoSink`, writing the panic message to any `embedded_io::Write` writer (a
+//! UART, a USB CDC class, a logger...) with blocking, best effort writes. It can be registered as the
+//! sink of the panic handler, alone or alongside the `RamSink` in a `MultiSink`, and writes the
+//! location of the panic in front of the message, as the default panic message does. On boot,
+//! `drain_panic_message` writes the persisted message, if any, to such a writer;
+//!
+//! ``` ignore
+//! if panic_persist::drain_panic_message(&mut board.uart).unwrap_or(false) {
+//!     // There was a panic message
+//! }
+//! ```
+//!
+//! ### serial-mirror
+//!
+//! When a console is wired but the device is never cleanly rebooted to read the persisted message,
+//! this feature also writes the message to an `embedded-io` writer, such as a UART, registered at
+//! startup. It is written once the message was persisted, before the device is reset, with blocking
+//! writes from panic context. The output is best effort, so errors are ignored, but a writer that
+//! never completes prevents the reset;
+//!
+//! ``` ignore
+//! static UART: StaticCell<Uart> = StaticCell::new();
+//!
+//! panic_persist::set_serial_mirror(UART.init(board.uart));
+//! ```
+//!
+//! With the `custom-panic-handler` feature, the message is written to it by `report_panic_info`.
+//!
+//! ### rtt-mirror
+//!
+//! This feature also writes the panic message to the print channel of `rtt-target`, once it was
+//! persisted and before the device is reset, so the message is shown right away when a probe is
+//! attached, while the persisted copy is still there when it is not. The channel must have been set
+//! up with `rtt_init_print!` (or `set_print_channel`), otherwise nothing is written. It is switched
+//! to non blocking mode, so the device is still reset when no probe reads it, at the cost of trimming
+//! the message if the channel is full.
+//!
+//! With the `custom-panic-handler` feature, the message is written to it by `report_panic_info`.
+//!
+//! ### semihosting-mirror
+//!
+//! For debug builds running under a debugger, this feature also writes the panic message to the
+//! semihosting console (on ARM and RISC-V), once it was persisted and before the device is reset, so
+//! the `openocd` or `probe-rs` console shows it right away. Semihosting calls fault when no debugger
+//! is attached. On Cortex-M, the message is not written when the `C_DEBUGEN` bit of `DHCSR` reports no
+//! debugger, but on the other cores this feature must not be enabled for the units in the field,
+//! which keep relying on the persisted copy.
+//!
+//! With the `custom-panic-handler` feature, the message is written to it by `report_panic_info`.
+//!
+//! ### defmt
+//!
+//! This feature also logs the panic message with `defmt::error!`, once it was persisted and before the
+//! device is reset, and then flushes the `defmt` transport, so the message is seen live when the
+//! transport is read, while the persisted copy is still there after the reset.
+//!
+//! With the `custom-panic-handler` feature, the message is logged by `report_panic_info`.
+//!
+//! ### defmt-payload
+//!
+//! With this feature, the message of a `defmt::panic!` (or of `defmt::unwrap!`, `defmt::assert!`,
+//! ...) is persisted as the `defmt` frame that logged it, with its interned format string and encoded
+//! arguments, rather than as rendered text. It is far smaller, so even a small region holds a rich
+//! message, and it is decoded on the host by the usual `defmt` tools, with the ELF file. The records
+//! holding a frame are told apart by their `is_defmt` method.
+//!
+//! The frame is kept as it is sent on the wire by the global logger, which must hand its bytes over with
+//! `defmt_frame_start` and `defmt_frame_write`, and `defmt_panic` must be called from the panic handler
+//! of `defmt`. Frames longer than 256 bytes, and the messages of other panics, are persisted as text;
+//!
+//! ``` ignore
+//! #[defmt::global_logger]
+//! struct Logger;
+//!
+//! unsafe impl defmt::Logger for Logger {
+//!     fn acquire() {
+//!         // ...
+//!         panic_persist::defmt_frame_start();
+//!         ENCODER.start_frame(|bytes| {
+//!             transport_write(bytes);
+//!             panic_persist::defmt_frame_write(bytes);
+//!         });
+//!     }
+//!
+//!     // ... and the same in `write` and `release`
+//! }
+//!
+//! #[defmt::panic_handler]
+//! fn defmt_panic() -> ! {
+//!     panic_persist::defmt_panic()
+//! }
+//! ```
+//!
+//! ### postcard
+//!
+//! With this feature, the message of the record is a `PanicReport`, serialized with [`postcard`],
+//! rather than the rendered text. The report holds the location of the panic, the message, and the
+//! metadata of the record, so host tools deserialize the same type as the firmware, instead of parsing
+//! free form text. It takes at most 256 bytes, the message being truncated to fit.
+//!
+//! The report is retrieved on boot with `get_panic_report`, or from a record with its `report` method,
+//! and the host deserializes it from the bytes of the message, as returned by
+//! `get_panic_message_bytes`;
+//!
+//! ``` ignore
+//! if let Some(report) = panic_persist::get_panic_report() {
+//!     defmt::info!("{}:{}: {}", report.file, report.line, report.message);
+//! }
+//!
+//! // On the host
+//! let report = PanicReport::from_bytes(&message).unwrap();
+//! ```
+//!
+//! [`postcard`]: https://docs.rs/postcard
+//!
+//! ### serde
+//!
+//! With this feature, `Record` implements `serde::Serialize`, as a struct of its fields named after
+//! the methods returning them, so the record is emitted as JSON, CBOR or `postcard` by the firmware
+//! or by host tools without describing it again. The message is serialized as text, with its invalid
+//! UTF-8 sequences replaced, and the fields of the disabled features are left out. `PanicLocation`,
+//! `HeapStats`, `Registers`, `Backtrace` and `StackPointers` implement it as well;
+//!
+//! ``` ignore
+//! if let Some(record) = panic_persist::get_panic_record() {
+//!     let json: heapless::String<512> = serde_json_core::to_string(&record).unwrap();
+//!     uplink(json.as_bytes());
+//! }
+//! ```
+//!
+//! ### zeroize
+//!
+//! The panic text and the context of the application can hold user data, which lingers in the region
+//! until a later panic overwrites it. With this feature, the functions copying the message out of the
+//! region, `get_panic_message_into`, `get_panic_message_utf8_lossy`, `drain_panic_message` and the
+//! ones of the `heapless` feature, overwrite the whole record with zeroes once it was copied, and
+//! `clear_panic_message` wipes the record as `wipe_panic_message` does. The functions borrowing the
+//! message from the region can't wipe it while it is borrowed, so `wipe_panic_message` must be called
+//! once it was handled.
+//!
+//! It also adds `set_panic_context_sensitive`, which marks the context registered with
+//! `set_panic_context` as sensitive, so the panic handler leaves it out of the record;
+//!
+//! ``` ignore
+//! panic_persist::set_panic_context(session.user_id());
+//! panic_persist::set_panic_context_sensitive(true);
+//! ```
+//!
+//! ### keep-tail
+//!
+//! When the message does not fit in the region, only its start is kept by default. The end of a long
+//! formatted panic is often the most useful part, such as the text of a failed assertion after the
+//! boilerplate, so with this feature the end of the message is kept instead, as much of it as the
+//! region holds after the location and the extra fields. The start of a character that was cut is
+//! dropped, so the message remains valid UTF-8, and the record is still flagged as truncated.
+//!
+//! Only the RAM region keeps the end of the message, the other sinks keep its start.
+//!
 //! ### min-panic
 //!
 //! This prints a smaller, line-number-only message, in order to reduce space needed when persisting panics, at a loss of some context.
 
 #![allow(clippy::empty_loop)]
+// The panic handler is left out on hosted targets, along with the code only it uses
+#![cfg_attr(not(target_os = "none"), allow(dead_code))]
 #![deny(missing_docs)]
 #![deny(warnings)]
 #![no_std]
 
-use core::cmp::min;
 use core::fmt::Write;
-use core::mem::size_of;
+#[cfg(any(feature = "custom-panic-handler", target_os = "none"))]
 use core::panic::PanicInfo;
 
-struct Ram {
-    offset: usize,
-}
+mod action;
+#[cfg(all(not(feature = "custom-panic-handler"), target_os = "none"))]
+mod arch;
+#[cfg(feature = "backtrace")]
+mod backtrace;
+#[cfg(any(
+    feature = "before-reset",
+    feature = "before-write",
+    feature = "samd5x-bkupram",
+    feature = "saml21-bkupram",
+    feature = "sim",
+    feature = "stm32f4-bkpsram",
+    feature = "stm32h7-bkpsram"
+))]
+mod hook;
+mod ram;
+mod region;
+#[cfg(feature = "before-reset")]
+mod before_reset;
+#[cfg(feature = "before-write")]
+mod before_write;
+#[cfg(feature = "breadcrumbs")]
+mod breadcrumbs;
+mod check;
+mod core_id;
+#[cfg(feature = "panic-counter")]
+mod counter;
+mod crc;
+#[cfg(feature = "dcache-clean")]
+mod dcache;
+#[cfg(feature = "dedupe")]
+mod dedupe;
+#[cfg(feature = "defmt")]
+mod defmt_log;
+#[cfg(feature = "defmt-payload")]
+mod defmt_payload;
+mod descriptor;
+#[cfg(feature = "eeprom")]
+mod eeprom;
+#[cfg(any(feature = "esp32-rtc-fast", feature = "esp32-rtc-slow"))]
+mod esp;
+#[cfg(feature = "ffi")]
+mod ffi;
+mod fields;
+#[cfg(feature = "nor-flash")]
+mod flash;
+#[cfg(feature = "fram")]
+mod fram;
+mod header;
+mod heap;
+mod location;
+mod metadata;
+#[cfg(feature = "embedded-io")]
+mod io;
+#[cfg(feature = "kv-store")]
+mod kv;
+#[cfg(feature = "log")]
+mod logs;
+#[cfg(feature = "multicore-lock")]
+mod lock;
+#[cfg(feature = "serial-mirror")]
+mod mirror;
+#[cfg(all(feature = "mpu-guard", cortex_m))]
+mod mpu;
+mod named;
+#[cfg(any(feature = "nrf52-gpregret", feature = "nrf52-retention"))]
+mod nrf52;
+#[cfg(feature = "heapless")]
+mod owned;
+mod panic_region;
+#[cfg(feature = "sequential-storage")]
+mod queue;
+mod record;
+#[cfg(feature = "registers")]
+mod registers;
+#[cfg(feature = "postcard")]
+mod report;
+#[cfg(feature = "rescue-region")]
+mod rescue;
+mod reset;
+#[cfg(feature = "panic-ring")]
+mod ring;
+#[cfg(feature = "rtt-mirror")]
+mod rtt;
+#[cfg(any(feature = "rp2040", feature = "rp2040-scratch"))]
+mod rp2040;
+#[cfg(any(feature = "samd5x-bkupram", feature = "saml21-bkupram"))]
+mod sam;
+#[cfg(feature = "scratch-area")]
+mod scratch;
+mod self_test;
+#[cfg(any(
+    feature = "semihosting-mirror",
+    all(feature = "sim", not(feature = "custom-panic-handler"))
+))]
+mod semihosting;
+#[cfg(feature = "sim")]
+mod sim;
+mod sink;
+#[cfg(feature = "spi-nor")]
+mod spi_nor;
+#[cfg(feature = "stack-dump")]
+mod stack;
+#[cfg(feature = "stack-pointers")]
+mod stack_pointers;
+#[cfg(any(feature = "stm32f4-bkpsram", feature = "stm32h7-bkpsram"))]
+mod stm32;
+#[cfg(feature = "storage")]
+mod storage;
+#[cfg(feature = "watermark")]
+mod watermark;
+mod writer;
 
-/// Internal Write implementation to output the formatted panic string into RAM
-impl core::fmt::Write for Ram {
-    fn write_str(&mut self, s: &str) -> Result<(), core::fmt::Error> {
-        // Obtain panic region start and end from linker symbol _panic_dump_start and _panic_dump_end
-        extern "C" {
-            static mut _panic_dump_start: u8;
-            static mut _panic_dump_end: u8;
-        }
+use crate::header::{Header, FORMAT_VERSION};
+use crate::location::Payload;
+use crate::region::Region;
+use crate::sink::Formatter;
 
-        // Get the data about the string that is being written now
-        let data = s.as_bytes();
-        let len = data.len();
+pub use crate::action::{set_panic_action, PanicAction};
+#[cfg(feature = "backtrace")]
+pub use crate::backtrace::Backtrace;
+pub use crate::check::{check_region, validate_region, RegionError, RegionInfo, RegionState};
+pub use crate::core_id::set_core_id_source;
+pub use crate::descriptor::{region_descriptor, RegionDescriptor};
+pub use crate::fields::{set_build_id, set_panic_context, set_time_source};
+pub use crate::heap::{set_heap_stats_source, HeapStats};
+pub use crate::location::PanicLocation;
+pub use crate::metadata::{get_panic_message_len, record_metadata, RecordMetadata};
+pub use crate::named::NamedRegion;
+pub use crate::panic_region::PanicRegion;
+pub use crate::ram::RamSink;
+pub use crate::record::{
+    decode_record, get_panic_record, panic_records, read_record_at, DecodeError, PanicRecords,
+    Record,
+};
+pub use crate::reset::{diagnose_reset, ResetCause, ResetDiagnosis};
+#[cfg(feature = "runtime-region")]
+pub use crate::region::init_with_region;
+pub use crate::self_test::{persistence_self_test, start_persistence_self_test, SelfTest};
+pub use crate::sink::{set_panic_sink, MultiSink, PanicSink, WriteStatus};
+pub use crate::writer::PersistWriter;
 
-        // Obtain info about the panic dump region
-        let start_ptr = unsafe { &mut _panic_dump_start as *mut u8 };
-        let end_ptr = unsafe { &mut _panic_dump_end as *mut u8 };
-        let max_len = end_ptr as usize - start_ptr as usize;
-        let max_len_str = max_len - size_of::<usize>() - size_of::<usize>();
+#[cfg(feature = "before-reset")]
+pub use crate::before_reset::set_before_reset;
+#[cfg(feature = "before-write")]
+pub use crate::before_write::set_before_write;
+#[cfg(feature = "breadcrumbs")]
+pub use crate::breadcrumbs::{
+    clear_panic_breadcrumbs, get_panic_breadcrumbs, leave_breadcrumb, Breadcrumbs,
+};
+#[cfg(feature = "panic-counter")]
+pub use crate::counter::{
+    consecutive_panics, is_boot_looping, mark_boot_ok, panic_count, reset_panic_count,
+};
+#[cfg(feature = "defmt-payload")]
+pub use crate::defmt_payload::{defmt_frame_start, defmt_frame_write, defmt_panic};
+#[cfg(feature = "eeprom")]
+pub use crate::eeprom::{EepromError, I2cEeprom};
+#[cfg(feature = "nor-flash")]
+pub use crate::flash::{
+    get_flash_panic_message, get_flash_panic_message_in_slot, get_flash_panic_record,
+    get_flash_panic_record_in_slot, FlashSink, FlashSlots,
+};
+#[cfg(feature = "fram")]
+pub use crate::fram::{FramError, I2cFram, SpiFram};
+#[cfg(feature = "embedded-io")]
+pub use crate::io::{drain_panic_message, IoSink};
+#[cfg(feature = "kv-store")]
+pub use crate::kv::{clear_panic_kv, get_panic_kv, persist_kv};
+#[cfg(feature = "log")]
+pub use crate::logs::{clear_panic_logs, get_panic_logs, persist_log, PanicLogger, PanicLogs};
+#[cfg(feature = "serial-mirror")]
+pub use crate::mirror::set_serial_mirror;
+#[cfg(all(feature = "mpu-guard", cortex_m))]
+pub use crate::mpu::{enable_mpu_guard, MpuGuardError};
+#[cfg(feature = "nrf52-gpregret")]
+pub use crate::nrf52::set_gpregret_bits;
+#[cfg(feature = "nrf52-retention")]
+pub use crate::nrf52::nrf52_retain_region;
+#[cfg(all(feature = "heapless", feature = "utf8"))]
+pub use crate::owned::get_panic_message_string;
+#[cfg(feature = "heapless")]
+pub use crate::owned::get_panic_message_vec;
+#[cfg(feature = "sequential-storage")]
+pub use crate::queue::QueueSink;
+#[cfg(feature = "registers")]
+pub use crate::registers::Registers;
+#[cfg(feature = "postcard")]
+pub use crate::report::{get_panic_report, PanicReport};
+#[cfg(feature = "rescue-region")]
+pub use crate::rescue::rescue_panic_message;
+#[cfg(feature = "rp2040-scratch")]
+pub use crate::rp2040::{get_scratch_summary, ScratchSummary};
+#[cfg(any(feature = "samd5x-bkupram", feature = "saml21-bkupram"))]
+pub use crate::sam::set_bkupram_enable;
+#[cfg(feature = "scratch-area")]
+pub use crate::scratch::{get_scratch_len, read_scratch, write_scratch};
+#[cfg(feature = "sim")]
+pub use crate::sim::set_sim_exit;
+#[cfg(feature = "spi-nor")]
+pub use crate::spi_nor::{SpiNorError, SpiNorFlash};
+#[cfg(feature = "stack-dump")]
+pub use crate::stack::{get_panic_stack_dump, StackDump};
+#[cfg(feature = "stack-pointers")]
+pub use crate::stack_pointers::StackPointers;
+#[cfg(any(feature = "stm32f4-bkpsram", feature = "stm32h7-bkpsram"))]
+pub use crate::stm32::set_bkpsram_enable;
+#[cfg(feature = "storage")]
+pub use crate::storage::{get_storage_panic_message, get_storage_panic_record, StorageSink};
+#[cfg(feature = "watermark")]
+pub use crate::watermark::{region_usage, reset_region_usage, RegionUsage};
+#[cfg(feature = "zeroize")]
+pub use crate::fields::set_panic_context_sensitive;
 
-        // If we have written the full length of the region, we can't write any
-        // more. This could happen with multiple writes with this implementation
-        if self.offset >= max_len_str {
-            return Ok(());
-        }
+#[cfg(all(feature = "mpu-guard", target_os = "none", not(cortex_m)))]
+compile_error!("The `mpu-guard` feature is only supported on Cortex-M targets");
 
-        // We should write the size of the string, or the amount of space
-        // we have remaining, whichever is less
-        let str_len = min(max_len_str - self.offset, len);
+/// Magic word at the start of the region, marking a persisted panic message
+const MAGIC: u32 = 0x0FACADE0;
 
-        unsafe {
-            // Write the magic word for later detection
-            start_ptr.cast::<usize>().write_unaligned(0x0FACADE0);
+/// Magic word marking a panic message persisted by a TrustZone secure image
+const SECURE_MAGIC: u32 = 0x5FACADE0;
 
-            // For now, skip writing the length...
+/// Magic word used for the messages persisted (and read back) by this image
+#[cfg(not(feature = "trustzone"))]
+const RECORD_MAGIC: u32 = MAGIC;
+#[cfg(feature = "trustzone")]
+const RECORD_MAGIC: u32 = SECURE_MAGIC;
 
-            // Write the string to RAM
-            core::ptr::copy(
-                data.as_ptr() as *mut u8,
-                start_ptr.offset(8).offset(self.offset as isize),
-                str_len,
-            );
+/// Size of the header in front of the message
+const HEADER_LEN: usize = core::mem::size_of::<Header>();
+
+/// Get the panic message from the last boot, if any.
+/// This method may possibly not return valid UTF-8 if the message
+/// was truncated before the end of a full UTF-8 character. Care must
+/// be taken before treating this as a proper &str.
+///
+/// If a message existed, this function will only return the value once
+/// (subsequent calls will return None), unless the `sticky` feature is
+/// enabled: the message is then returned until `acknowledge_panic_message`
+/// is called, on this boot and on the next ones
+#[cfg(not(feature = "rp2040"))]
+pub fn get_panic_message_bytes() -> Option<&'static [u8]> {
+    #[cfg(not(feature = "panic-ring"))]
+    let bytes = take_pending_bytes(Region::panic_dump());
+    // The oldest record of the ring is retrieved first
+    #[cfg(feature = "panic-ring")]
+    let bytes = ring::slots().find_map(take_pending_bytes);
 
-            // Increment the offset so later writes will be appended
-            self.offset += str_len;
+    // Only the copy in the rescue region is left if the panic region was wiped, and it must not
+    // be returned again otherwise
+    #[cfg(feature = "rescue-region")]
+    let bytes = match bytes {
+        Some(bytes) => {
+            #[cfg(not(feature = "sticky"))]
+            rescue::discard();
+            Some(bytes)
+        }
+        None => take_pending_bytes(Region::rescue()),
+    };
 
-            // ... and now write the current offset (or total size) to the size location
-            start_ptr
-                .offset(4)
-                .cast::<usize>()
-                .write_unaligned(self.offset);
-        };
+    // Once the message was retrieved, the region can be written again
+    #[cfg(all(feature = "multicore-lock", not(feature = "sticky")))]
+    lock::release();
 
-        Ok(())
-    }
+    bytes
 }
 
-/// Get the panic message from the last boot, if any.
+/// Get the panic message from the last boot, if any, of whichever core panicked.
+/// If both cores panicked, the message of core 0 is returned first.
 /// This method may possibly not return valid UTF-8 if the message
 /// was truncated before the end of a full UTF-8 character. Care must
 /// be taken before treating this as a proper &str.
 ///
 /// If a message existed, this function will only return the value once
 /// (subsequent calls will return None)
+#[cfg(feature = "rp2040")]
 pub fn get_panic_message_bytes() -> Option<&'static [u8]> {
-    // Obtain panic region start and end from linker symbol _panic_dump_start and _panic_dump_end
-    extern "C" {
-        static mut _panic_dump_start: u8;
-        static mut _panic_dump_end: u8;
+    get_panic_message_bytes_with_core().map(|(_core, bytes)| bytes)
+}
+
+/// Get the panic message from the last boot, if any, along with the index
+/// of the core that panicked. If both cores panicked, the message of core 0
+/// is returned first.
+/// This method may possibly not return valid UTF-8 if the message
+/// was truncated before the end of a full UTF-8 character. Care must
+/// be taken before treating this as a proper &str.
+///
+/// If a message existed, this function will only return the value once
+/// (subsequent calls will return None)
+#[cfg(feature = "rp2040")]
+pub fn get_panic_message_bytes_with_core() -> Option<(usize, &'static [u8])> {
+    (0..rp2040::CORES).find_map(|core| Some((core, get_core_panic_message_bytes(core)?)))
+}
+
+/// Get the panic message from the last boot of core `core` (0 or 1), if any.
+/// This method may possibly not return valid UTF-8 if the message
+/// was truncated before the end of a full UTF-8 character. Care must
+/// be taken before treating this as a proper &str.
+///
+/// If a message existed, this function will only return the value once
+/// (subsequent calls will return None)
+#[cfg(feature = "rp2040")]
+pub fn get_core_panic_message_bytes(core: usize) -> Option<&'static [u8]> {
+    if core >= rp2040::CORES {
+        return None;
+    }
+
+    take_pending_bytes(Region::panic_dump().core_slot(core))
+}
+
+/// Get the panic message from the last boot, if any, by copying it into
+/// `buf`. Returns the number of bytes that were copied, which are truncated
+/// to the length of `buf` if the message does not fit.
+///
+/// The message is consumed as with `get_panic_message_bytes`, so nothing
+/// borrows the region once this returns.
+pub fn get_panic_message_into(buf: &mut [u8]) -> Option<usize> {
+    copy_panic_message(|bytes| {
+        let len = core::cmp::min(bytes.len(), buf.len());
+        buf[..len].copy_from_slice(&bytes[..len]);
+
+        len
+    })
+}
+
+/// Get the panic message from the last boot, if any, and pass it to `copy`, which copies it out of
+/// the region. With the `zeroize` feature, the whole record is then wiped, so the message does not
+/// linger in memory
+fn copy_panic_message<T>(copy: impl FnOnce(&'static [u8]) -> T) -> Option<T> {
+    let bytes = get_panic_message_bytes()?;
+    let copied = copy(bytes);
+
+    #[cfg(all(feature = "zeroize", not(feature = "sticky")))]
+    wipe_record_of(bytes);
+
+    Some(copied)
+}
+
+/// Get the panic message persisted in the region of `len` bytes at `start`,
+/// if any. This is meant to read the region of another firmware, such as
+/// the one running on another core, with the same checks of the header as
+/// for the region of this image.
+/// This method may possibly not return valid UTF-8 if the message
+/// was truncated before the end of a full UTF-8 character. Care must
+/// be taken before treating this as a proper &str.
+///
+/// If a message existed, this function will only return the value once
+/// (subsequent calls will return None)
+///
+/// # Safety
+///
+/// `start` must point to `len` bytes of memory that are valid for reads
+/// and writes for the rest of the program, and which are not written to
+/// while the returned message is in use.
+pub unsafe fn get_panic_message_bytes_in(start: *mut u8, len: usize) -> Option<&'static [u8]> {
+    Some(take_message(Region { start, len }, MAGIC)?.text)
+}
+
+/// Take the message of `region` as a byte slice
+fn take_message_bytes(region: Region) -> Option<&'static [u8]> {
+    Some(take_message(region, RECORD_MAGIC)?.text)
+}
+
+/// Get the panic message from the last boot, if any, without consuming it.
+/// This method may possibly not return valid UTF-8 if the message
+/// was truncated before the end of a full UTF-8 character. Care must
+/// be taken before treating this as a proper &str.
+///
+/// Unlike `get_panic_message_bytes`, the magic word is left in place, so
+/// the message is returned again by later calls, until it is retrieved
+/// with `get_panic_message_bytes`. This is meant to inspect the message
+/// early on boot, such as to enter a safe mode, and let a later part of
+/// the firmware consume it.
+pub fn peek_panic_message_bytes() -> Option<&'static [u8]> {
+    peek_message_bytes(find_pending()?)
+}
+
+/// Whether a panic message from the last boot is waiting to be retrieved.
+///
+/// This only reads the magic word of the region, without checking the
+/// length or the CRC of the record, or clearing anything, so it is meant for
+/// the paths of the boot that only need to branch on whether the firmware
+/// panicked. A record whose payload was corrupted still counts, although
+/// `get_panic_message_bytes` does not return it.
+pub fn has_panic_message() -> bool {
+    #[cfg(not(any(feature = "panic-ring", feature = "rp2040")))]
+    let found = has_magic(Region::panic_dump(), RECORD_MAGIC);
+    #[cfg(feature = "panic-ring")]
+    let found = ring::slots().any(|slot| has_magic(slot, RECORD_MAGIC));
+    #[cfg(all(feature = "rp2040", not(feature = "panic-ring")))]
+    let found = (0..rp2040::CORES)
+        .any(|core| has_magic(Region::panic_dump().core_slot(core), RECORD_MAGIC));
+
+    #[cfg(feature = "rescue-region")]
+    let found = found || has_magic(Region::rescue(), RECORD_MAGIC);
+
+    found
+}
+
+/// Get the panic message from the last boot, if any, without consuming it,
+/// as with `peek_panic_message_bytes`. If any invalid UTF-8 characters
+/// occur, the message will be truncated before the first error.
+#[cfg(feature = "utf8")]
+pub fn peek_panic_message_utf8() -> Option<&'static str> {
+    bytes_to_utf8(peek_panic_message_bytes()?)
+}
+
+/// Get the location of the panic from the last boot, if any, and if it was
+/// known. The location is stored in its own fields, so panics can be
+/// grouped by location without parsing the message.
+///
+/// The record is left in place, as with `peek_panic_message_bytes`, so the
+/// message can still be retrieved afterwards.
+pub fn get_panic_location() -> Option<PanicLocation<'static>> {
+    find_message(find_pending()?, RECORD_MAGIC)?.location
+}
+
+/// Find the message of `region` as a byte slice, leaving it in place
+fn peek_message_bytes(region: Region) -> Option<&'static [u8]> {
+    Some(find_message(region, RECORD_MAGIC)?.text)
+}
+
+/// Take the message of `region`, one of the regions read by `get_panic_message_bytes`, as a byte
+/// slice. With the `sticky` feature, it is left in place until `acknowledge_panic_message` is
+/// called
+fn take_pending_bytes(region: Region) -> Option<&'static [u8]> {
+    #[cfg(feature = "sticky")]
+    let bytes = peek_message_bytes(region);
+    #[cfg(not(feature = "sticky"))]
+    let bytes = take_message_bytes(region);
+
+    bytes
+}
+
+/// Acknowledge the panic message from the last boot, once it was handled,
+/// such as uploaded. With the `sticky` feature, `get_panic_message_bytes`
+/// returns the message until it is acknowledged, on this boot and on the
+/// next ones, so a message that could not be handled is not lost.
+///
+/// This clears the message as with `clear_panic_message`.
+#[cfg(feature = "sticky")]
+pub fn acknowledge_panic_message() {
+    clear_panic_message();
+}
+
+/// Clear the panic message from the last boot, if any, without reading it,
+/// so it is no longer returned by `get_panic_message_bytes`. Along with
+/// `peek_panic_message_bytes`, this lets the application decide when the
+/// message is handled.
+///
+/// Only the header of the record is cleared, the message itself is left in
+/// memory: see `wipe_panic_message` to clear it as well, which this does with
+/// the `zeroize` feature.
+pub fn clear_panic_message() {
+    clear_pending(cfg!(feature = "zeroize"));
+}
+
+/// Clear the panic message from the last boot, if any, as with
+/// `clear_panic_message`, and overwrite the whole region with zeroes, so
+/// the message does not linger in memory.
+pub fn wipe_panic_message() {
+    clear_pending(true);
+}
+
+/// Clear the records that would be retrieved with `get_panic_message_bytes`, with their message if
+/// `wipe` is set
+fn clear_pending(wipe: bool) {
+    #[cfg(not(any(feature = "panic-ring", feature = "rp2040")))]
+    clear_region(Region::panic_dump(), wipe);
+    #[cfg(feature = "panic-ring")]
+    for slot in ring::slots() {
+        clear_region(slot, wipe);
+    }
+    #[cfg(feature = "rp2040")]
+    for core in 0..rp2040::CORES {
+        clear_region(Region::panic_dump().core_slot(core), wipe);
+    }
+
+    #[cfg(feature = "rescue-region")]
+    clear_region(Region::rescue(), wipe);
+
+    // Once the message was cleared, the region can be written again
+    #[cfg(feature = "multicore-lock")]
+    lock::release();
+}
+
+/// Overwrite the header of `region` with zeroes, starting with the magic word, and the rest of the
+/// region as well if `wipe` is set
+fn clear_region(region: Region, wipe: bool) {
+    // The region must at least be able to hold the header
+    if region.len < HEADER_LEN {
+        return;
+    }
+
+    let len = if wipe { region.len } else { HEADER_LEN };
+
+    // Only whole words of the region are used
+    #[cfg(feature = "aligned-writes")]
+    for offset in (0..len & !3).step_by(4) {
+        unsafe { region.start.add(offset).cast::<u32>().write_volatile(0) };
+    }
+
+    #[cfg(not(feature = "aligned-writes"))]
+    for offset in 0..len {
+        unsafe { region.start.add(offset).write_volatile(0) };
+    }
+}
+
+/// Overwrite the whole record holding `bytes`, a message that was copied out of it, with zeroes
+#[cfg(all(feature = "zeroize", not(feature = "sticky")))]
+fn wipe_record_of(bytes: &'static [u8]) {
+    // The message follows the location block, so the byte before it is in the record, even when
+    // the message is empty
+    let at = (bytes.as_ptr() as usize).wrapping_sub(1);
+
+    let regions = record_regions();
+    #[cfg(feature = "rescue-region")]
+    let regions = regions.chain(core::iter::once(Region::rescue()));
+
+    for region in regions {
+        let start = region.start as usize;
+
+        if (start..start + region.len).contains(&at) {
+            clear_region(region, true);
+            return;
+        }
     }
+}
+
+/// Get the panic message persisted by the non-secure image, if any, by copying it
+/// into `buf`. Returns the bytes that were copied, which are truncated to the
+/// length of `buf` if the message does not fit.
+///
+/// This is meant to be called from the secure image: the message is copied out
+/// of the non-secure region rather than borrowed, and its length is checked
+/// against the bounds of the region, so the non-secure image can't make the
+/// secure image read outside of it. Only records written by a non-secure image
+/// are returned.
+///
+/// If a message existed, this function will only return the value once
+/// (subsequent calls will return None)
+#[cfg(feature = "trustzone")]
+pub fn get_nonsecure_panic_message(buf: &mut [u8]) -> Option<&[u8]> {
+    let text = take_message(Region::panic_dump_ns(), MAGIC)?.text;
 
-    let start_ptr = unsafe { &mut _panic_dump_start as *mut u8 };
+    let len = core::cmp::min(text.len(), buf.len());
+    buf[..len].copy_from_slice(&text[..len]);
+
+    Some(&buf[..len])
+}
 
-    if 0x0FACADE0 != unsafe { core::ptr::read_unaligned(start_ptr.cast::<usize>()) } {
+/// Check the header of `region`, and return the payload of the record
+/// if it holds one marked with `magic`.
+///
+/// The magic word is cleared, so the message is only returned once.
+fn take_message(region: Region, magic: u32) -> Option<Payload<'static>> {
+    if !has_magic(region, magic) {
         return None;
     }
 
     // Clear the magic word to prevent this message from "sticking"
     // across multiple boots
     unsafe {
-        start_ptr.cast::<usize>().write_unaligned(0x00000000);
+        region.start.cast::<u32>().write_unaligned(0x00000000);
+    }
+
+    message_of(region)
+}
+
+/// Check the header of `region`, and return the payload of the record
+/// if it holds one marked with `magic`, leaving the message in place.
+fn find_message(region: Region, magic: u32) -> Option<Payload<'static>> {
+    if !has_magic(region, magic) {
+        return None;
+    }
+
+    message_of(region)
+}
+
+/// Check whether the header of `region` starts with `magic`
+fn has_magic(region: Region, magic: u32) -> bool {
+    // The region must at least be able to hold the header
+    if region.len < HEADER_LEN {
+        return false;
     }
 
-    // Obtain info about the panic dump region
-    let end_ptr = unsafe { &mut _panic_dump_end as *mut u8 };
-    let max_len = end_ptr as usize - start_ptr as usize;
-    let max_len_str = max_len - size_of::<usize>() - size_of::<usize>();
+    magic == unsafe { Header::read_magic(region.start) }
+}
+
+/// Whether a panic record is waiting to be retrieved with `get_panic_message_bytes`, which is left
+/// in place
+fn record_pending() -> bool {
+    find_pending().is_some()
+}
+
+/// The region holding the record waiting to be retrieved with `get_panic_message_bytes`, if any,
+/// which is left in place
+fn find_pending() -> Option<Region> {
+    pending_regions().next()
+}
+
+/// The regions holding the records waiting to be retrieved with `get_panic_message_bytes`, in the
+/// order they would be retrieved in, which are left in place
+fn pending_regions() -> impl Iterator<Item = Region> {
+    let holds_record = |region: &Region| find_message(*region, RECORD_MAGIC).is_some();
+
+    let pending = record_regions().filter(holds_record);
+
+    // The copy in the rescue region is only retrieved if the panic region was wiped
+    #[cfg(feature = "rescue-region")]
+    let pending = {
+        let mut pending = pending.peekable();
+        let rescue = Some(Region::rescue())
+            .filter(|_| pending.peek().is_none())
+            .filter(holds_record);
+
+        pending.chain(rescue)
+    };
+
+    pending
+}
+
+/// The regions the records are persisted to: the panic region, or the slots of the ring, oldest
+/// first, or the parts of the panic region belonging to each core
+fn record_regions() -> impl Iterator<Item = Region> {
+    #[cfg(not(any(feature = "panic-ring", feature = "rp2040")))]
+    let regions = core::iter::once(Region::panic_dump());
+    #[cfg(feature = "panic-ring")]
+    let regions = ring::slots();
+    #[cfg(all(feature = "rp2040", not(feature = "panic-ring")))]
+    let regions = (0..rp2040::CORES).map(|core| Region::panic_dump().core_slot(core));
+
+    regions
+}
+
+/// The payload of the record of `region`, if it has the layout of this version of the crate, its
+/// length fits in the region and it matches the CRC of the header
+fn message_of(region: Region) -> Option<Payload<'static>> {
+    let start_ptr = region.start;
+
+    let max_len_str = region.len - HEADER_LEN;
 
-    let len = unsafe { core::ptr::read_unaligned(start_ptr.offset(4).cast::<usize>()) };
+    let header = unsafe { Header::read(start_ptr) };
+    let len = header.len as usize;
+
+    // The record was written with another layout, which can't be parsed
+    if header.version != FORMAT_VERSION {
+        return None;
+    }
 
     if len > max_len_str {
         return None;
     }
 
     // TODO: This is prooooooooobably undefined behavior
-    let byte_slice = unsafe { core::slice::from_raw_parts(start_ptr.offset(8), len) };
+    let payload = unsafe { core::slice::from_raw_parts(start_ptr.add(HEADER_LEN), len) };
+    if header.crc != crc::crc32(payload) {
+        return None;
+    }
 
-    Some(byte_slice)
+    Payload::parse(payload)
 }
 
 /// Get the panic message from the last boot, if any. If any invalid
@@ -223,46 +2223,395 @@ pub fn get_panic_message_bytes() -> Option<&'static [u8]> {
 /// (subsequent calls will return None)
 #[cfg(feature = "utf8")]
 pub fn get_panic_message_utf8() -> Option<&'static str> {
-    let bytes = get_panic_message_bytes()?;
+    bytes_to_utf8(get_panic_message_bytes()?)
+}
+
+/// Get the panic message from the last boot, if any, by decoding it into
+/// `buf`. Unlike `get_panic_message_utf8`, invalid UTF-8 sequences are
+/// replaced with U+FFFD, the replacement character, instead of truncating
+/// the message at the first one, so as much of it as possible is returned.
+/// The message is truncated before the first character that does not fit
+/// in `buf`.
+///
+/// If a message existed, this function will only return the value once
+/// (subsequent calls will return None)
+#[cfg(feature = "utf8")]
+pub fn get_panic_message_utf8_lossy(buf: &mut [u8]) -> Option<&str> {
+    copy_panic_message(move |bytes| bytes_to_utf8_lossy(bytes, buf))
+}
+
+/// The message in `bytes`, decoded into `buf` with its invalid UTF-8 sequences replaced, and
+/// truncated before the first character that does not fit
+#[cfg(feature = "utf8")]
+fn bytes_to_utf8_lossy<'b>(bytes: &[u8], buf: &'b mut [u8]) -> &'b str {
+    let mut len = 0;
+
+    let chars = bytes.utf8_chunks().flat_map(|chunk| {
+        let replacement = match chunk.invalid().is_empty() {
+            true => None,
+            false => Some(char::REPLACEMENT_CHARACTER),
+        };
+
+        chunk.valid().chars().chain(replacement)
+    });
+
+    for c in chars {
+        if len + c.len_utf8() > buf.len() {
+            break;
+        }
+        len += c.encode_utf8(&mut buf[len..]).len();
+    }
+
+    // Only whole characters were written
+    core::str::from_utf8(&buf[..len]).unwrap_or("")
+}
 
+/// The message in `bytes`, truncated before the first invalid UTF-8 character
+#[cfg(feature = "utf8")]
+fn bytes_to_utf8(bytes: &[u8]) -> Option<&str> {
     use core::str::from_utf8;
 
     match from_utf8(bytes) {
         Ok(stir) => Some(stir),
         Err(utf_err) => {
-            match from_utf8(&bytes[..utf_err.valid_up_to()]) {
-                Ok(stir) => Some(stir),
-                Err(_) => {
-                    // This shouldn't be possible...
-                    None
-                }
-            }
+            // This shouldn't be possible to fail...
+            from_utf8(&bytes[..utf_err.valid_up_to()]).ok()
         }
     }
 }
 
-/// Report the panic so the message is persisted.
+/// Persist a message formatted as with `format_args!`, without panicking, such as to record a
+/// fatal but handled condition (a watchdog about to fire, a brown-out) before resetting the
+/// device. The message is written to the same sink as a panic message, with the same header and
+/// the same fields, but without a location, so it is retrieved by the `get_panic_message_*`
+/// functions on the next boot, as a panic message would be;
+///
+/// ``` ignore
+/// panic_persist::persist_message(format_args!("watchdog about to fire in {}", task));
+/// ```
+///
+/// Like a panic, it overwrites the message held by the region, unless the region is claimed with
+/// the `multicore-lock` feature. It is not meant to be called from several contexts at once, such
+/// as from thread mode and from an interrupt handler. As in the panic handler, the MPU region
+/// guarding the panic region with `enable_mpu_guard` is disabled, and the function registered with
+/// `set_before_write` is called, before writing, and the MPU region is enabled again once the
+/// message was written. Returns the outcome of writing the message, as with `report_panic_info`
+pub fn persist_message(args: core::fmt::Arguments<'_>) -> WriteStatus {
+    persist_with(|out| {
+        writeln!(out, "{}", args).ok();
+    })
+}
+
+/// Persist the message written by `write` to the sink of the panic handler, outside of a panic
+pub(crate) fn persist_with(write: impl FnOnce(&mut Formatter<'_>)) -> WriteStatus {
+    #[cfg(all(feature = "mpu-guard", cortex_m))]
+    mpu::release();
+
+    #[cfg(feature = "before-write")]
+    before_write::call();
+
+    // Another core already owns the region
+    #[cfg(feature = "multicore-lock")]
+    if !lock::claim() {
+        #[cfg(all(feature = "mpu-guard", cortex_m))]
+        mpu::restore();
+
+        return WriteStatus {
+            failed: true,
+            ..WriteStatus::default()
+        };
+    }
+
+    #[cfg(all(feature = "custom-sink", not(feature = "custom-panic-handler")))]
+    let mut out = Formatter(sink::custom());
+    #[cfg(not(all(feature = "custom-sink", not(feature = "custom-panic-handler"))))]
+    let mut ram = RamSink::new();
+    #[cfg(not(all(feature = "custom-sink", not(feature = "custom-panic-handler"))))]
+    let mut out = Formatter(sink::registered().unwrap_or(&mut ram));
+
+    write(&mut out);
+    out.0.finalize();
+
+    #[cfg(feature = "watermark")]
+    watermark::update();
+
+    #[cfg(feature = "multicore-lock")]
+    lock::release();
+
+    #[cfg(feature = "dcache-clean")]
+    dcache::clean(Region::current());
+
+    #[cfg(all(feature = "mpu-guard", cortex_m))]
+    mpu::restore();
+
+    out.0.status()
+}
+
+/// Panic with the requested size and alignment of an allocation that failed, so it is persisted
+/// (and the device reset) as any other panic, with the location of the caller. This is meant to
+/// be called from an `#[alloc_error_handler]`, or by an allocator failing an allocation;
+///
+/// ``` ignore
+/// #[alloc_error_handler]
+/// fn oom(layout: core::alloc::Layout) -> ! {
+///     panic_persist::alloc_error(layout)
+/// }
+/// ```
+///
+/// The message reads `memory allocation of <size> bytes (align <align>) failed`
+#[track_caller]
+pub fn alloc_error(layout: core::alloc::Layout) -> ! {
+    panic!(
+        "memory allocation of {} bytes (align {}) failed",
+        layout.size(),
+        layout.align()
+    )
+}
+
+/// Report the panic so the message is persisted. Returns the outcome of writing the message, so
+/// the handler can fall back to another sink, or signal an error, when it failed.
 ///
 /// This function is used in custom panic handlers.
 #[cfg(feature = "custom-panic-handler")]
-pub fn report_panic_info(info: &PanicInfo) {
-    writeln!(Ram { offset: 0 }, "{}", info).ok();
+pub fn report_panic_info(info: &PanicInfo) -> WriteStatus {
+    let mut ram = RamSink::new();
+    report_panic_info_to(info, sink::registered().unwrap_or(&mut ram))
 }
 
-#[cfg(not(feature = "custom-panic-handler"))]
+/// Report the panic so the message is persisted to `sink`, rather than to the panic region.
+/// Returns the outcome of writing the message, as reported by the sink.
+///
+/// This function is used in custom panic handlers.
+#[cfg(feature = "custom-panic-handler")]
+pub fn report_panic_info_to(info: &PanicInfo, sink: &mut dyn PanicSink) -> WriteStatus {
+    #[cfg(all(feature = "registers", cortex_m))]
+    registers::capture();
+
+    #[cfg(all(feature = "stack-dump", cortex_m))]
+    stack::capture();
+
+    #[cfg(all(feature = "backtrace", cortex_m))]
+    backtrace::capture();
+
+    #[cfg(all(feature = "mpu-guard", cortex_m))]
+    mpu::release();
+
+    #[cfg(feature = "before-write")]
+    before_write::call();
+
+    // Another core already owns the region
+    #[cfg(feature = "multicore-lock")]
+    if !lock::claim() {
+        #[cfg(all(feature = "mpu-guard", cortex_m))]
+        mpu::restore();
+
+        return WriteStatus {
+            failed: true,
+            ..WriteStatus::default()
+        };
+    }
+
+    let mut out = Formatter(sink);
+    if let Some(location) = info.location() {
+        out.0.location(location);
+    }
+
+    // `defmt::panic!` already encoded the message, which is persisted as is
+    #[cfg(feature = "defmt-payload")]
+    let message_written = defmt_payload::write_frame(out.0);
+    #[cfg(not(feature = "defmt-payload"))]
+    let message_written = false;
+
+    // The report holds the message, instead of its text
+    #[cfg(feature = "postcard")]
+    let message_written = message_written || report::write(out.0, info);
+
+    // The location is stored apart from the message
+    if !message_written {
+        writeln!(out, "{}", info.message()).ok();
+    }
+    out.0.finalize();
+    let status = out.0.status();
+
+    persist_extras(info);
+
+    #[cfg(feature = "serial-mirror")]
+    mirror::write(info);
+
+    #[cfg(feature = "rtt-mirror")]
+    rtt::write(info);
+
+    #[cfg(feature = "semihosting-mirror")]
+    semihosting::write(info);
+
+    #[cfg(feature = "defmt")]
+    defmt_log::write(info);
+
+    #[cfg(feature = "multicore-lock")]
+    lock::release();
+
+    #[cfg(all(feature = "mpu-guard", cortex_m))]
+    mpu::restore();
+
+    status
+}
+
+/// Persist everything that is kept alongside the panic message
+#[cfg(any(feature = "custom-panic-handler", target_os = "none"))]
+#[allow(unused_variables)]
+fn persist_extras(info: &PanicInfo) {
+    #[cfg(feature = "rp2040-scratch")]
+    rp2040::write_scratch_summary(info.location());
+
+    #[cfg(feature = "nrf52-gpregret")]
+    nrf52::signal_panic();
+
+    #[cfg(feature = "rescue-region")]
+    rescue::copy_message(Region::last_record(), Region::rescue());
+
+    #[cfg(feature = "panic-counter")]
+    counter::increment();
+
+    #[cfg(feature = "kv-store")]
+    kv::freeze();
+
+    #[cfg(feature = "breadcrumbs")]
+    breadcrumbs::freeze();
+
+    #[cfg(feature = "log")]
+    logs::freeze();
+
+    #[cfg(feature = "watermark")]
+    watermark::update();
+
+    #[cfg(all(feature = "stack-dump", cortex_m))]
+    stack::write_dump();
+
+    // Last, so everything written to the region reaches the RAM
+    #[cfg(all(
+        feature = "dcache-clean",
+        not(any(
+            feature = "breadcrumbs",
+            feature = "kv-store",
+            feature = "log",
+            feature = "panic-counter",
+            feature = "stack-dump",
+            feature = "watermark"
+        ))
+    ))]
+    dcache::clean(Region::current());
+
+    // The rings of breadcrumbs and of log records, the key-value store, the counter and usage
+    // blocks and the stack dump are outside of the region
+    #[cfg(all(
+        feature = "dcache-clean",
+        any(
+            feature = "breadcrumbs",
+            feature = "kv-store",
+            feature = "log",
+            feature = "panic-counter",
+            feature = "stack-dump",
+            feature = "watermark"
+        )
+    ))]
+    dcache::clean(Region::memory());
+}
+
+#[cfg(all(not(feature = "custom-panic-handler"), target_os = "none"))]
 #[panic_handler]
 fn panic(info: &PanicInfo) -> ! {
-    cortex_m::interrupt::disable();
+    // First, before the registers are used by anything else
+    #[cfg(all(feature = "registers", cortex_m))]
+    registers::capture();
+
+    #[cfg(all(feature = "stack-dump", cortex_m))]
+    stack::capture();
+
+    #[cfg(all(feature = "backtrace", cortex_m))]
+    backtrace::capture();
+
+    arch::disable_interrupts();
+
+    #[cfg(all(feature = "mpu-guard", cortex_m))]
+    mpu::release();
+
+    #[cfg(feature = "before-write")]
+    before_write::call();
+
+    // Another core already owns the region, its record is kept
+    #[cfg(feature = "multicore-lock")]
+    if !lock::claim() {
+        lock::wait();
+        finish();
+    }
+
+    #[cfg(feature = "custom-sink")]
+    let mut out = Formatter(sink::custom());
+    #[cfg(not(feature = "custom-sink"))]
+    let mut ram = RamSink::new();
+    #[cfg(not(feature = "custom-sink"))]
+    let mut out = Formatter(sink::registered().unwrap_or(&mut ram));
 
-    #[cfg(feature = "min-panic")]
     if let Some(location) = info.location() {
-        writeln!(Ram { offset: 0 }, "Panicked at {}", location).ok();
-    } else {
-        writeln!(Ram { offset: 0 }, "Panic occured!").ok();
+        out.0.location(location);
     }
 
-    #[cfg(not(feature = "min-panic"))]
-    writeln!(Ram { offset: 0 }, "{}", info).ok();
+    // `defmt::panic!` already encoded the message, which is persisted as is
+    #[cfg(feature = "defmt-payload")]
+    let message_written = defmt_payload::write_frame(out.0);
+    #[cfg(not(feature = "defmt-payload"))]
+    let message_written = false;
+
+    // The report holds the message, instead of its text
+    #[cfg(feature = "postcard")]
+    let message_written = message_written || report::write(out.0, info);
+
+    if !message_written {
+        #[cfg(feature = "min-panic")]
+        if let Some(location) = info.location() {
+            writeln!(out, "Panicked at {}", location).ok();
+        } else {
+            writeln!(out, "Panic occured!").ok();
+        }
+
+        // The location is stored apart from the message
+        #[cfg(not(feature = "min-panic"))]
+        writeln!(out, "{}", info.message()).ok();
+    }
+
+    out.0.finalize();
+
+    persist_extras(info);
+
+    // The record is complete, the hardware is put in a safe state before it is mirrored
+    #[cfg(feature = "before-reset")]
+    before_reset::call();
+
+    #[cfg(feature = "serial-mirror")]
+    mirror::write(info);
+
+    #[cfg(feature = "rtt-mirror")]
+    rtt::write(info);
+
+    #[cfg(feature = "semihosting-mirror")]
+    semihosting::write(info);
+
+    #[cfg(feature = "defmt")]
+    defmt_log::write(info);
+
+    // The record is complete, another core waiting for it can reset the device
+    #[cfg(feature = "multicore-lock")]
+    lock::release();
+
+    finish();
+}
+
+/// End the panic, once the record was persisted
+#[cfg(all(not(feature = "custom-panic-handler"), target_os = "none"))]
+fn finish() -> ! {
+    #[cfg(feature = "sim")]
+    sim::exit();
 
-    cortex_m::peripheral::SCB::sys_reset();
+    #[cfg(not(feature = "sim"))]
+    action::run();
 }
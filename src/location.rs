@@ -0,0 +1,272 @@
+//! Location of the panic, stored in front of the text of the message
+
+use core::convert::TryFrom;
+use core::fmt;
+
+use crate::fields::Fields;
+
+/// Size of the location block at the start of the payload of a record: the line and the column of
+/// the panic, which are zero when the location is unknown, the length of the file name that
+/// follows it, and the length of the extra fields that follow the file name. The file name is
+/// padded to a multiple of 4 bytes, as the fields are, so the text of the message is aligned
+pub(crate) const LOCATION_LEN: usize = 16;
+
+/// The location block of a record
+pub(crate) struct Block {
+    pub(crate) line: u32,
+    pub(crate) column: u32,
+    pub(crate) file_len: usize,
+    pub(crate) fields_len: usize,
+}
+
+impl Block {
+    /// Parse the location block `block`, if the lengths fit in a `usize` (on 16 bit targets)
+    pub(crate) fn parse(block: [u8; LOCATION_LEN]) -> Option<Self> {
+        let word = |at: usize| {
+            u32::from_le_bytes([block[at], block[at + 1], block[at + 2], block[at + 3]])
+        };
+
+        Some(Self {
+            line: word(0),
+            column: word(4),
+            file_len: usize::try_from(word(8)).ok()?,
+            fields_len: usize::try_from(word(12)).ok()?,
+        })
+    }
+
+    /// The location block as stored in the record
+    fn to_bytes(&self) -> [u8; LOCATION_LEN] {
+        let mut block = [0; LOCATION_LEN];
+
+        block[..4].copy_from_slice(&self.line.to_le_bytes());
+        block[4..8].copy_from_slice(&self.column.to_le_bytes());
+        block[8..12].copy_from_slice(&(self.file_len as u32).to_le_bytes());
+        block[12..].copy_from_slice(&(self.fields_len as u32).to_le_bytes());
+
+        block
+    }
+
+    /// The offset of the extra fields in the payload, after the location block and the file name,
+    /// if it does not overflow
+    fn fields_offset(&self) -> Option<usize> {
+        Some(self.file_len.checked_add(LOCATION_LEN + 3)? & !3)
+    }
+
+    /// The offset of the text of the message in the payload, after the extra fields, if it does
+    /// not overflow and the fields are a multiple of 4 bytes
+    pub(crate) fn text_offset(&self) -> Option<usize> {
+        if !self.fields_len.is_multiple_of(4) {
+            return None;
+        }
+
+        self.fields_offset()?.checked_add(self.fields_len)
+    }
+}
+
+/// The length of a file name of `file_len` bytes, once padded
+pub(crate) const fn padded_len(file_len: usize) -> usize {
+    (file_len + 3) & !3
+}
+
+/// Give the start of a payload to `out`, in pieces: the location block of a panic at `line` and
+/// `column` of `file`, the file name and `fields`. The fields, and then the file name, are left out
+/// if they don't fit in `max_len` bytes
+pub(crate) fn write_prefix(
+    line: u32,
+    column: u32,
+    file: &[u8],
+    fields: &Fields<'_>,
+    max_len: usize,
+    mut out: impl FnMut(&[u8]),
+) {
+    let fields_len = match LOCATION_LEN + fields.encoded_len() <= max_len {
+        true => fields.encoded_len(),
+        false => 0,
+    };
+    let file = match LOCATION_LEN + fields_len + padded_len(file.len()) <= max_len {
+        true => file,
+        false => &[],
+    };
+
+    let block = Block {
+        line,
+        column,
+        file_len: file.len(),
+        fields_len,
+    };
+    out(&block.to_bytes());
+    out(file);
+    out(&[0; 3][..padded_len(file.len()) - file.len()]);
+    if fields_len > 0 {
+        fields.encode(out);
+    }
+}
+
+/// The location of a persisted panic, as returned by [`Record::location`]
+///
+/// [`Record::location`]: crate::Record::location
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct PanicLocation<'a> {
+    file: &'a str,
+    line: u32,
+    column: u32,
+}
+
+impl<'a> PanicLocation<'a> {
+    /// The name of the source file the panic happened in. It is empty if it did not fit in the
+    /// region
+    pub fn file(&self) -> &'a str {
+        self.file
+    }
+
+    /// The line the panic happened at
+    pub fn line(&self) -> u32 {
+        self.line
+    }
+
+    /// The column the panic happened at
+    pub fn column(&self) -> u32 {
+        self.column
+    }
+}
+
+impl fmt::Display for PanicLocation<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}:{}", self.file, self.line, self.column)
+    }
+}
+
+/// The parts of the payload of a record: the location of the panic, the extra fields, and the
+/// text of the message
+#[derive(Clone, Copy)]
+pub(crate) struct Payload<'a> {
+    pub(crate) location: Option<PanicLocation<'a>>,
+    pub(crate) fields: Fields<'a>,
+    pub(crate) text: &'a [u8],
+}
+
+impl<'a> Payload<'a> {
+    /// Split `payload` into its parts, if the location block is consistent with its length
+    pub(crate) fn parse(payload: &'a [u8]) -> Option<Self> {
+        if payload.len() < LOCATION_LEN {
+            return None;
+        }
+
+        let mut bytes = [0; LOCATION_LEN];
+        bytes.copy_from_slice(&payload[..LOCATION_LEN]);
+        let block = Block::parse(bytes)?;
+
+        let fields_start = block.fields_offset()?;
+        let text_start = block.text_offset()?;
+        if text_start > payload.len() {
+            return None;
+        }
+
+        // The file name was copied from a `&str`, and the record matches its CRC
+        let file =
+            core::str::from_utf8(&payload[LOCATION_LEN..LOCATION_LEN + block.file_len]).ok()?;
+        let location = match block.line {
+            0 => None,
+            line => Some(PanicLocation {
+                file,
+                line,
+                column: block.column,
+            }),
+        };
+
+        Some(Self {
+            location,
+            fields: Fields::parse(&payload[fields_start..text_start]),
+            text: &payload[text_start..],
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A payload of a panic at `lib.rs:7:3`, with a build id, a field of an unknown tag, and the
+    /// text `oops`
+    const PAYLOAD: [u8; 44] = [
+        // Line, column, length of the file name and of the fields
+        7, 0, 0, 0, 3, 0, 0, 0, 6, 0, 0, 0, 16, 0, 0, 0, //
+        // File name, padded to a multiple of 4 bytes
+        b'l', b'i', b'b', b'.', b'r', b's', 0, 0, //
+        // Build id: tag 1, 2 bytes, padded
+        1, 0, 2, 0, 0xB1, 0xD0, 0, 0, //
+        // Unknown tag, 3 bytes, padded
+        0xFF, 0x7F, 3, 0, 1, 2, 3, 0, //
+        // Text
+        b'o', b'o', b'p', b's',
+    ];
+
+    #[test]
+    fn parse_location_fields_and_text() {
+        let payload = Payload::parse(&PAYLOAD).unwrap();
+        let location = payload.location.unwrap();
+
+        assert_eq!(location.file(), "lib.rs");
+        assert_eq!(location.line(), 7);
+        assert_eq!(location.column(), 3);
+        assert_eq!(payload.fields.build_id, Some(&[0xB1, 0xD0][..]));
+        assert_eq!(payload.fields.timestamp, None);
+        assert_eq!(payload.text, b"oops");
+    }
+
+    #[test]
+    fn parse_unknown_location() {
+        let mut bytes = PAYLOAD;
+        bytes[0] = 0;
+
+        assert_eq!(Payload::parse(&bytes).unwrap().location, None);
+    }
+
+    #[test]
+    fn parse_inconsistent_block() {
+        // Shorter than the location block
+        assert!(Payload::parse(&PAYLOAD[..LOCATION_LEN - 1]).is_none());
+
+        // The fields don't fit in the payload
+        let mut bytes = PAYLOAD;
+        bytes[12] = 40;
+        assert!(Payload::parse(&bytes).is_none());
+
+        // The fields are not a multiple of 4 bytes
+        let mut bytes = PAYLOAD;
+        bytes[12] = 15;
+        assert!(Payload::parse(&bytes).is_none());
+
+        // The file name is not UTF-8
+        let mut bytes = PAYLOAD;
+        bytes[LOCATION_LEN] = 0xFF;
+        assert!(Payload::parse(&bytes).is_none());
+    }
+
+    #[test]
+    fn write_prefix_then_parse() {
+        let fields = Fields {
+            timestamp: Some(42),
+            ..Fields::default()
+        };
+        let mut bytes = [0; 64];
+        let mut len = 0;
+        write_prefix(12, 5, b"src/main.rs", &fields, bytes.len(), |piece| {
+            bytes[len..len + piece.len()].copy_from_slice(piece);
+            len += piece.len();
+        });
+
+        let payload = Payload::parse(&bytes[..len]).unwrap();
+        assert_eq!(
+            payload.location,
+            Some(PanicLocation {
+                file: "src/main.rs",
+                line: 12,
+                column: 5,
+            })
+        );
+        assert_eq!(payload.fields.timestamp, Some(42));
+        assert_eq!(payload.text, b"");
+    }
+}
@@ -0,0 +1,72 @@
+//! Claim of a panic region shared between several cores
+
+use core::sync::atomic::{AtomicU32, Ordering};
+
+use crate::region::Region;
+
+/// Value of the claim word while no core owns the region. Any other value than `CLAIMED`, such as
+/// the random content of the memory after a power cycle, counts as free as well
+const FREE: u32 = 0;
+
+/// Value of the claim word while a core writes its record
+const CLAIMED: u32 = 0xC1A1_4ED0;
+
+/// The most times the claim word is checked while waiting for another core to release it
+#[cfg(all(not(feature = "custom-panic-handler"), target_os = "none"))]
+const WAIT_SPINS: u32 = 10_000_000;
+
+#[cfg(not(target_has_atomic = "32"))]
+compile_error!("the `multicore-lock` feature requires 32 bit atomic compare and swap");
+
+/// The claim word, in front of the panic region, unless it is not 4 byte aligned, as atomic
+/// accesses must be
+fn claim_word() -> Option<&'static AtomicU32> {
+    let claim = Region::claim_word()?;
+    if !(claim as usize).is_multiple_of(4) {
+        return None;
+    }
+
+    // The claim word is only ever accessed atomically
+    Some(unsafe { &*claim.cast::<AtomicU32>() })
+}
+
+/// Try to claim the region for the current core, while it writes its record. Only the first core
+/// to call this wins, until the claim is released. Without a claim word, the record is written
+/// without claiming the region
+pub(crate) fn claim() -> bool {
+    let claim = match claim_word() {
+        Some(claim) => claim,
+        None => return true,
+    };
+
+    let mut current = claim.load(Ordering::Relaxed);
+    while current != CLAIMED {
+        match claim.compare_exchange(current, CLAIMED, Ordering::Acquire, Ordering::Relaxed) {
+            Ok(_) => return true,
+            Err(value) => current = value,
+        }
+    }
+
+    false
+}
+
+/// Release the claim, so the region can be written again
+pub(crate) fn release() {
+    if let Some(claim) = claim_word() {
+        claim.store(FREE, Ordering::Release);
+    }
+}
+
+/// Wait for the core owning the region to release it, once its record is written. This gives up
+/// after a while, so a claim left by a reset in the middle of a write does not hang the core
+#[cfg(all(not(feature = "custom-panic-handler"), target_os = "none"))]
+pub(crate) fn wait() {
+    if let Some(claim) = claim_word() {
+        for _ in 0..WAIT_SPINS {
+            if claim.load(Ordering::Acquire) != CLAIMED {
+                return;
+            }
+            core::hint::spin_loop();
+        }
+    }
+}
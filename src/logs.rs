@@ -0,0 +1,284 @@
+//! Ring of log records kept in front of the panic region, which is frozen by the panic handler
+
+use core::fmt::Write;
+
+use log::{Level, Log, Metadata, Record};
+
+use crate::region::Region;
+
+/// The number of log records the ring holds
+const SLOTS: usize = 8;
+
+/// Size of a slot: the length of the text of the record and its level, followed by its text
+const SLOT_LEN: usize = 64;
+
+/// Size of the ring: the state word and the count of the records logged so far, followed by the
+/// slots
+pub(crate) const LOGS_LEN: usize = 8 + SLOTS * SLOT_LEN;
+
+/// Marks a ring the records are logged in
+const ACTIVE: u32 = 0x0FACADE5;
+
+/// Marks a ring frozen by the panic handler
+const FROZEN: u32 = 0x0FACADE6;
+
+/// A `log::Log` keeping the `WARN` and `ERROR` records in the ring of the panic region, so the
+/// last ones logged before a panic are returned by [`get_panic_logs`] on the next boot;
+///
+/// ``` ignore
+/// log::set_logger(&panic_persist::PanicLogger).ok();
+/// log::set_max_level(log::LevelFilter::Warn);
+/// ```
+///
+/// To keep another logger, its records can be forwarded with [`persist_log`] instead.
+pub struct PanicLogger;
+
+impl Log for PanicLogger {
+    fn enabled(&self, metadata: &Metadata<'_>) -> bool {
+        metadata.level() <= Level::Warn
+    }
+
+    fn log(&self, record: &Record<'_>) {
+        if self.enabled(record.metadata()) {
+            persist_log(record);
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+/// The words of the ring, if the memory of the region can hold it
+fn words() -> Option<*mut u32> {
+    let ring = Region::logs()?;
+
+    // The words of the ring are accessed with aligned 32 bit reads and writes
+    if !(ring as usize).is_multiple_of(4) {
+        return None;
+    }
+
+    Some(ring.cast::<u32>())
+}
+
+/// The slot at `index`
+fn slot(words: *mut u32, index: u32) -> *mut u8 {
+    words
+        .cast::<u8>()
+        .wrapping_add(8 + (index as usize % SLOTS) * SLOT_LEN)
+}
+
+/// Formats the text of a record into a slot, dropping what does not fit
+struct SlotWriter {
+    slot: *mut u8,
+    len: usize,
+}
+
+impl Write for SlotWriter {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        // The text is cut at a character boundary
+        let mut len = s.len().min(SLOT_LEN - 2 - self.len);
+        while !s.is_char_boundary(len) {
+            len -= 1;
+        }
+
+        unsafe {
+            core::ptr::copy_nonoverlapping(s.as_ptr(), self.slot.add(2 + self.len), len);
+        }
+        self.len += len;
+
+        Ok(())
+    }
+}
+
+/// Keep `record` in the ring, whatever its level, which keeps the last 8 records, so the records
+/// logged before a panic are returned by [`get_panic_logs`] on the next boot. Each record keeps
+/// the first 62 bytes of its message. This is meant to be called by a logger of the application,
+/// to keep its records along with the panic record.
+///
+/// It is not meant to be called from several contexts at once, such as from thread mode and from
+/// an interrupt handler, and records are ignored while the ring still holds the ones of the last
+/// panic, until [`clear_panic_logs`] is called
+pub fn persist_log(record: &Record<'_>) {
+    let words = match words() {
+        Some(words) => words,
+        None => return,
+    };
+
+    match unsafe { words.read_volatile() } {
+        FROZEN => return,
+        ACTIVE => {}
+        // The ring lost power
+        _ => clear_panic_logs(),
+    }
+
+    let count = unsafe { words.add(1).read_volatile() };
+    let slot = slot(words, count);
+
+    // The length is cleared first, so a panic in between never freezes half of a record
+    unsafe { slot.write_volatile(0) };
+
+    let mut out = SlotWriter { slot, len: 0 };
+    out.write_fmt(*record.args()).ok();
+
+    unsafe {
+        slot.add(1).write_volatile(record.level() as u8);
+        slot.write_volatile(out.len as u8);
+        words.add(1).write_volatile(count.wrapping_add(1));
+    }
+}
+
+/// The records logged with [`PanicLogger`] (or [`persist_log`]) before the firmware panicked,
+/// oldest first, with their level.
+///
+/// The ring is left as it is, so the records are returned until [`clear_panic_logs`] is called
+pub fn get_panic_logs() -> PanicLogs {
+    let words = match words() {
+        Some(words) if unsafe { words.read_volatile() } == FROZEN => words,
+        _ => {
+            return PanicLogs {
+                words: core::ptr::null_mut(),
+                next: 0,
+                end: 0,
+            }
+        }
+    };
+
+    let end = unsafe { words.add(1).read_volatile() };
+
+    PanicLogs {
+        words,
+        next: end.wrapping_sub(end.min(SLOTS as u32)),
+        end,
+    }
+}
+
+/// Clear the records of the ring, and let [`persist_log`] keep new ones. This is meant to be
+/// called on boot, once the records of the last panic were read
+pub fn clear_panic_logs() {
+    if let Some(words) = words() {
+        unsafe {
+            words.write_volatile(ACTIVE);
+            words.add(1).write_volatile(0);
+        }
+    }
+}
+
+/// Freeze the ring, so the records it holds are kept along with the panic record
+pub(crate) fn freeze() {
+    if let Some(words) = words() {
+        if unsafe { words.read_volatile() } == ACTIVE {
+            unsafe { words.write_volatile(FROZEN) };
+        }
+    }
+}
+
+/// The level stored in a slot, if it is valid
+fn level(value: u8) -> Option<Level> {
+    match value {
+        1 => Some(Level::Error),
+        2 => Some(Level::Warn),
+        3 => Some(Level::Info),
+        4 => Some(Level::Debug),
+        5 => Some(Level::Trace),
+        _ => None,
+    }
+}
+
+/// The records logged before the last panic, as returned by [`get_panic_logs`]
+pub struct PanicLogs {
+    words: *mut u32,
+    next: u32,
+    end: u32,
+}
+
+impl Iterator for PanicLogs {
+    type Item = (Level, &'static str);
+
+    fn next(&mut self) -> Option<(Level, &'static str)> {
+        while self.next != self.end {
+            let slot = slot(self.words, self.next);
+            self.next = self.next.wrapping_add(1);
+
+            // Records whose length is cleared were being logged when the firmware panicked
+            let len = unsafe { slot.read_volatile() } as usize;
+            if len == 0 || len > SLOT_LEN - 2 {
+                continue;
+            }
+
+            let level = match level(unsafe { slot.add(1).read_volatile() }) {
+                Some(level) => level,
+                None => continue,
+            };
+
+            let text = unsafe { core::slice::from_raw_parts(slot.add(2), len) };
+            if let Ok(text) = core::str::from_utf8(text) {
+                return Some((level, text));
+            }
+        }
+
+        None
+    }
+}
+
+#[cfg(all(test, feature = "runtime-region"))]
+mod tests {
+    use super::*;
+    use crate::region::with_test_region;
+
+    /// Log `args` at `level` with the [`PanicLogger`]
+    fn log(level: Level, args: core::fmt::Arguments<'_>) {
+        PanicLogger.log(&Record::builder().level(level).args(args).build());
+    }
+
+    #[test]
+    fn last_records_are_frozen_by_a_panic() {
+        with_test_region(2048, |_| {
+            // The ring lost power, so it is cleared by the first record
+            for index in 0..10 {
+                log(Level::Warn, format_args!("warning {}", index));
+            }
+            log(Level::Info, format_args!("not kept"));
+            log(Level::Error, format_args!("error"));
+
+            // The records are only returned once they were frozen
+            assert_eq!(get_panic_logs().count(), 0);
+            freeze();
+            let mut logs = get_panic_logs();
+            assert_eq!(logs.next(), Some((Level::Warn, "warning 3")));
+            assert_eq!(logs.nth(5), Some((Level::Warn, "warning 9")));
+            assert_eq!(logs.next(), Some((Level::Error, "error")));
+            assert_eq!(logs.next(), None);
+
+            // The records of the panic are kept until they are cleared
+            log(Level::Error, format_args!("later"));
+            assert_eq!(get_panic_logs().last(), Some((Level::Error, "error")));
+
+            clear_panic_logs();
+            assert_eq!(get_panic_logs().count(), 0);
+        });
+    }
+
+    #[test]
+    fn records_of_any_level_are_kept_when_forwarded() {
+        with_test_region(2048, |_| {
+            clear_panic_logs();
+            persist_log(
+                &Record::builder()
+                    .level(Level::Debug)
+                    .args(format_args!("debug"))
+                    .build(),
+            );
+            freeze();
+
+            assert!(get_panic_logs().eq([(Level::Debug, "debug")].iter().copied()));
+        });
+    }
+
+    #[test]
+    fn random_content_holds_no_records() {
+        with_test_region(2048, |_| {
+            freeze();
+
+            assert_eq!(get_panic_logs().count(), 0);
+        });
+    }
+}
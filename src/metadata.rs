@@ -0,0 +1,56 @@
+//! Metadata of the pending panic record, read without retrieving its message
+
+use crate::header::{Header, TRUNCATED};
+use crate::{find_message, find_pending, HEADER_LEN, RECORD_MAGIC};
+
+/// What the panic record waiting to be retrieved holds, as returned by [`record_metadata`]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RecordMetadata {
+    /// The length of the message, as copied by `get_panic_message_into`
+    pub message_len: usize,
+    /// The bytes of the region used by the record, header and fields included
+    pub record_len: usize,
+    /// Whether the message did not fit in the region, so only part of it was stored
+    pub truncated: bool,
+    /// The id of the core that wrote the record
+    pub core: u8,
+    /// The number of times the panic happened, counting the repeats of the `dedupe` feature
+    pub occurrences: u32,
+    /// The number of panics persisted, as returned by `panic_count`
+    #[cfg(feature = "panic-counter")]
+    pub panic_count: u32,
+    /// The number of panics persisted in a row, as returned by `consecutive_panics`
+    #[cfg(feature = "panic-counter")]
+    pub consecutive_panics: u32,
+}
+
+/// The metadata of the panic record from the last boot, if any: the length of its message, whether
+/// it was truncated, and the counts of the panics, without returning the message. This is meant to
+/// plan for the message before retrieving it, such as to allocate the buffer it is uploaded from.
+///
+/// The record is left in place, as with `peek_panic_message_bytes`, and it is the one retrieved
+/// next by `get_panic_message_bytes`
+pub fn record_metadata() -> Option<RecordMetadata> {
+    let region = find_pending()?;
+    let payload = find_message(region, RECORD_MAGIC)?;
+
+    let header = unsafe { Header::read(region.start) };
+
+    Some(RecordMetadata {
+        message_len: payload.text.len(),
+        record_len: HEADER_LEN + header.len as usize,
+        truncated: header.flags & TRUNCATED != 0,
+        core: header.core,
+        occurrences: u32::from(header.repeats) + 1,
+        #[cfg(feature = "panic-counter")]
+        panic_count: crate::counter::panic_count(),
+        #[cfg(feature = "panic-counter")]
+        consecutive_panics: crate::counter::consecutive_panics(),
+    })
+}
+
+/// The length of the message of the panic record from the last boot, if any, as copied by
+/// `get_panic_message_into`, which is left in place
+pub fn get_panic_message_len() -> Option<usize> {
+    Some(record_metadata()?.message_len)
+}
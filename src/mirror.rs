@@ -0,0 +1,39 @@
+//! Blocking output of the panic message to a serial port, in addition to persisting it
+
+use core::fmt::Write as _;
+use core::panic::PanicInfo;
+
+use embedded_io::Write;
+
+use crate::io::IoSink;
+use crate::sink::{Formatter, PanicSink, SinkSlot};
+
+/// The writer registered with `set_serial_mirror`, if any
+static MIRROR: SinkSlot = SinkSlot::new();
+
+/// Register `writer`, such as a UART, which the panic handler also writes the panic message to,
+/// once it was persisted and before the device is reset.
+///
+/// The writer is used from panic context, so it must not wait for interrupts. The output is best
+/// effort: errors are ignored, and a writer that never completes prevents the reset.
+///
+/// Only the first writer is registered: this returns `false`, and `writer` is not used, if a writer
+/// already was.
+pub fn set_serial_mirror<W: Write>(writer: &'static mut W) -> bool {
+    MIRROR.set((writer as *mut W).cast::<()>(), cast::<W>)
+}
+
+/// Turn `writer`, which was registered as a `W`, into a sink
+fn cast<W: Write + 'static>(writer: *mut ()) -> &'static mut dyn PanicSink {
+    // `IoSink` has the same layout as the writer it wraps
+    unsafe { &mut *writer.cast::<IoSink<W>>() }
+}
+
+/// Write the panic message to the registered writer, if any
+pub(crate) fn write(info: &PanicInfo) {
+    if let Some(sink) = MIRROR.get() {
+        let mut out = Formatter(sink);
+        writeln!(out, "{}", info).ok();
+        out.0.finalize();
+    }
+}
@@ -0,0 +1,158 @@
+//! Cortex-M MPU region guarding the panic region during normal operation
+
+use core::sync::atomic::{AtomicU8, Ordering};
+
+use cortex_m::peripheral::MPU;
+
+use crate::region::Region;
+
+/// The MPU region guarding the panic region, or `NO_GUARD`
+static GUARD: AtomicU8 = AtomicU8::new(NO_GUARD);
+
+/// No MPU region guards the panic region
+const NO_GUARD: u8 = 0xFF;
+
+/// MPU_CTRL: enable the MPU
+const CTRL_ENABLE: u32 = 1 << 0;
+
+/// MPU_CTRL: use the default memory map for the privileged accesses outside of the MPU regions
+const CTRL_PRIVDEFENA: u32 = 1 << 2;
+
+/// The errors of [`enable_mpu_guard`]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MpuGuardError {
+    /// The core has no MPU
+    NoMpu,
+    /// The MPU does not have this region
+    InvalidRegionNumber,
+    /// The guarded part of the panic region can't be covered by an MPU region: on ARMv6-M and
+    /// ARMv7-M, its length must be a power of two, of at least 32 bytes, and its start must be
+    /// aligned to its length. On ARMv8-M, both must be multiples of 32 bytes
+    Unaligned,
+}
+
+/// Guard the panic region with MPU region `number`, so it can't be accessed during normal
+/// operation: stray writes fault instead of corrupting, or faking, a panic record. The panic
+/// handler disables the MPU region before writing to the panic region, and `report_panic_info`,
+/// `persist_message` and `PersistWriter` enable it again once they wrote their message.
+///
+/// The blocks at the front of the memory holding the panic region, which are written during normal
+/// operation by the `kv-store`, `breadcrumbs`, `log`, `panic-counter` and `watermark` features,
+/// and the scratch area of the application are left out of the MPU region, so the guarded part
+/// starts after the blocks. The panic message of the last boot must be retrieved before calling
+/// this, and the MPU region must not be used for anything else. The MPU is enabled, with the default memory map for the
+/// privileged accesses outside of the MPU regions.
+///
+/// On ARMv6-M and ARMv7-M, the region can't be accessed at all. There is no such access
+/// permission on ARMv8-M, so the region is left readable by privileged code there.
+pub fn enable_mpu_guard(mpu: &mut MPU, number: u8) -> Result<(), MpuGuardError> {
+    let regions = (mpu._type.read() >> 8) & 0xFF;
+    if regions == 0 {
+        return Err(MpuGuardError::NoMpu);
+    }
+    if u32::from(number) >= regions {
+        return Err(MpuGuardError::InvalidRegionNumber);
+    }
+
+    let region = Region::guarded();
+    let (rbar, attributes) = registers(region).ok_or(MpuGuardError::Unaligned)?;
+
+    unsafe {
+        mpu.rnr.write(u32::from(number));
+        mpu.rbar.write(rbar);
+        #[cfg(not(armv8m))]
+        mpu.rasr.write(attributes);
+        #[cfg(armv8m)]
+        mpu.rlar.write(attributes);
+
+        mpu.ctrl.modify(|ctrl| ctrl | CTRL_ENABLE | CTRL_PRIVDEFENA);
+    }
+    GUARD.store(number, Ordering::Release);
+
+    cortex_m::asm::dsb();
+    cortex_m::asm::isb();
+
+    Ok(())
+}
+
+/// Disable the MPU region guarding the panic region, if any, so it can be written
+pub(crate) fn release() {
+    let number = GUARD.load(Ordering::Acquire);
+    if number == NO_GUARD {
+        return;
+    }
+
+    let mpu = unsafe { &*MPU::PTR };
+    unsafe {
+        mpu.rnr.write(u32::from(number));
+        #[cfg(not(armv8m))]
+        mpu.rasr.write(0);
+        #[cfg(armv8m)]
+        mpu.rlar.write(0);
+    }
+
+    cortex_m::asm::dsb();
+    cortex_m::asm::isb();
+}
+
+/// Enable the MPU region guarding the panic region again, if any, once it was written outside of a
+/// panic
+pub(crate) fn restore() {
+    let number = GUARD.load(Ordering::Acquire);
+    if number == NO_GUARD {
+        return;
+    }
+
+    // `enable_mpu_guard` already checked that the region can be covered
+    let (rbar, attributes) = match registers(Region::guarded()) {
+        Some(registers) => registers,
+        None => return,
+    };
+
+    let mpu = unsafe { &*MPU::PTR };
+    unsafe {
+        mpu.rnr.write(u32::from(number));
+        mpu.rbar.write(rbar);
+        #[cfg(not(armv8m))]
+        mpu.rasr.write(attributes);
+        #[cfg(armv8m)]
+        mpu.rlar.write(attributes);
+    }
+
+    cortex_m::asm::dsb();
+    cortex_m::asm::isb();
+}
+
+/// The base address and attributes registers of an MPU region covering `region`, with no access
+/// allowed, if it can
+#[cfg(not(armv8m))]
+fn registers(region: Region) -> Option<(u32, u32)> {
+    let start = region.start as u32;
+    let len = region.len as u32;
+
+    if len < 32 || !len.is_power_of_two() || !start.is_multiple_of(len) {
+        return None;
+    }
+
+    // RASR: XN, AP = no access, SIZE = log2(len) - 1, ENABLE
+    let size = len.trailing_zeros() - 1;
+
+    Some((start, 1 << 28 | size << 1 | 1))
+}
+
+/// The base address and limit registers of an MPU region covering `region`, with read only access
+/// for privileged code, if it can
+#[cfg(armv8m)]
+fn registers(region: Region) -> Option<(u32, u32)> {
+    let start = region.start as u32;
+    let len = region.len as u32;
+
+    if len == 0 || !start.is_multiple_of(32) || !len.is_multiple_of(32) {
+        return None;
+    }
+
+    // RBAR: AP = read only by privileged code, XN. RLAR: limit of the last 32 byte block, ENABLE
+    let limit = start + len - 32;
+
+    Some((start | 0b10 << 1 | 1, limit | 1))
+}
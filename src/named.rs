@@ -0,0 +1,129 @@
+//! Named panic regions, located by their own linker symbols, so several images can each own their
+//! slot
+
+use crate::ram::RamSink;
+use crate::region::Region;
+use crate::{peek_message_bytes, take_message_bytes};
+
+/// A panic region located by its own linker symbols, as declared by [`named_region!`](crate::named_region)
+#[derive(Clone, Copy)]
+pub struct NamedRegion {
+    region: Region,
+}
+
+impl NamedRegion {
+    /// The region between `start` and `end`
+    ///
+    /// # Safety
+    ///
+    /// The memory between `start` and `end` must not be used for anything else than panic
+    /// messages, and must not be initialized on boot
+    pub unsafe fn from_bounds(start: *mut u8, end: *mut u8) -> Self {
+        Self {
+            region: Region {
+                start,
+                len: (end as usize).saturating_sub(start as usize),
+            },
+        }
+    }
+
+    /// A region that never holds a panic message, used on hosted targets where there are no
+    /// linker symbols
+    pub const fn empty() -> Self {
+        Self {
+            region: Region {
+                start: core::ptr::null_mut(),
+                len: 0,
+            },
+        }
+    }
+
+    /// A sink storing the panic message in this region
+    pub fn sink(self) -> RamSink {
+        RamSink::in_region(self.region)
+    }
+
+    /// Get the panic message persisted to this region, if any.
+    /// This method may possibly not return valid UTF-8 if the message
+    /// was truncated before the end of a full UTF-8 character. Care must
+    /// be taken before treating this as a proper &str.
+    ///
+    /// If a message existed, this function will only return the value once
+    /// (subsequent calls will return None)
+    pub fn get_panic_message_bytes(self) -> Option<&'static [u8]> {
+        take_message_bytes(self.region)
+    }
+
+    /// Get the panic message persisted to this region, if any, without
+    /// consuming it: it is returned again by later calls, until it is
+    /// retrieved with `get_panic_message_bytes`
+    pub fn peek_panic_message_bytes(self) -> Option<&'static [u8]> {
+        peek_message_bytes(self.region)
+    }
+
+    /// Get the panic message persisted to this region, if any. If any invalid
+    /// UTF-8 characters occur, the message will be truncated before the
+    /// first error.
+    ///
+    /// If a message existed, this function will only return the value once
+    /// (subsequent calls will return None)
+    #[cfg(feature = "utf8")]
+    pub fn get_panic_message_utf8(self) -> Option<&'static str> {
+        crate::bytes_to_utf8(self.get_panic_message_bytes()?)
+    }
+}
+
+/// Declare a module `$name` giving access to the panic region between linker symbols
+/// `_panic_dump_$name_start` and `_panic_dump_$name_end`;
+///
+/// ``` ignore
+/// panic_persist::named_region!(bootloader);
+///
+/// if let Some(msg) = bootloader::get_panic_message_bytes() {
+///     // The bootloader panicked
+/// }
+/// ```
+///
+/// The module holds `region()`, returning the [`NamedRegion`], as well as `sink()` and
+/// `get_panic_message_bytes()`. On hosted targets, the region is empty.
+#[macro_export]
+macro_rules! named_region {
+    ($vis:vis $name:ident) => {
+        $vis mod $name {
+            /// The panic region of this module
+            #[cfg(target_os = "none")]
+            pub fn region() -> $crate::NamedRegion {
+                extern "C" {
+                    #[link_name = concat!("_panic_dump_", stringify!($name), "_start")]
+                    static mut START: u8;
+                    #[link_name = concat!("_panic_dump_", stringify!($name), "_end")]
+                    static mut END: u8;
+                }
+
+                unsafe {
+                    $crate::NamedRegion::from_bounds(
+                        core::ptr::addr_of_mut!(START),
+                        core::ptr::addr_of_mut!(END),
+                    )
+                }
+            }
+
+            /// The panic region of this module, which is empty on hosted targets
+            #[cfg(not(target_os = "none"))]
+            pub fn region() -> $crate::NamedRegion {
+                $crate::NamedRegion::empty()
+            }
+
+            /// A sink storing the panic message in the panic region of this module
+            pub fn sink() -> $crate::RamSink {
+                region().sink()
+            }
+
+            /// Get the panic message persisted to the panic region of this module, if any, as
+            /// with [`NamedRegion::get_panic_message_bytes`]($crate::NamedRegion::get_panic_message_bytes)
+            pub fn get_panic_message_bytes() -> Option<&'static [u8]> {
+                region().get_panic_message_bytes()
+            }
+        }
+    };
+}
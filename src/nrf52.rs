@@ -0,0 +1,85 @@
+//! nRF52 specific support
+
+#[cfg(feature = "nrf52-gpregret")]
+use core::sync::atomic::{AtomicU8, Ordering};
+
+#[cfg(feature = "nrf52-retention")]
+use crate::region::Region;
+
+/// Bits to set in `GPREGRET` after a panic
+#[cfg(feature = "nrf52-gpregret")]
+static GPREGRET_BITS: AtomicU8 = AtomicU8::new(0);
+
+/// Bits to set in `GPREGRET2` after a panic
+#[cfg(feature = "nrf52-gpregret")]
+static GPREGRET2_BITS: AtomicU8 = AtomicU8::new(0);
+
+/// Set the bits that are set in the `GPREGRET` and `GPREGRET2` retained
+/// registers when a panic is persisted, so a bootloader can detect that the
+/// previous boot panicked without knowing the layout of the panic region.
+///
+/// The bits are OR'd into the current value of the registers. By default, no
+/// bits are set.
+#[cfg(feature = "nrf52-gpregret")]
+pub fn set_gpregret_bits(gpregret: u8, gpregret2: u8) {
+    GPREGRET_BITS.store(gpregret, Ordering::Relaxed);
+    GPREGRET2_BITS.store(gpregret2, Ordering::Relaxed);
+}
+
+/// Set the configured bits in `GPREGRET` and `GPREGRET2`
+#[cfg(all(feature = "nrf52-gpregret", target_os = "none"))]
+pub(crate) fn signal_panic() {
+    const GPREGRET: *mut u32 = 0x4000_051C as *mut u32;
+    const GPREGRET2: *mut u32 = 0x4000_0520 as *mut u32;
+
+    for (reg, bits) in [(GPREGRET, &GPREGRET_BITS), (GPREGRET2, &GPREGRET2_BITS)] {
+        let bits = u32::from(bits.load(Ordering::Relaxed));
+        if bits != 0 {
+            unsafe { reg.write_volatile(reg.read_volatile() | bits) };
+        }
+    }
+}
+
+/// There are no retained registers on hosted targets
+#[cfg(all(feature = "nrf52-gpregret", not(target_os = "none")))]
+pub(crate) fn signal_panic() {}
+
+/// Enable the retention of the RAM sections holding the panic region, so the
+/// panic message survives System OFF (and the pin reset or wakeup leaving it).
+///
+/// This sets the matching `SnRETENTION` bits of the `RAM[n].POWER` registers:
+/// RAM0 to RAM7 are made of two 4K sections each, and RAM8 (nRF52840 only)
+/// of six 32K sections. Call this at startup, before entering System OFF.
+#[cfg(feature = "nrf52-retention")]
+pub fn nrf52_retain_region() {
+    const RAM_START: usize = 0x2000_0000;
+    const RAM0_POWERSET: usize = 0x4000_0904;
+
+    let region = Region::panic_dump();
+    let start = (region.start as usize).saturating_sub(RAM_START);
+    let end = start + region.len;
+
+    let mut offset = start;
+    while offset < end {
+        // Find the block and section of the byte at `offset`, and the start of the next section
+        let (block, section, next) = if offset < 0x1_0000 {
+            (offset / 0x2000, (offset % 0x2000) / 0x1000, (offset & !0xFFF) + 0x1000)
+        } else {
+            let offset8 = offset - 0x1_0000;
+            (8, offset8 / 0x8000, 0x1_0000 + (offset8 & !0x7FFF) + 0x8000)
+        };
+
+        retain(RAM0_POWERSET + block * 0x10, 1 << (16 + section));
+        offset = next;
+    }
+}
+
+/// Set `bits` in the `RAM[n].POWERSET` register at `powerset`
+#[cfg(all(feature = "nrf52-retention", target_os = "none"))]
+fn retain(powerset: usize, bits: u32) {
+    unsafe { (powerset as *mut u32).write_volatile(bits) };
+}
+
+/// There are no RAM power registers on hosted targets
+#[cfg(all(feature = "nrf52-retention", not(target_os = "none")))]
+fn retain(_powerset: usize, _bits: u32) {}
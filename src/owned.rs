@@ -0,0 +1,45 @@
+//! Copies of the panic message into owned `heapless` buffers
+
+#[cfg(feature = "utf8")]
+use heapless::String;
+use heapless::Vec;
+
+use crate::copy_panic_message;
+
+/// Get the panic message from the last boot, if any, by copying it into a `heapless::Vec`, so it
+/// can be kept to be sent later on. The message is truncated to `N` bytes if it does not fit.
+///
+/// If a message existed, this function will only return the value once
+/// (subsequent calls will return None)
+pub fn get_panic_message_vec<const N: usize>() -> Option<Vec<u8, N>> {
+    copy_panic_message(|bytes| {
+        let mut vec = Vec::new();
+        vec.extend_from_slice(&bytes[..bytes.len().min(N)]).ok();
+
+        vec
+    })
+}
+
+/// Get the panic message from the last boot, if any, by copying it into a `heapless::String`, so
+/// it can be kept to be sent later on. As with `get_panic_message_utf8`, the message is truncated
+/// before its first invalid UTF-8 character, and it is truncated before the first character that
+/// does not fit in `N` bytes.
+///
+/// If a message existed, this function will only return the value once
+/// (subsequent calls will return None)
+#[cfg(feature = "utf8")]
+pub fn get_panic_message_string<const N: usize>() -> Option<String<N>> {
+    copy_panic_message(|bytes| {
+        let text = crate::bytes_to_utf8(bytes)?;
+
+        let mut len = text.len().min(N);
+        while !text.is_char_boundary(len) {
+            len -= 1;
+        }
+
+        let mut string = String::new();
+        string.push_str(&text[..len]).ok();
+
+        Some(string)
+    })?
+}
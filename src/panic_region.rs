@@ -0,0 +1,103 @@
+//! Panic region owning its buffer, with its size as a type level constant
+
+use core::cell::UnsafeCell;
+use core::mem::MaybeUninit;
+
+use crate::location::LOCATION_LEN;
+use crate::ram::RamSink;
+use crate::region::Region;
+use crate::{peek_message_bytes, take_message_bytes, HEADER_LEN};
+
+/// A panic region of `N` bytes (header included), owning its buffer. It is meant to be a static
+/// that is not initialized on boot, such as one in a `.uninit` section;
+///
+/// ``` ignore
+/// #[link_section = ".uninit.PANIC_REGION"]
+/// static PANIC_REGION: PanicRegion<1024> = PanicRegion::new();
+///
+/// // The region must hold messages of at least 512 bytes
+/// const _: () = assert!(PanicRegion::<1024>::MAX_MESSAGE_LEN >= 512);
+/// ```
+///
+/// Messages are written to the region through its sink, and retrieved with its own
+/// `get_panic_message_*` functions. With the `runtime-region` feature, the region can also be
+/// installed as the panic region of the crate.
+#[repr(C, align(4))]
+pub struct PanicRegion<const N: usize> {
+    buf: UnsafeCell<MaybeUninit<[u8; N]>>,
+}
+
+impl<const N: usize> Default for PanicRegion<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// The buffer is only accessed through raw pointers, as with the other panic regions
+unsafe impl<const N: usize> Sync for PanicRegion<N> {}
+
+impl<const N: usize> PanicRegion<N> {
+    /// The longest message the region can hold
+    pub const MAX_MESSAGE_LEN: usize = N - HEADER_LEN - LOCATION_LEN;
+
+    /// Create the region. This fails to compile if the region can't hold the header and the
+    /// location block
+    pub const fn new() -> Self {
+        assert!(
+            N >= HEADER_LEN + LOCATION_LEN,
+            "the panic region can't hold the 16 byte header and the 16 byte location"
+        );
+
+        Self {
+            buf: UnsafeCell::new(MaybeUninit::uninit()),
+        }
+    }
+
+    /// A sink storing the panic message in this region
+    pub fn sink(&'static self) -> RamSink {
+        RamSink::in_region(self.region())
+    }
+
+    /// Get the panic message persisted to this region, if any.
+    /// This method may possibly not return valid UTF-8 if the message
+    /// was truncated before the end of a full UTF-8 character. Care must
+    /// be taken before treating this as a proper &str.
+    ///
+    /// If a message existed, this function will only return the value once
+    /// (subsequent calls will return None)
+    pub fn get_panic_message_bytes(&'static self) -> Option<&'static [u8]> {
+        take_message_bytes(self.region())
+    }
+
+    /// Get the panic message persisted to this region, if any, without
+    /// consuming it: it is returned again by later calls, until it is
+    /// retrieved with `get_panic_message_bytes`
+    pub fn peek_panic_message_bytes(&'static self) -> Option<&'static [u8]> {
+        peek_message_bytes(self.region())
+    }
+
+    /// Get the panic message persisted to this region, if any. If any invalid
+    /// UTF-8 characters occur, the message will be truncated before the
+    /// first error.
+    ///
+    /// If a message existed, this function will only return the value once
+    /// (subsequent calls will return None)
+    #[cfg(feature = "utf8")]
+    pub fn get_panic_message_utf8(&'static self) -> Option<&'static str> {
+        crate::bytes_to_utf8(self.get_panic_message_bytes()?)
+    }
+
+    /// Use this region as the panic region of the crate, as with `init_with_region`
+    #[cfg(feature = "runtime-region")]
+    pub fn install(&'static self) {
+        crate::region::set_runtime_region(self.buf.get().cast::<u8>(), N);
+    }
+
+    /// The memory of this region
+    fn region(&'static self) -> Region {
+        Region {
+            start: self.buf.get().cast::<u8>(),
+            len: N,
+        }
+    }
+}
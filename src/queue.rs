@@ -0,0 +1,88 @@
+//! Sink appending the panic messages to a `sequential-storage` queue in flash, keeping a history of
+//! the last panics
+
+use core::cmp::min;
+use core::fmt::Write as _;
+use core::future::Future;
+use core::panic::Location;
+use core::task::{Context, Poll, Waker};
+
+use embedded_storage_async::nor_flash::NorFlash;
+use sequential_storage::cache::CacheImpl;
+use sequential_storage::queue::QueueStorage;
+
+use crate::sink::{Formatter, PanicSink, WriteStatus};
+
+/// A [`PanicSink`] appending the panic message to a [`sequential-storage`] queue, where the oldest
+/// messages are dropped once the queue is full. The message is buffered in the sink, which holds
+/// messages of up to `N` bytes, and pushed once it is complete.
+///
+/// The queue is accessed from panic context, so the flash must not rely on interrupts: its futures
+/// are polled until they complete.
+///
+/// [`sequential-storage`]: https://crates.io/crates/sequential-storage
+pub struct QueueSink<S: NorFlash, C: CacheImpl<()>, const N: usize> {
+    queue: QueueStorage<S, C>,
+    buf: [u8; N],
+    len: usize,
+    /// Whether bytes of the message were dropped, as they did not fit in the buffer
+    truncated: bool,
+    /// Whether the message could not be pushed to the queue
+    failed: bool,
+}
+
+impl<S: NorFlash, C: CacheImpl<()>, const N: usize> QueueSink<S, C, N> {
+    /// Create a sink appending to `queue`
+    pub const fn new(queue: QueueStorage<S, C>) -> Self {
+        Self {
+            queue,
+            buf: [0; N],
+            len: 0,
+            truncated: false,
+            failed: false,
+        }
+    }
+
+    /// Give back the queue
+    pub fn release(self) -> QueueStorage<S, C> {
+        self.queue
+    }
+}
+
+impl<S: NorFlash, C: CacheImpl<()>, const N: usize> PanicSink for QueueSink<S, C, N> {
+    fn location(&mut self, location: &Location<'_>) {
+        writeln!(Formatter(self), "panicked at {}:", location).ok();
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        let len = min(N - self.len, bytes.len());
+        self.truncated |= len < bytes.len();
+
+        self.buf[self.len..self.len + len].copy_from_slice(&bytes[..len]);
+        self.len += len;
+    }
+
+    fn finalize(&mut self) {
+        self.failed = block_on(self.queue.push(&self.buf[..self.len], true)).is_err();
+    }
+
+    fn status(&self) -> WriteStatus {
+        WriteStatus {
+            written: self.len,
+            truncated: self.truncated,
+            failed: self.failed,
+        }
+    }
+}
+
+/// Poll `future` until it completes. There is nothing else to run in panic context
+fn block_on<F: Future>(future: F) -> F::Output {
+    let mut future = core::pin::pin!(future);
+    let mut cx = Context::from_waker(Waker::noop());
+
+    loop {
+        if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+            return output;
+        }
+    }
+}
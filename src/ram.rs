@@ -0,0 +1,549 @@
+//! Sink that stores the formatted panic message in the panic region
+
+use core::cmp::min;
+use core::panic::Location;
+#[cfg(not(feature = "aligned-writes"))]
+use core::sync::atomic::{compiler_fence, Ordering};
+
+use crate::crc::Crc32;
+use crate::fields::Fields;
+use crate::header::Header;
+use crate::location::write_prefix;
+use crate::region::Region;
+use crate::sink::{PanicSink, WriteStatus};
+use crate::{HEADER_LEN, RECORD_MAGIC};
+
+/// The default [`PanicSink`], storing the message in the panic region of this crate, where it is
+/// retrieved by the `get_panic_message_*` functions. The location of the panic is stored in its
+/// own fields, in front of the message
+pub struct RamSink {
+    region: Region,
+    /// Bytes of the payload written so far, the location block included
+    offset: usize,
+    /// Whether the location block was written
+    started: bool,
+    crc: Crc32,
+    /// Whether bytes of the message were dropped, as they did not fit in the region
+    truncated: bool,
+    /// Offset of the text of the message in the payload, after the location block and the fields
+    #[cfg(feature = "keep-tail")]
+    text_start: usize,
+    /// Offset in the text of the oldest byte, which the next byte that does not fit overwrites
+    #[cfg(feature = "keep-tail")]
+    cursor: usize,
+    /// Whether the region already holds the record of the same panic, whose repeats are counted
+    /// instead
+    #[cfg(feature = "dedupe")]
+    repeat: bool,
+    /// Whether the region is the next slot of the ring of the panic region, so repeats are looked
+    /// for in the newest record instead
+    #[cfg(all(feature = "dedupe", feature = "panic-ring"))]
+    ring: bool,
+    /// Bytes of the message word that is not complete yet
+    #[cfg(feature = "aligned-writes")]
+    pending: [u8; 4],
+}
+
+impl RamSink {
+    /// Create a sink that stores the message in the panic region of the current core
+    #[cfg(not(feature = "panic-ring"))]
+    pub fn new() -> Self {
+        Self::in_region(Region::current())
+    }
+
+    /// Create a sink that stores the message in the panic region, in the slot of the ring after the
+    /// newest record
+    #[cfg(feature = "panic-ring")]
+    pub fn new() -> Self {
+        let sink = Self::in_region(crate::ring::next_slot());
+
+        #[cfg(feature = "dedupe")]
+        let sink = Self { ring: true, ..sink };
+
+        sink
+    }
+
+    /// Create a sink that stores the message at the start of `region`
+    pub(crate) fn in_region(region: Region) -> Self {
+        Self {
+            region,
+            offset: 0,
+            started: false,
+            crc: Crc32::new(),
+            truncated: false,
+            #[cfg(feature = "keep-tail")]
+            text_start: 0,
+            #[cfg(feature = "keep-tail")]
+            cursor: 0,
+            #[cfg(feature = "dedupe")]
+            repeat: false,
+            #[cfg(all(feature = "dedupe", feature = "panic-ring"))]
+            ring: false,
+            #[cfg(feature = "aligned-writes")]
+            pending: [0; 4],
+        }
+    }
+
+    /// Start the payload with the location block of a panic at `line` and `column` of `file`, and
+    /// with the fields returned by `fields`, unless it was already started. The fields and the file
+    /// name are left out if they do not fit in the region
+    pub(crate) fn begin<'f>(
+        &mut self,
+        line: u32,
+        column: u32,
+        file: &[u8],
+        fields: impl FnOnce() -> Fields<'f>,
+    ) {
+        if self.started {
+            return;
+        }
+        self.started = true;
+
+        #[cfg(feature = "dedupe")]
+        {
+            let last = self.last_record();
+            if crate::dedupe::is_repeat(last, line, column, file) {
+                self.region = last;
+                self.repeat = true;
+                return;
+            }
+        }
+
+        let max_len = self.max_payload_len();
+        let fields = fields();
+        write_prefix(line, column, file, &fields, max_len, |bytes| {
+            self.append(bytes)
+        });
+
+        #[cfg(feature = "keep-tail")]
+        {
+            self.text_start = self.offset;
+        }
+    }
+
+    /// The region holding the record a new panic may be a repeat of: the one being written to, or
+    /// the newest record of the ring, rather than the oldest one it overwrites
+    #[cfg(feature = "dedupe")]
+    fn last_record(&self) -> Region {
+        #[cfg(feature = "panic-ring")]
+        if self.ring {
+            if let Some(newest) = crate::ring::newest() {
+                return newest;
+            }
+        }
+
+        self.region
+    }
+
+    /// The longest payload the region can hold
+    fn max_payload_len(&self) -> usize {
+        let len = self.region.len.saturating_sub(HEADER_LEN);
+
+        // Only whole words of the region are used
+        #[cfg(feature = "aligned-writes")]
+        let len = len & !3;
+
+        len
+    }
+
+    /// Store `word` at `offset` in the region, which must be a multiple of 4, with a single
+    /// aligned 32 bit write
+    #[cfg(feature = "aligned-writes")]
+    fn store_word(&self, offset: usize, word: [u8; 4]) {
+        unsafe {
+            self.region
+                .start
+                .add(offset)
+                .cast::<u32>()
+                .write_volatile(u32::from_ne_bytes(word))
+        };
+    }
+}
+
+impl Default for RamSink {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PanicSink for RamSink {
+    fn location(&mut self, location: &Location<'_>) {
+        self.begin(
+            location.line(),
+            location.column(),
+            location.file().as_bytes(),
+            Fields::current,
+        );
+    }
+
+    fn write(&mut self, data: &[u8]) {
+        self.begin(0, 0, &[], Fields::current);
+
+        #[cfg(feature = "dedupe")]
+        if self.repeat {
+            return;
+        }
+
+        self.append(data);
+    }
+
+    fn finalize(&mut self) {
+        self.begin(0, 0, &[], Fields::current);
+
+        #[cfg(feature = "dedupe")]
+        if self.repeat {
+            crate::dedupe::count_repeat(self.region);
+            return;
+        }
+
+        self.commit();
+    }
+
+    fn status(&self) -> WriteStatus {
+        WriteStatus {
+            written: self.offset,
+            truncated: self.truncated,
+            // The header does not fit in the region
+            failed: self.region.len < HEADER_LEN,
+        }
+    }
+}
+
+/// Output the formatted panic string into RAM. The header is only written by `commit`, so a
+/// message that was only partly written when the device reset or lost power is never returned
+#[cfg(not(feature = "aligned-writes"))]
+impl RamSink {
+    /// Append `data` to the payload
+    fn append(&mut self, data: &[u8]) {
+        // Get the data about the string that is being written now
+        let len = data.len();
+
+        // Obtain info about the panic dump region, which must at least be able to hold the header
+        let start_ptr = self.region.start;
+        if self.region.len < HEADER_LEN {
+            return;
+        }
+        let max_len_str = self.max_payload_len();
+
+        // If we have written the full length of the region, we can't write any
+        // more. This could happen with multiple writes with this implementation
+        if self.offset >= max_len_str {
+            self.truncated |= len > 0;
+            #[cfg(feature = "keep-tail")]
+            self.overwrite_oldest(data);
+            return;
+        }
+
+        // We should write the size of the string, or the amount of space
+        // we have remaining, whichever is less
+        let str_len = min(max_len_str - self.offset, len);
+        self.truncated |= str_len < len;
+
+        unsafe {
+            // Invalidate the previous message first, it is being overwritten
+            if self.offset == 0 {
+                start_ptr.cast::<u32>().write_unaligned(0x00000000);
+                compiler_fence(Ordering::SeqCst);
+            }
+
+            // Write the string to RAM
+            core::ptr::copy(
+                data.as_ptr() as *mut u8,
+                start_ptr.add(HEADER_LEN + self.offset),
+                str_len,
+            );
+
+            // Increment the offset so later writes will be appended
+            self.offset += str_len;
+            self.crc.update(&data[..str_len]);
+        };
+
+        #[cfg(feature = "keep-tail")]
+        self.overwrite_oldest(&data[str_len..]);
+    }
+
+    /// Write the header, with the total size and the CRC of the payload, and the magic word for
+    /// later detection
+    fn commit(&mut self) {
+        if self.region.len < HEADER_LEN {
+            return;
+        }
+
+        #[cfg(feature = "keep-tail")]
+        self.unwrap_tail();
+
+        // The magic word is written last, so the record is only valid once it is complete
+        let header = Header::new(RECORD_MAGIC, self.offset as u32, self.crc.finish())
+            .truncated(self.truncated);
+        unsafe { header.write(self.region.start) };
+    }
+}
+
+/// Output the formatted panic string into RAM, only using aligned 32 bit writes. The header is
+/// only written by `commit`
+#[cfg(feature = "aligned-writes")]
+impl RamSink {
+    /// Append `data` to the payload
+    fn append(&mut self, data: &[u8]) {
+        // Obtain info about the panic dump region, which must at least be able to hold the header
+        if self.region.len < HEADER_LEN {
+            return;
+        }
+        let max_len_str = self.max_payload_len();
+
+        // Invalidate the previous message first, it is being overwritten
+        if self.offset == 0 {
+            self.store_word(0, [0; 4]);
+        }
+
+        // We should write the size of the string, or the amount of space
+        // we have remaining, whichever is less
+        let str_len = min(max_len_str.saturating_sub(self.offset), data.len());
+        self.truncated |= str_len < data.len();
+        self.crc.update(&data[..str_len]);
+
+        for byte in &data[..str_len] {
+            self.pending[self.offset % 4] = *byte;
+            self.offset += 1;
+
+            // Store every word as soon as it is complete
+            if self.offset.is_multiple_of(4) {
+                self.store_word(HEADER_LEN + self.offset - 4, self.pending);
+                self.pending = [0; 4];
+            }
+        }
+
+        #[cfg(feature = "keep-tail")]
+        self.overwrite_oldest(&data[str_len..]);
+    }
+
+    /// Store the last, incomplete, word of the payload (padded with zeroes), and then the header
+    fn commit(&mut self) {
+        if self.region.len < HEADER_LEN {
+            return;
+        }
+
+        #[cfg(feature = "keep-tail")]
+        self.unwrap_tail();
+
+        if !self.offset.is_multiple_of(4) {
+            self.store_word(HEADER_LEN + self.offset - self.offset % 4, self.pending);
+        }
+
+        // The magic word is the first word of the header, which is stored last
+        let header = Header::new(RECORD_MAGIC, self.offset as u32, self.crc.finish())
+            .truncated(self.truncated)
+            .to_words();
+        for (index, word) in header.iter().enumerate().rev() {
+            self.store_word(index * 4, word.to_ne_bytes());
+        }
+    }
+}
+
+/// Keeping the end of a message that does not fit, instead of its start. Once the region is full,
+/// the text of the message is used as a ring, whose oldest bytes are overwritten, and which is
+/// turned back into a plain text by `commit`
+#[cfg(feature = "keep-tail")]
+impl RamSink {
+    /// The byte at `offset` of the payload, which was written already
+    fn payload_byte(&self, offset: usize) -> u8 {
+        #[cfg(not(feature = "aligned-writes"))]
+        let byte = unsafe { self.region.start.add(HEADER_LEN + offset).read_volatile() };
+
+        #[cfg(feature = "aligned-writes")]
+        let byte = self.load_word(offset - offset % 4)[offset % 4];
+
+        byte
+    }
+
+    /// Overwrite the byte at `offset` of the payload, which was written already
+    fn set_payload_byte(&mut self, offset: usize, byte: u8) {
+        #[cfg(not(feature = "aligned-writes"))]
+        unsafe {
+            self.region
+                .start
+                .add(HEADER_LEN + offset)
+                .write_volatile(byte)
+        };
+
+        // The word holding the byte is read, and written back with a single aligned write
+        #[cfg(feature = "aligned-writes")]
+        {
+            let mut word = self.load_word(offset - offset % 4);
+            word[offset % 4] = byte;
+            self.store_word(HEADER_LEN + offset - offset % 4, word);
+        }
+    }
+
+    /// The word at `offset` of the payload, which must be a multiple of 4, read with a single
+    /// aligned 32 bit read
+    #[cfg(feature = "aligned-writes")]
+    fn load_word(&self, offset: usize) -> [u8; 4] {
+        let word = unsafe {
+            self.region
+                .start
+                .add(HEADER_LEN + offset)
+                .cast::<u32>()
+                .read_volatile()
+        };
+
+        word.to_ne_bytes()
+    }
+
+    /// The number of bytes of the ring, the text of the message once the region is full
+    fn ring_len(&self) -> usize {
+        self.max_payload_len().saturating_sub(self.text_start)
+    }
+
+    /// Overwrite the oldest bytes of the ring with `data`, which did not fit in the region
+    fn overwrite_oldest(&mut self, data: &[u8]) {
+        let ring_len = self.ring_len();
+        if ring_len == 0 {
+            return;
+        }
+
+        for byte in data {
+            self.set_payload_byte(self.text_start + self.cursor, *byte);
+            self.cursor = (self.cursor + 1) % ring_len;
+        }
+    }
+
+    /// Reverse the bytes of the payload from `start` to `end`
+    fn reverse(&mut self, mut start: usize, mut end: usize) {
+        while start + 1 < end {
+            end -= 1;
+
+            let byte = self.payload_byte(start);
+            self.set_payload_byte(start, self.payload_byte(end));
+            self.set_payload_byte(end, byte);
+
+            start += 1;
+        }
+    }
+
+    /// Turn the ring back into the text of the message, starting with its oldest byte. The start
+    /// of a character that was overwritten is dropped, and the CRC is computed again over the
+    /// whole payload
+    fn unwrap_tail(&mut self) {
+        if !self.truncated || self.ring_len() == 0 {
+            return;
+        }
+
+        // Rotate the ring in place, so the oldest byte comes first
+        let (start, end) = (self.text_start, self.offset);
+        let oldest = start + self.cursor;
+        self.reverse(start, oldest);
+        self.reverse(oldest, end);
+        self.reverse(start, end);
+        self.cursor = 0;
+
+        let skip = (start..min(start + 3, end))
+            .take_while(|offset| self.payload_byte(*offset) & 0xC0 == 0x80)
+            .count();
+        for offset in start..end - skip {
+            self.set_payload_byte(offset, self.payload_byte(offset + skip));
+        }
+        self.offset -= skip;
+
+        // The last, incomplete, word is stored again by `commit`, padded with zeroes
+        #[cfg(feature = "aligned-writes")]
+        if !self.offset.is_multiple_of(4) {
+            self.pending = self.load_word(self.offset - self.offset % 4);
+            for byte in &mut self.pending[self.offset % 4..] {
+                *byte = 0;
+            }
+        }
+
+        self.crc = Crc32::new();
+        for offset in 0..self.offset {
+            let byte = self.payload_byte(offset);
+            self.crc.update(&[byte]);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::record::{decode, DecodeError};
+
+    /// Memory holding a region, aligned as the panic region is
+    #[repr(align(4))]
+    struct Memory([u8; 128]);
+
+    impl Memory {
+        fn region(&mut self, len: usize) -> Region {
+            Region {
+                start: self.0.as_mut_ptr(),
+                len,
+            }
+        }
+    }
+
+    /// The text of a message longer than the regions of the tests
+    const LONG: &[u8] = b"0123456789abcdefghijklmnopqrstuvwxyz0123456789abcdefghijklmnopqrstuvwxyz";
+
+    #[test]
+    fn record_round_trip() {
+        let mut memory = Memory([0; 128]);
+        let mut sink = RamSink::in_region(memory.region(128));
+        let location = Location::caller();
+        sink.location(location);
+        sink.write(b"index out of ");
+        sink.write(b"bounds");
+        sink.finalize();
+        let status = sink.status();
+        assert!(!status.failed && !status.truncated);
+
+        let record = decode(&memory.0, RECORD_MAGIC).unwrap();
+        let stored = record.location().unwrap();
+        assert_eq!(stored.file(), location.file());
+        assert_eq!(stored.line(), location.line());
+        assert_eq!(stored.column(), location.column());
+        assert_eq!(record.message(), b"index out of bounds");
+        assert_eq!(record.occurrences(), 1);
+        assert!(!record.was_truncated());
+    }
+
+    #[test]
+    fn partly_written_record_is_not_valid() {
+        let mut memory = Memory([0; 128]);
+        let mut sink = RamSink::in_region(memory.region(128));
+        sink.write(b"index out of bounds");
+
+        assert!(matches!(
+            decode(&memory.0, RECORD_MAGIC),
+            Err(DecodeError::NoRecord)
+        ));
+    }
+
+    #[test]
+    fn long_message_is_truncated() {
+        let mut memory = Memory([0; 128]);
+        let mut sink = RamSink::in_region(memory.region(64));
+        sink.write(LONG);
+        sink.finalize();
+        assert!(sink.status().truncated);
+
+        let record = decode(&memory.0[..64], RECORD_MAGIC).unwrap();
+        assert!(record.was_truncated());
+        let message = record.message();
+        assert!(!message.is_empty() && message.len() < LONG.len());
+
+        // The start of the message is kept, or its end with the `keep-tail` feature
+        #[cfg(not(feature = "keep-tail"))]
+        assert!(LONG.starts_with(message));
+        #[cfg(feature = "keep-tail")]
+        assert!(LONG.ends_with(message));
+    }
+
+    #[test]
+    fn region_too_short_for_the_header_fails() {
+        let mut memory = Memory([0; 128]);
+        let mut sink = RamSink::in_region(memory.region(HEADER_LEN - 1));
+        sink.write(b"index out of bounds");
+        sink.finalize();
+
+        assert!(memory.0.iter().all(|&byte| byte == 0));
+    }
+}
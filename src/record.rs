@@ -0,0 +1,598 @@
+//! Records read from the panic region of another image
+
+use core::fmt::{self, Write};
+
+#[cfg(feature = "backtrace")]
+use crate::backtrace::Backtrace;
+use crate::crc::crc32;
+use crate::header::{Header, FORMAT_VERSION, TRUNCATED};
+use crate::heap::HeapStats;
+use crate::location::{PanicLocation, Payload};
+#[cfg(feature = "sticky")]
+use crate::pending_regions;
+use crate::region::Region;
+#[cfg(feature = "registers")]
+use crate::registers::Registers;
+#[cfg(feature = "postcard")]
+use crate::report::PanicReport;
+#[cfg(feature = "stack-pointers")]
+use crate::stack_pointers::StackPointers;
+use crate::{find_pending, has_magic, HEADER_LEN, MAGIC, RECORD_MAGIC, SECURE_MAGIC};
+
+/// A panic record, as returned by [`get_panic_record`], or read from a region by [`read_record_at`]
+#[derive(Clone, Copy)]
+pub struct Record<'a> {
+    core: u8,
+    flags: u8,
+    repeats: u8,
+    location: Option<PanicLocation<'a>>,
+    build_id: Option<&'a [u8]>,
+    timestamp: Option<u64>,
+    context: Option<&'a [u8]>,
+    exception: Option<u16>,
+    heap_stats: Option<HeapStats>,
+    #[cfg(feature = "registers")]
+    registers: Option<Registers>,
+    #[cfg(feature = "backtrace")]
+    backtrace: Option<Backtrace>,
+    #[cfg(feature = "stack-pointers")]
+    stack_pointers: Option<StackPointers>,
+    #[cfg(feature = "defmt-payload")]
+    defmt: bool,
+    #[cfg(feature = "postcard")]
+    postcard: bool,
+    message: &'a [u8],
+}
+
+impl<'a> Record<'a> {
+    /// The id of the core that wrote the record, as returned by the source registered with
+    /// [`set_core_id_source`], or read from the core itself
+    ///
+    /// [`set_core_id_source`]: crate::set_core_id_source
+    pub fn core(&self) -> u8 {
+        self.core
+    }
+
+    /// The number of times the panic happened: with the `dedupe` feature, a panic at the same
+    /// location as the record, before it was read, only counts one more occurrence
+    pub fn occurrences(&self) -> u32 {
+        u32::from(self.repeats) + 1
+    }
+
+    /// Whether the message did not fit in the region, so only its start was stored, or its end
+    /// with the `keep-tail` feature
+    pub fn was_truncated(&self) -> bool {
+        self.flags & TRUNCATED != 0
+    }
+
+    /// The location of the panic, if it was known. This is stored in its own fields, so panics
+    /// can be grouped by location without parsing the message
+    pub fn location(&self) -> Option<PanicLocation<'a>> {
+        self.location
+    }
+
+    /// The id of the firmware that panicked, as registered with [`set_build_id`], if any
+    ///
+    /// [`set_build_id`]: crate::set_build_id
+    pub fn build_id(&self) -> Option<&'a [u8]> {
+        self.build_id
+    }
+
+    /// The time of the panic, as returned by the source registered with [`set_time_source`], if
+    /// any
+    ///
+    /// [`set_time_source`]: crate::set_time_source
+    pub fn timestamp(&self) -> Option<u64> {
+        self.timestamp
+    }
+
+    /// The context of the application at the time of the panic, as registered with
+    /// [`set_panic_context`], if any
+    ///
+    /// [`set_panic_context`]: crate::set_panic_context
+    pub fn context(&self) -> Option<&'a [u8]> {
+        self.context
+    }
+
+    /// The number of the exception the panic happened in, as read from the IPSR register on
+    /// Cortex-M targets: 0 in thread mode, 1 to 15 for the system exceptions (such as 3 for
+    /// HardFault), and 16 and up for the interrupts, where 16 is IRQ 0
+    pub fn exception(&self) -> Option<u16> {
+        self.exception
+    }
+
+    /// The statistics of the heap allocator at the time of the panic, as returned by the source
+    /// registered with [`set_heap_stats_source`], if any
+    ///
+    /// [`set_heap_stats_source`]: crate::set_heap_stats_source
+    pub fn heap_stats(&self) -> Option<HeapStats> {
+        self.heap_stats
+    }
+
+    /// The registers captured on entry of the panic handler, if they were
+    #[cfg(feature = "registers")]
+    pub fn registers(&self) -> Option<Registers> {
+        self.registers
+    }
+
+    /// The return addresses walked from the frame pointers by the panic handler, if they were
+    #[cfg(feature = "backtrace")]
+    pub fn backtrace(&self) -> Option<Backtrace> {
+        self.backtrace
+    }
+
+    /// The stack pointers and CONTROL register read by the panic handler, if they were
+    #[cfg(feature = "stack-pointers")]
+    pub fn stack_pointers(&self) -> Option<StackPointers> {
+        self.stack_pointers
+    }
+
+    /// Whether the message is the encoded `defmt` frame of a `defmt::panic!`, which is decoded on
+    /// the host with the ELF file, rather than text
+    #[cfg(feature = "defmt-payload")]
+    pub fn is_defmt(&self) -> bool {
+        self.defmt
+    }
+
+    /// The report serialized with `postcard` as the message of the record, if it holds one
+    #[cfg(feature = "postcard")]
+    pub fn report(&self) -> Option<PanicReport<'a>> {
+        match self.postcard {
+            true => PanicReport::from_bytes(self.message),
+            false => None,
+        }
+    }
+
+    /// The panic message of the record.
+    /// This method may possibly not return valid UTF-8 if the message
+    /// was truncated before the end of a full UTF-8 character. Care must
+    /// be taken before treating this as a proper &str.
+    pub fn message(&self) -> &'a [u8] {
+        self.message
+    }
+
+    /// The panic message of the record. If any invalid UTF-8 characters
+    /// occur, the message will be truncated before the first error.
+    #[cfg(feature = "utf8")]
+    pub fn message_utf8(&self) -> Option<&'a str> {
+        crate::bytes_to_utf8(self.message)
+    }
+}
+
+/// Writes the message of the record, with its invalid UTF-8 sequences replaced with U+FFFD, followed
+/// by ` [truncated]` if it did not fit in the region
+impl fmt::Display for Record<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        Lossy(self.message).fmt(f)?;
+
+        if self.was_truncated() {
+            f.write_str(" [truncated]")?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Writes bytes as text, with their invalid UTF-8 sequences replaced with U+FFFD
+struct Lossy<'a>(&'a [u8]);
+
+impl fmt::Display for Lossy<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for chunk in self.0.utf8_chunks() {
+            f.write_str(chunk.valid())?;
+            if !chunk.invalid().is_empty() {
+                f.write_char(char::REPLACEMENT_CHARACTER)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Serializes the record as a struct of its fields, named after the methods returning them, with
+/// `truncated` for `was_truncated`. The message is serialized as text, with its invalid UTF-8
+/// sequences replaced with U+FFFD, and the report of the `postcard` feature, if the record holds
+/// one, as `report`. The fields of the disabled features are left out
+#[cfg(feature = "serde")]
+impl serde::Serialize for Record<'_> {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::SerializeStruct;
+
+        let len = 10;
+        #[cfg(feature = "registers")]
+        let len = len + 1;
+        #[cfg(feature = "backtrace")]
+        let len = len + 1;
+        #[cfg(feature = "stack-pointers")]
+        let len = len + 1;
+        #[cfg(feature = "defmt-payload")]
+        let len = len + 1;
+        #[cfg(feature = "postcard")]
+        let len = len + 1;
+
+        let mut record = serializer.serialize_struct("Record", len)?;
+        record.serialize_field("core", &self.core)?;
+        record.serialize_field("occurrences", &self.occurrences())?;
+        record.serialize_field("truncated", &self.was_truncated())?;
+        record.serialize_field("location", &self.location)?;
+        record.serialize_field("build_id", &self.build_id)?;
+        record.serialize_field("timestamp", &self.timestamp)?;
+        record.serialize_field("context", &self.context)?;
+        record.serialize_field("exception", &self.exception)?;
+        record.serialize_field("heap_stats", &self.heap_stats)?;
+        #[cfg(feature = "registers")]
+        record.serialize_field("registers", &self.registers)?;
+        #[cfg(feature = "backtrace")]
+        record.serialize_field("backtrace", &self.backtrace)?;
+        #[cfg(feature = "stack-pointers")]
+        record.serialize_field("stack_pointers", &self.stack_pointers)?;
+        #[cfg(feature = "defmt-payload")]
+        record.serialize_field("defmt", &self.defmt)?;
+        #[cfg(feature = "postcard")]
+        record.serialize_field("report", &self.report())?;
+        record.serialize_field("message", &Lossy(self.message))?;
+        record.end()
+    }
+}
+
+/// Serializes the bytes as text, as written by `Display`
+#[cfg(feature = "serde")]
+impl serde::Serialize for Lossy<'_> {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+/// Writes the message of the record, as with `Display`
+#[cfg(feature = "defmt")]
+impl defmt::Format for Record<'_> {
+    fn format(&self, f: defmt::Formatter<'_>) {
+        for chunk in self.message.utf8_chunks() {
+            defmt::write!(f, "{=str}", chunk.valid());
+            if !chunk.invalid().is_empty() {
+                defmt::write!(f, "\u{FFFD}");
+            }
+        }
+
+        if self.was_truncated() {
+            defmt::write!(f, " [truncated]");
+        }
+    }
+}
+
+/// Read the panic record in the region of `len` bytes at `ptr`, if it holds one. This is meant to
+/// read the region of another image, whose location is only known at runtime, such as from a
+/// configuration block shared with it.
+///
+/// The header is checked as for the region of this image: the region must be able to hold it, it
+/// must start with the magic word, and the message must fit in the region. The record is left in
+/// place, so it is returned again until the region is written to.
+///
+/// # Safety
+///
+/// `ptr` must point to `len` bytes of memory that are valid for reads, and which are not written to
+/// while the returned record is in use.
+pub unsafe fn read_record_at<'a>(ptr: *const u8, len: usize) -> Option<Record<'a>> {
+    let region = Region {
+        start: ptr as *mut u8,
+        len,
+    };
+
+    if ptr.is_null() {
+        return None;
+    }
+
+    record_in(region, MAGIC)
+}
+
+/// Get the panic record from the last boot, if any, with the message and all the fields that were
+/// stored along with it, such as the location of the panic.
+///
+/// The record is consumed as with [`get_panic_message_bytes`], which is called to that end: if a
+/// record existed, this function will only return it once (subsequent calls will return None),
+/// unless the `sticky` feature is enabled
+///
+/// [`get_panic_message_bytes`]: crate::get_panic_message_bytes
+pub fn get_panic_record() -> Option<Record<'static>> {
+    let record = record_in(find_pending()?, RECORD_MAGIC)?;
+
+    // The record is the first one found by `get_panic_message_bytes` as well
+    crate::get_panic_message_bytes();
+
+    Some(record)
+}
+
+/// Get all the panic records from the last boots, in the order they are retrieved in by
+/// [`get_panic_record`], such as the records of both cores with the `rp2040` feature, so they can
+/// all be drained at once.
+///
+/// Each record is consumed as it is returned, unless the `sticky` feature is enabled
+pub fn panic_records() -> PanicRecords {
+    PanicRecords {
+        #[cfg(feature = "sticky")]
+        next: 0,
+    }
+}
+
+/// The panic records from the last boots, as returned by [`panic_records`]
+pub struct PanicRecords {
+    /// The number of records that were returned, and left in place
+    #[cfg(feature = "sticky")]
+    next: usize,
+}
+
+impl Iterator for PanicRecords {
+    type Item = Record<'static>;
+
+    fn next(&mut self) -> Option<Record<'static>> {
+        // The record returned last was consumed, so the next one is retrieved first
+        #[cfg(not(feature = "sticky"))]
+        let record = get_panic_record();
+
+        #[cfg(feature = "sticky")]
+        let record = {
+            let region = pending_regions().nth(self.next)?;
+            self.next += 1;
+            record_in(region, RECORD_MAGIC)
+        };
+
+        record
+    }
+}
+
+/// The ways a copy of a record can fail to decode, as reported by [`decode_record`]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DecodeError {
+    /// The bytes can't hold the header of a record
+    TooShort {
+        /// The number of bytes
+        len: usize,
+    },
+    /// The bytes don't start with the magic word of a record: the region was never written, the
+    /// record was already retrieved, or the region was wiped (or lost power) since
+    NoRecord,
+    /// The record was written with another layout of the header, such as by an older or a newer
+    /// version of this crate, which can't be parsed
+    WrongVersion {
+        /// The version of the layout, as stored in the header
+        version: u8,
+    },
+    /// The length stored in the header does not fit in the bytes, or the lengths of the location
+    /// block do not fit in the payload
+    CorruptLength {
+        /// The length of the payload, as stored in the header
+        len: usize,
+    },
+    /// The payload of the record does not match the CRC of its header, as it was overwritten
+    BadChecksum,
+}
+
+/// Decode the panic record held by `bytes`, a copy of the region starting with the header of the
+/// record, such as a dump received from a device. This only parses `bytes`, so it is meant for
+/// host tools as well as for the firmware, and the record borrows them.
+///
+/// The header is checked as for the region of this image: it must start with the magic word, the
+/// message must fit in `bytes`, and match the CRC of the header. The records persisted by a
+/// TrustZone secure image, with the `trustzone` feature, are decoded as well. A dump of the whole memory holding
+/// the region starts with what is reserved in front of the records, which is skipped first, as
+/// given by [`RegionDescriptor::record_offset`]
+///
+/// [`RegionDescriptor::record_offset`]: crate::RegionDescriptor::record_offset
+pub fn decode_record(bytes: &[u8]) -> Result<Record<'_>, DecodeError> {
+    match decode(bytes, MAGIC) {
+        Err(DecodeError::NoRecord) => decode(bytes, SECURE_MAGIC),
+        decoded => decoded,
+    }
+}
+
+/// The record of `region`, if it holds one marked with `magic`, which is left in place
+fn record_in(region: Region, magic: u32) -> Option<Record<'static>> {
+    if !has_magic(region, magic) {
+        return None;
+    }
+
+    // The region is valid for reads of its whole length
+    let bytes = unsafe { core::slice::from_raw_parts(region.start, region.len) };
+
+    decode(bytes, magic).ok()
+}
+
+/// The record held by `bytes`, if it is marked with `magic`, it has the layout of this version of
+/// the crate, its length fits in `bytes` and it matches the CRC of the header
+pub(crate) fn decode(bytes: &[u8], magic: u32) -> Result<Record<'_>, DecodeError> {
+    let header = match bytes.get(..HEADER_LEN) {
+        Some(header) => {
+            let mut copy = [0; HEADER_LEN];
+            copy.copy_from_slice(header);
+            Header::from_bytes(copy)
+        }
+        None => return Err(DecodeError::TooShort { len: bytes.len() }),
+    };
+
+    if header.magic != magic {
+        return Err(DecodeError::NoRecord);
+    }
+
+    if header.version != FORMAT_VERSION {
+        return Err(DecodeError::WrongVersion {
+            version: header.version,
+        });
+    }
+
+    let len = header.len as usize;
+    let payload = bytes[HEADER_LEN..]
+        .get(..len)
+        .ok_or(DecodeError::CorruptLength { len })?;
+    if header.crc != crc32(payload) {
+        return Err(DecodeError::BadChecksum);
+    }
+
+    let payload = Payload::parse(payload).ok_or(DecodeError::CorruptLength { len })?;
+
+    Ok(Record {
+        core: header.core,
+        flags: header.flags,
+        repeats: header.repeats,
+        location: payload.location,
+        build_id: payload.fields.build_id,
+        timestamp: payload.fields.timestamp,
+        context: payload.fields.context,
+        exception: payload.fields.exception,
+        heap_stats: payload.fields.heap_stats,
+        #[cfg(feature = "registers")]
+        registers: payload.fields.registers,
+        #[cfg(feature = "backtrace")]
+        backtrace: payload.fields.backtrace,
+        #[cfg(feature = "stack-pointers")]
+        stack_pointers: payload.fields.stack_pointers,
+        #[cfg(feature = "defmt-payload")]
+        defmt: payload.fields.defmt,
+        #[cfg(feature = "postcard")]
+        postcard: payload.fields.postcard,
+        message: payload.text,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fields::Fields;
+    use crate::location::write_prefix;
+
+    /// Encode the record of a panic at `src/main.rs:12:5` into `bytes`, as the panic handler
+    /// stores it, marked with `magic`, and return its length
+    fn encode(bytes: &mut [u8; 128], magic: u32) -> usize {
+        let fields = Fields {
+            build_id: Some(b"v1.2"),
+            timestamp: Some(42),
+            ..Fields::default()
+        };
+        let message = b"index out of bounds";
+
+        let mut len = HEADER_LEN;
+        write_prefix(12, 5, b"src/main.rs", &fields, 128 - HEADER_LEN, |piece| {
+            bytes[len..len + piece.len()].copy_from_slice(piece);
+            len += piece.len();
+        });
+        bytes[len..len + message.len()].copy_from_slice(message);
+        len += message.len();
+
+        let header = Header {
+            magic,
+            version: FORMAT_VERSION,
+            core: 1,
+            flags: TRUNCATED,
+            repeats: 2,
+            len: (len - HEADER_LEN) as u32,
+            crc: crc32(&bytes[HEADER_LEN..len]),
+        };
+        bytes[..HEADER_LEN].copy_from_slice(&header.to_bytes());
+
+        len
+    }
+
+    /// Store the CRC of the payload of the record of `len` bytes in its header again
+    fn update_crc(bytes: &mut [u8; 128], len: usize) {
+        let crc = crc32(&bytes[HEADER_LEN..len]);
+        bytes[12..HEADER_LEN].copy_from_slice(&crc.to_le_bytes());
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut bytes = [0; 128];
+        let len = encode(&mut bytes, MAGIC);
+
+        // Trailing bytes, such as the rest of the region, are ignored
+        let record = decode_record(&bytes).unwrap();
+        let location = record.location().unwrap();
+
+        assert_eq!(location.file(), "src/main.rs");
+        assert_eq!(location.line(), 12);
+        assert_eq!(location.column(), 5);
+        assert_eq!(record.core(), 1);
+        assert_eq!(record.occurrences(), 3);
+        assert!(record.was_truncated());
+        assert_eq!(record.build_id(), Some(&b"v1.2"[..]));
+        assert_eq!(record.timestamp(), Some(42));
+        assert_eq!(record.context(), None);
+        assert_eq!(record.message(), b"index out of bounds");
+
+        assert!(decode_record(&bytes[..len]).is_ok());
+    }
+
+    #[test]
+    fn secure_record() {
+        let mut bytes = [0; 128];
+        encode(&mut bytes, SECURE_MAGIC);
+
+        assert_eq!(
+            decode_record(&bytes).unwrap().message(),
+            b"index out of bounds"
+        );
+    }
+
+    #[test]
+    fn too_short() {
+        let mut bytes = [0; 128];
+        encode(&mut bytes, MAGIC);
+
+        assert_eq!(
+            decode_record(&bytes[..HEADER_LEN - 1]).err(),
+            Some(DecodeError::TooShort {
+                len: HEADER_LEN - 1
+            })
+        );
+    }
+
+    #[test]
+    fn no_record() {
+        assert_eq!(decode_record(&[0; 128]).err(), Some(DecodeError::NoRecord));
+    }
+
+    #[test]
+    fn wrong_version() {
+        let mut bytes = [0; 128];
+        encode(&mut bytes, MAGIC);
+        bytes[4] = FORMAT_VERSION + 1;
+
+        assert_eq!(
+            decode_record(&bytes).err(),
+            Some(DecodeError::WrongVersion {
+                version: FORMAT_VERSION + 1
+            })
+        );
+    }
+
+    #[test]
+    fn corrupt_length() {
+        let mut bytes = [0; 128];
+        let len = encode(&mut bytes, MAGIC);
+
+        // The payload does not fit in the bytes
+        assert_eq!(
+            decode_record(&bytes[..len - 1]).err(),
+            Some(DecodeError::CorruptLength {
+                len: len - HEADER_LEN
+            })
+        );
+
+        // The file name does not fit in the payload, although the CRC matches
+        bytes[HEADER_LEN + 8] = 0xFF;
+        update_crc(&mut bytes, len);
+        assert_eq!(
+            decode_record(&bytes).err(),
+            Some(DecodeError::CorruptLength {
+                len: len - HEADER_LEN
+            })
+        );
+    }
+
+    #[test]
+    fn bad_checksum() {
+        let mut bytes = [0; 128];
+        let len = encode(&mut bytes, MAGIC);
+        bytes[len - 1] ^= 1;
+
+        assert_eq!(decode_record(&bytes).err(), Some(DecodeError::BadChecksum));
+    }
+}
@@ -0,0 +1,805 @@
+//! Location of the regions used to persist panic messages
+
+#[cfg(feature = "runtime-region")]
+use core::mem::MaybeUninit;
+#[cfg(all(test, feature = "runtime-region"))]
+use core::sync::atomic::AtomicBool;
+#[cfg(feature = "runtime-region")]
+use core::sync::atomic::{AtomicPtr, AtomicUsize, Ordering};
+
+/// Start of the buffer given to `init_with_region`, or null until then
+#[cfg(feature = "runtime-region")]
+static RUNTIME_START: AtomicPtr<u8> = AtomicPtr::new(core::ptr::null_mut());
+
+/// Length of the buffer given to `init_with_region`
+#[cfg(feature = "runtime-region")]
+static RUNTIME_LEN: AtomicUsize = AtomicUsize::new(0);
+
+/// Use `region` as the panic region, instead of the one given by the linker symbols. This must be
+/// called on startup, before reading the panic message of the last boot, and with the same buffer
+/// on every boot: a static that is not initialized on boot, such as one in a `.uninit` section;
+///
+/// ``` ignore
+/// #[link_section = ".uninit.PANIC_REGION"]
+/// static mut PANIC_REGION: [MaybeUninit<u8>; 1024] = [MaybeUninit::uninit(); 1024];
+///
+/// panic_persist::init_with_region(unsafe { &mut *core::ptr::addr_of_mut!(PANIC_REGION) });
+/// ```
+///
+/// Panics happening before this is called are not persisted.
+#[cfg(feature = "runtime-region")]
+pub fn init_with_region(region: &'static mut [MaybeUninit<u8>]) {
+    set_runtime_region(region.as_mut_ptr().cast::<u8>(), region.len());
+}
+
+/// Use the `len` bytes at `start` as the panic region
+#[cfg(feature = "runtime-region")]
+pub(crate) fn set_runtime_region(start: *mut u8, len: usize) {
+    RUNTIME_START.store(core::ptr::null_mut(), Ordering::Release);
+    RUNTIME_LEN.store(len, Ordering::Relaxed);
+    RUNTIME_START.store(start, Ordering::Release);
+}
+
+#[cfg(all(
+    feature = "runtime-region",
+    any(
+        feature = "esp32-rtc-fast",
+        feature = "esp32-rtc-slow",
+        feature = "samd5x-bkupram",
+        feature = "saml21-bkupram",
+        feature = "stm32f4-bkpsram",
+        feature = "stm32h7-bkpsram",
+        feature = "uncached-alias"
+    )
+))]
+compile_error!("The `runtime-region` feature replaces the other ways to locate the panic region");
+
+#[cfg(any(
+    all(
+        any(feature = "stm32f4-bkpsram", feature = "stm32h7-bkpsram"),
+        any(
+            feature = "samd5x-bkupram",
+            feature = "saml21-bkupram",
+            feature = "esp32-rtc-fast",
+            feature = "esp32-rtc-slow"
+        )
+    ),
+    all(
+        any(feature = "samd5x-bkupram", feature = "saml21-bkupram"),
+        any(feature = "esp32-rtc-fast", feature = "esp32-rtc-slow")
+    )
+))]
+compile_error!(
+    "only one of the `stm32*-bkpsram`, `sam*-bkupram` and `esp32-rtc-*` features can locate the \
+     panic region"
+);
+
+#[cfg(all(
+    feature = "uncached-alias",
+    any(
+        feature = "esp32-rtc-fast",
+        feature = "esp32-rtc-slow",
+        feature = "samd5x-bkupram",
+        feature = "saml21-bkupram",
+        feature = "stm32f4-bkpsram",
+        feature = "stm32h7-bkpsram"
+    )
+))]
+compile_error!("The `uncached-alias` feature only applies to the region located by linker symbols");
+
+/// A region of memory holding a header and a panic message
+#[derive(Clone, Copy)]
+pub(crate) struct Region {
+    pub(crate) start: *mut u8,
+    pub(crate) len: usize,
+}
+
+impl Region {
+    /// The region of this image
+    pub(crate) fn panic_dump() -> Self {
+        let region = Self::memory();
+
+        #[cfg(feature = "scratch-area")]
+        let region = region.before_scratch();
+
+        #[cfg(feature = "stack-dump")]
+        let region = region.before_stack_dump();
+
+        region.after_front_blocks()
+    }
+
+    /// The part of the memory holding the region which the MPU guards with the `mpu-guard`
+    /// feature: the records and the stack dump, but the blocks at the front of the memory, which
+    /// are written during normal operation, and the scratch area of the application
+    #[cfg(all(feature = "mpu-guard", cortex_m))]
+    pub(crate) fn guarded() -> Self {
+        let region = Self::memory();
+
+        #[cfg(feature = "scratch-area")]
+        let region = region.before_scratch();
+
+        region.after_front_blocks()
+    }
+
+    /// The rest of the region, after the blocks at the front of the memory holding it
+    fn after_front_blocks(self) -> Self {
+        let region = self;
+
+        #[cfg(feature = "multicore-lock")]
+        let region = region.after_claim_word();
+
+        #[cfg(feature = "panic-counter")]
+        let region = region.after_counter();
+
+        #[cfg(feature = "kv-store")]
+        let region = region.after_kv();
+
+        #[cfg(feature = "breadcrumbs")]
+        let region = region.after_breadcrumbs();
+
+        #[cfg(feature = "log")]
+        let region = region.after_logs();
+
+        #[cfg(feature = "watermark")]
+        let region = region.after_usage();
+
+        region
+    }
+
+    /// The memory holding the region of this image, from linker symbols _panic_dump_start and
+    /// _panic_dump_end
+    #[cfg(all(
+        target_os = "none",
+        not(any(
+            feature = "esp32-rtc-fast",
+            feature = "esp32-rtc-slow",
+            feature = "runtime-region",
+            feature = "samd5x-bkupram",
+            feature = "saml21-bkupram",
+            feature = "stm32f4-bkpsram",
+            feature = "stm32h7-bkpsram"
+        ))
+    ))]
+    pub(crate) fn memory() -> Self {
+        extern "C" {
+            static mut _panic_dump_start: u8;
+            static mut _panic_dump_end: u8;
+        }
+
+        let region = Self::from_bounds(
+            core::ptr::addr_of_mut!(_panic_dump_start),
+            core::ptr::addr_of_mut!(_panic_dump_end),
+        );
+
+        #[cfg(feature = "uncached-alias")]
+        let region = {
+            extern "C" {
+                static mut _panic_dump_alias: u8;
+            }
+
+            // Same region, accessed through its non-cacheable alias
+            Self {
+                start: core::ptr::addr_of_mut!(_panic_dump_alias),
+                ..region
+            }
+        };
+
+        region
+    }
+
+    /// The addresses of linker symbols _panic_dump_start and _panic_dump_end, when the memory is
+    /// located by them
+    #[cfg(all(
+        target_os = "none",
+        not(any(
+            feature = "esp32-rtc-fast",
+            feature = "esp32-rtc-slow",
+            feature = "runtime-region",
+            feature = "samd5x-bkupram",
+            feature = "saml21-bkupram",
+            feature = "stm32f4-bkpsram",
+            feature = "stm32h7-bkpsram"
+        ))
+    ))]
+    pub(crate) fn symbols() -> Option<(usize, usize)> {
+        extern "C" {
+            static _panic_dump_start: u8;
+            static _panic_dump_end: u8;
+        }
+
+        Some((
+            core::ptr::addr_of!(_panic_dump_start) as usize,
+            core::ptr::addr_of!(_panic_dump_end) as usize,
+        ))
+    }
+
+    /// The memory is not located by the linker symbols
+    #[cfg(not(all(
+        target_os = "none",
+        not(any(
+            feature = "esp32-rtc-fast",
+            feature = "esp32-rtc-slow",
+            feature = "runtime-region",
+            feature = "samd5x-bkupram",
+            feature = "saml21-bkupram",
+            feature = "stm32f4-bkpsram",
+            feature = "stm32h7-bkpsram"
+        ))
+    )))]
+    pub(crate) fn symbols() -> Option<(usize, usize)> {
+        None
+    }
+
+    /// The memory holding the region of this image, which is the STM32 backup SRAM
+    #[cfg(all(
+        target_os = "none",
+        any(feature = "stm32f4-bkpsram", feature = "stm32h7-bkpsram")
+    ))]
+    pub(crate) fn memory() -> Self {
+        crate::stm32::bkpsram()
+    }
+
+    /// The memory holding the region of this image, which is the SAM backup RAM
+    #[cfg(all(
+        target_os = "none",
+        any(feature = "samd5x-bkupram", feature = "saml21-bkupram")
+    ))]
+    pub(crate) fn memory() -> Self {
+        crate::sam::bkupram()
+    }
+
+    /// The memory holding the region of this image, which is placed in the ESP32 RTC memory
+    #[cfg(all(
+        target_os = "none",
+        any(feature = "esp32-rtc-fast", feature = "esp32-rtc-slow")
+    ))]
+    pub(crate) fn memory() -> Self {
+        crate::esp::rtc_region()
+    }
+
+    /// The region of the non-secure image, from linker symbols _panic_dump_ns_start and
+    /// _panic_dump_ns_end
+    #[cfg(all(feature = "trustzone", target_os = "none"))]
+    pub(crate) fn panic_dump_ns() -> Self {
+        extern "C" {
+            static mut _panic_dump_ns_start: u8;
+            static mut _panic_dump_ns_end: u8;
+        }
+
+        Self::from_bounds(
+            core::ptr::addr_of_mut!(_panic_dump_ns_start),
+            core::ptr::addr_of_mut!(_panic_dump_ns_end),
+        )
+    }
+
+    /// The region the panic message is moved to before the RAM is wiped, from linker symbols
+    /// _panic_rescue_start and _panic_rescue_end
+    #[cfg(all(feature = "rescue-region", target_os = "none"))]
+    pub(crate) fn rescue() -> Self {
+        extern "C" {
+            static mut _panic_rescue_start: u8;
+            static mut _panic_rescue_end: u8;
+        }
+
+        Self::from_bounds(
+            core::ptr::addr_of_mut!(_panic_rescue_start),
+            core::ptr::addr_of_mut!(_panic_rescue_end),
+        )
+    }
+
+    /// The memory holding the region of this image, which is the buffer given to
+    /// `init_with_region`, or an empty region until then
+    #[cfg(feature = "runtime-region")]
+    pub(crate) fn memory() -> Self {
+        let start = RUNTIME_START.load(Ordering::Acquire);
+
+        if start.is_null() {
+            return Self::empty();
+        }
+
+        Self {
+            start,
+            len: RUNTIME_LEN.load(Ordering::Relaxed),
+        }
+    }
+
+    /// There are no linker symbols on hosted targets, so an empty region is used instead, which
+    /// never holds a panic message
+    #[cfg(all(not(target_os = "none"), not(feature = "runtime-region")))]
+    pub(crate) fn memory() -> Self {
+        Self::empty()
+    }
+
+    /// There are no linker symbols on hosted targets, so an empty region is used instead, which
+    /// never holds a panic message
+    #[cfg(all(feature = "trustzone", not(target_os = "none")))]
+    pub(crate) fn panic_dump_ns() -> Self {
+        Self::empty()
+    }
+
+    /// There are no linker symbols on hosted targets, so an empty region is used instead, which
+    /// never holds a panic message
+    #[cfg(all(feature = "rescue-region", not(target_os = "none")))]
+    pub(crate) fn rescue() -> Self {
+        Self::empty()
+    }
+
+    /// A region that can't hold anything
+    #[cfg(any(
+        feature = "breadcrumbs",
+        feature = "kv-store",
+        feature = "log",
+        feature = "multicore-lock",
+        feature = "panic-counter",
+        feature = "runtime-region",
+        feature = "watermark",
+        not(target_os = "none")
+    ))]
+    fn empty() -> Self {
+        Self {
+            start: core::ptr::null_mut(),
+            len: 0,
+        }
+    }
+
+    /// The region the panic message of the current core is persisted to
+    #[cfg(not(feature = "rp2040"))]
+    pub(crate) fn current() -> Self {
+        Self::panic_dump()
+    }
+
+    /// The region the panic message of the current core is persisted to, which is the half of
+    /// the panic region belonging to that core
+    #[cfg(feature = "rp2040")]
+    pub(crate) fn current() -> Self {
+        Self::panic_dump().core_slot(crate::rp2040::core_id())
+    }
+
+    /// The region holding the record persisted last by the current core, which is the slot of the
+    /// newest record with the `panic-ring` feature
+    #[cfg(any(feature = "postcard", feature = "rescue-region", feature = "watermark"))]
+    pub(crate) fn last_record() -> Self {
+        #[cfg(feature = "panic-ring")]
+        if let Some(newest) = crate::ring::newest() {
+            return newest;
+        }
+
+        Self::current()
+    }
+
+    /// The part of the region belonging to `core`, when it is shared between several cores. Each
+    /// part is a multiple of 4 bytes, to keep the headers aligned
+    #[cfg(feature = "rp2040")]
+    pub(crate) fn core_slot(self, core: usize) -> Self {
+        let len = (self.len / crate::rp2040::CORES) & !3;
+
+        Self {
+            start: self.start.wrapping_add(core * len),
+            len,
+        }
+    }
+
+    /// The claim word at the start of the memory holding the region, if it can hold it
+    #[cfg(feature = "multicore-lock")]
+    pub(crate) fn claim_word() -> Option<*mut u32> {
+        let memory = Self::memory();
+
+        if memory.len < 4 {
+            return None;
+        }
+
+        Some(memory.start.cast::<u32>())
+    }
+
+    /// The rest of the region, after the claim word
+    #[cfg(feature = "multicore-lock")]
+    fn after_claim_word(self) -> Self {
+        if self.len < 4 {
+            return Self::empty();
+        }
+
+        Self {
+            start: self.start.wrapping_add(4),
+            len: self.len - 4,
+        }
+    }
+
+    /// The counter block at the start of the memory holding the region, after the claim word, if
+    /// it can hold it
+    #[cfg(feature = "panic-counter")]
+    pub(crate) fn counter() -> Option<*mut u8> {
+        let memory = Self::memory();
+
+        #[cfg(feature = "multicore-lock")]
+        let memory = memory.after_claim_word();
+
+        if memory.len < crate::counter::COUNTER_LEN {
+            return None;
+        }
+
+        Some(memory.start)
+    }
+
+    /// The rest of the region, after the counter block
+    #[cfg(feature = "panic-counter")]
+    fn after_counter(self) -> Self {
+        if self.len < crate::counter::COUNTER_LEN {
+            return Self::empty();
+        }
+
+        Self {
+            start: self.start.wrapping_add(crate::counter::COUNTER_LEN),
+            len: self.len - crate::counter::COUNTER_LEN,
+        }
+    }
+
+    /// The key-value store at the start of the memory holding the region, after the claim word and
+    /// the counter block, if it can hold it
+    #[cfg(feature = "kv-store")]
+    pub(crate) fn kv() -> Option<*mut u8> {
+        let memory = Self::memory();
+
+        #[cfg(feature = "multicore-lock")]
+        let memory = memory.after_claim_word();
+
+        #[cfg(feature = "panic-counter")]
+        let memory = memory.after_counter();
+
+        if memory.len < crate::kv::KV_LEN {
+            return None;
+        }
+
+        Some(memory.start)
+    }
+
+    /// The rest of the region, after the key-value store
+    #[cfg(feature = "kv-store")]
+    fn after_kv(self) -> Self {
+        if self.len < crate::kv::KV_LEN {
+            return Self::empty();
+        }
+
+        Self {
+            start: self.start.wrapping_add(crate::kv::KV_LEN),
+            len: self.len - crate::kv::KV_LEN,
+        }
+    }
+
+    /// The ring of breadcrumbs at the start of the memory holding the region, after the claim
+    /// word, the counter block and the key-value store, if it can hold it
+    #[cfg(feature = "breadcrumbs")]
+    pub(crate) fn breadcrumbs() -> Option<*mut u8> {
+        let memory = Self::memory();
+
+        #[cfg(feature = "multicore-lock")]
+        let memory = memory.after_claim_word();
+
+        #[cfg(feature = "panic-counter")]
+        let memory = memory.after_counter();
+
+        #[cfg(feature = "kv-store")]
+        let memory = memory.after_kv();
+
+        if memory.len < crate::breadcrumbs::BREADCRUMBS_LEN {
+            return None;
+        }
+
+        Some(memory.start)
+    }
+
+    /// The rest of the region, after the ring of breadcrumbs
+    #[cfg(feature = "breadcrumbs")]
+    fn after_breadcrumbs(self) -> Self {
+        if self.len < crate::breadcrumbs::BREADCRUMBS_LEN {
+            return Self::empty();
+        }
+
+        Self {
+            start: self.start.wrapping_add(crate::breadcrumbs::BREADCRUMBS_LEN),
+            len: self.len - crate::breadcrumbs::BREADCRUMBS_LEN,
+        }
+    }
+
+    /// The ring of log records at the start of the memory holding the region, after the claim
+    /// word, the counter block, the key-value store and the ring of breadcrumbs, if it can hold it
+    #[cfg(feature = "log")]
+    pub(crate) fn logs() -> Option<*mut u8> {
+        let memory = Self::memory();
+
+        #[cfg(feature = "multicore-lock")]
+        let memory = memory.after_claim_word();
+
+        #[cfg(feature = "panic-counter")]
+        let memory = memory.after_counter();
+
+        #[cfg(feature = "kv-store")]
+        let memory = memory.after_kv();
+
+        #[cfg(feature = "breadcrumbs")]
+        let memory = memory.after_breadcrumbs();
+
+        if memory.len < crate::logs::LOGS_LEN {
+            return None;
+        }
+
+        Some(memory.start)
+    }
+
+    /// The rest of the region, after the ring of log records
+    #[cfg(feature = "log")]
+    fn after_logs(self) -> Self {
+        if self.len < crate::logs::LOGS_LEN {
+            return Self::empty();
+        }
+
+        Self {
+            start: self.start.wrapping_add(crate::logs::LOGS_LEN),
+            len: self.len - crate::logs::LOGS_LEN,
+        }
+    }
+
+    /// The usage block at the start of the memory holding the region, after the claim word, the
+    /// counter block, the key-value store and the rings of breadcrumbs and of log records, if it
+    /// can hold it
+    #[cfg(feature = "watermark")]
+    pub(crate) fn usage() -> Option<*mut u8> {
+        let memory = Self::memory();
+
+        #[cfg(feature = "multicore-lock")]
+        let memory = memory.after_claim_word();
+
+        #[cfg(feature = "panic-counter")]
+        let memory = memory.after_counter();
+
+        #[cfg(feature = "kv-store")]
+        let memory = memory.after_kv();
+
+        #[cfg(feature = "breadcrumbs")]
+        let memory = memory.after_breadcrumbs();
+
+        #[cfg(feature = "log")]
+        let memory = memory.after_logs();
+
+        if memory.len < crate::watermark::USAGE_LEN {
+            return None;
+        }
+
+        Some(memory.start)
+    }
+
+    /// The rest of the region, after the usage block
+    #[cfg(feature = "watermark")]
+    fn after_usage(self) -> Self {
+        if self.len < crate::watermark::USAGE_LEN {
+            return Self::empty();
+        }
+
+        Self {
+            start: self.start.wrapping_add(crate::watermark::USAGE_LEN),
+            len: self.len - crate::watermark::USAGE_LEN,
+        }
+    }
+
+    /// The scratch area of the application, at the end of the memory holding the region
+    #[cfg(feature = "scratch-area")]
+    pub(crate) fn scratch() -> Self {
+        let memory = Self::memory();
+        let len = core::cmp::min(crate::scratch::scratch_len(), memory.len);
+
+        Self {
+            start: memory.start.wrapping_add(memory.len - len),
+            len,
+        }
+    }
+
+    /// The rest of the region, before the scratch area
+    #[cfg(feature = "scratch-area")]
+    fn before_scratch(self) -> Self {
+        Self {
+            len: self.len.saturating_sub(crate::scratch::scratch_len()),
+            ..self
+        }
+    }
+
+    /// The area of the stack dump, at the end of the memory holding the region, before the scratch
+    /// area
+    #[cfg(feature = "stack-dump")]
+    pub(crate) fn stack_dump() -> Self {
+        let memory = Self::memory();
+
+        #[cfg(feature = "scratch-area")]
+        let memory = memory.before_scratch();
+
+        let len = core::cmp::min(crate::stack::area_len(), memory.len);
+
+        Self {
+            start: memory.start.wrapping_add(memory.len - len),
+            len,
+        }
+    }
+
+    /// The rest of the region, before the area of the stack dump
+    #[cfg(feature = "stack-dump")]
+    fn before_stack_dump(self) -> Self {
+        Self {
+            len: self.len.saturating_sub(crate::stack::area_len()),
+            ..self
+        }
+    }
+
+    /// The region between the `start` and `end` linker symbols, which the backends that are not
+    /// placed by the linker symbols may not need
+    #[cfg(target_os = "none")]
+    #[cfg_attr(
+        any(
+            feature = "esp32-rtc-fast",
+            feature = "esp32-rtc-slow",
+            feature = "runtime-region",
+            feature = "samd5x-bkupram",
+            feature = "saml21-bkupram",
+            feature = "stm32f4-bkpsram",
+            feature = "stm32h7-bkpsram"
+        ),
+        allow(dead_code)
+    )]
+    fn from_bounds(start: *mut u8, end: *mut u8) -> Self {
+        Self {
+            start,
+            len: (end as usize).saturating_sub(start as usize),
+        }
+    }
+}
+
+/// Memory the tests use as the panic region
+#[cfg(all(test, feature = "runtime-region"))]
+#[repr(align(4))]
+struct TestMemory([u8; 2048]);
+
+#[cfg(all(test, feature = "runtime-region"))]
+static mut TEST_MEMORY: TestMemory = TestMemory([0; 2048]);
+
+/// Whether a test uses the panic region, which is shared by all of them
+#[cfg(all(test, feature = "runtime-region"))]
+static TEST_LOCK: AtomicBool = AtomicBool::new(false);
+
+/// Lets the next test use the panic region, even if the current one failed
+#[cfg(all(test, feature = "runtime-region"))]
+struct TestUnlock;
+
+#[cfg(all(test, feature = "runtime-region"))]
+impl Drop for TestUnlock {
+    fn drop(&mut self) {
+        TEST_LOCK.store(false, Ordering::Release);
+    }
+}
+
+/// Run `test` with `len` bytes of memory as the panic region, filled with random looking content,
+/// as after a power cycle. The tests using the region are run one after the other
+#[cfg(all(test, feature = "runtime-region"))]
+pub(crate) fn with_test_region<R>(len: usize, test: impl FnOnce(*mut u8) -> R) -> R {
+    while TEST_LOCK
+        .compare_exchange_weak(false, true, Ordering::Acquire, Ordering::Relaxed)
+        .is_err()
+    {
+        core::hint::spin_loop();
+    }
+    let _unlock = TestUnlock;
+
+    let memory = unsafe { &mut (*core::ptr::addr_of_mut!(TEST_MEMORY)).0 };
+    let memory = &mut memory[..len];
+    memory.fill(0xA5);
+    set_runtime_region(memory.as_mut_ptr(), len);
+
+    test(memory.as_mut_ptr())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A region of `len` bytes, at an address that is never accessed
+    fn region(len: usize) -> Region {
+        Region {
+            start: 0x2000_0000 as *mut u8,
+            len,
+        }
+    }
+
+    /// The length of the blocks at the front of the memory, with the enabled features
+    fn front_len() -> usize {
+        #[allow(unused_mut)]
+        let mut len = 0;
+
+        #[cfg(feature = "multicore-lock")]
+        {
+            len += 4;
+        }
+        #[cfg(feature = "panic-counter")]
+        {
+            len += crate::counter::COUNTER_LEN;
+        }
+        #[cfg(feature = "kv-store")]
+        {
+            len += crate::kv::KV_LEN;
+        }
+        #[cfg(feature = "breadcrumbs")]
+        {
+            len += crate::breadcrumbs::BREADCRUMBS_LEN;
+        }
+        #[cfg(feature = "log")]
+        {
+            len += crate::logs::LOGS_LEN;
+        }
+        #[cfg(feature = "watermark")]
+        {
+            len += crate::watermark::USAGE_LEN;
+        }
+
+        len
+    }
+
+    #[test]
+    fn front_blocks_are_skipped() {
+        let rest = region(front_len() + 100).after_front_blocks();
+
+        assert_eq!(rest.start as usize, 0x2000_0000 + front_len());
+        assert_eq!(rest.len, 100);
+    }
+
+    #[test]
+    fn region_too_short_for_the_front_blocks_is_empty() {
+        if front_len() > 0 {
+            assert_eq!(region(front_len() - 1).after_front_blocks().len, 0);
+        }
+    }
+
+    #[cfg(not(feature = "runtime-region"))]
+    #[test]
+    fn hosted_region_is_empty() {
+        assert_eq!(Region::memory().len, 0);
+        assert_eq!(Region::panic_dump().len, 0);
+    }
+
+    #[cfg(feature = "runtime-region")]
+    #[test]
+    fn runtime_region_is_carved_in_order() {
+        with_test_region(front_len() + 256, |start| {
+            let mut offset = 0;
+            let mut next = |len| {
+                let at = start.wrapping_add(offset);
+                offset += len;
+                at
+            };
+
+            #[cfg(feature = "multicore-lock")]
+            assert_eq!(Region::claim_word(), Some(next(4).cast::<u32>()));
+            #[cfg(feature = "panic-counter")]
+            assert_eq!(Region::counter(), Some(next(crate::counter::COUNTER_LEN)));
+            #[cfg(feature = "kv-store")]
+            assert_eq!(Region::kv(), Some(next(crate::kv::KV_LEN)));
+            #[cfg(feature = "breadcrumbs")]
+            assert_eq!(
+                Region::breadcrumbs(),
+                Some(next(crate::breadcrumbs::BREADCRUMBS_LEN))
+            );
+            #[cfg(feature = "log")]
+            assert_eq!(Region::logs(), Some(next(crate::logs::LOGS_LEN)));
+            #[cfg(feature = "watermark")]
+            assert_eq!(Region::usage(), Some(next(crate::watermark::USAGE_LEN)));
+
+            let rest = Region::panic_dump();
+            assert_eq!(rest.start, next(0));
+            assert_eq!(rest.len, 256);
+        });
+    }
+
+    #[cfg(feature = "runtime-region")]
+    #[test]
+    fn runtime_region_is_empty_until_given() {
+        with_test_region(512, |_| {
+            set_runtime_region(core::ptr::null_mut(), 512);
+
+            assert_eq!(Region::memory().len, 0);
+        });
+    }
+}
@@ -0,0 +1,166 @@
+//! Snapshot of the CPU registers on entry of the panic handler
+
+#[cfg(cortex_m)]
+use core::cell::UnsafeCell;
+#[cfg(cortex_m)]
+use core::sync::atomic::{AtomicBool, Ordering};
+
+#[cfg(all(feature = "registers", target_os = "none", not(cortex_m)))]
+compile_error!("The `registers` feature is only supported on Cortex-M targets");
+
+/// Size of the registers, as stored in a record
+pub(crate) const REGISTERS_LEN: usize = 17 * 4;
+
+/// The Cortex-M core registers, as captured on entry of the panic handler and returned by
+/// [`Record::registers`].
+///
+/// `r4` to `r11` are preserved across calls, so they mostly hold the values of the code that
+/// panicked. `r0` to `r3` and `r12` are scratch registers, which hold the values of the panic
+/// machinery instead, and `sp`, `lr` and `pc` are the ones of the panic handler: `lr` returns into
+/// the `core::panicking` function that called it.
+///
+/// [`Record::registers`]: crate::Record::registers
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct Registers {
+    /// `r0` to `r12`
+    pub r: [u32; 13],
+    /// The stack pointer
+    pub sp: u32,
+    /// The link register
+    pub lr: u32,
+    /// The program counter
+    pub pc: u32,
+    /// The program status register
+    pub xpsr: u32,
+}
+
+impl Registers {
+    /// The registers in `words`, in the order of a snapshot
+    fn from_words(words: [u32; 17]) -> Self {
+        let mut r = [0; 13];
+        r.copy_from_slice(&words[..13]);
+
+        Self {
+            r,
+            sp: words[13],
+            lr: words[14],
+            pc: words[15],
+            xpsr: words[16],
+        }
+    }
+
+    /// The registers in the order of a snapshot
+    fn to_words(self) -> [u32; 17] {
+        let mut words = [0; 17];
+        words[..13].copy_from_slice(&self.r);
+        words[13..].copy_from_slice(&[self.sp, self.lr, self.pc, self.xpsr]);
+
+        words
+    }
+
+    /// The registers as stored in a record: the words of a snapshot, in little endian
+    pub(crate) fn to_bytes(self) -> [u8; REGISTERS_LEN] {
+        let mut bytes = [0; REGISTERS_LEN];
+
+        for (chunk, word) in bytes.chunks_exact_mut(4).zip(&self.to_words()) {
+            chunk.copy_from_slice(&word.to_le_bytes());
+        }
+
+        bytes
+    }
+
+    /// The registers stored in a record as `bytes`
+    pub(crate) fn from_bytes(bytes: &[u8; REGISTERS_LEN]) -> Self {
+        let mut words = [0; 17];
+
+        for (word, chunk) in words.iter_mut().zip(bytes.chunks_exact(4)) {
+            *word = u32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]);
+        }
+
+        Self::from_words(words)
+    }
+}
+
+/// The snapshot of the registers taken by `capture`, which is only written from panic context
+#[cfg(cortex_m)]
+struct Snapshot {
+    words: UnsafeCell<[u32; 17]>,
+    captured: AtomicBool,
+}
+
+// The words are only written by the panic handler, with interrupts disabled
+#[cfg(cortex_m)]
+unsafe impl Sync for Snapshot {}
+
+#[cfg(cortex_m)]
+static SNAPSHOT: Snapshot = Snapshot {
+    words: UnsafeCell::new([0; 17]),
+    captured: AtomicBool::new(false),
+};
+
+/// Take a snapshot of the registers. This is inlined into the panic handler, so it must be called
+/// before anything else touches the registers
+#[cfg(cortex_m)]
+#[inline(always)]
+pub(crate) fn capture() {
+    let words = SNAPSHOT.words.get();
+
+    // Only Thumb-1 instructions are used, so this also runs on ARMv6-M. `r0` is used as a scratch
+    // register once it was stored, and is restored from the stack, as the stack pointer is stored
+    // from before it was pushed
+    unsafe {
+        core::arch::asm!(
+            "str r0, [r3, #0]",
+            "str r1, [r3, #4]",
+            "str r2, [r3, #8]",
+            "str r3, [r3, #12]",
+            "str r4, [r3, #16]",
+            "str r5, [r3, #20]",
+            "str r6, [r3, #24]",
+            "str r7, [r3, #28]",
+            "push {{r0}}",
+            "mrs r0, xpsr",
+            "str r0, [r3, #64]",
+            "mov r0, r8",
+            "str r0, [r3, #32]",
+            "mov r0, r9",
+            "str r0, [r3, #36]",
+            "mov r0, r10",
+            "str r0, [r3, #40]",
+            "mov r0, r11",
+            "str r0, [r3, #44]",
+            "mov r0, r12",
+            "str r0, [r3, #48]",
+            "mov r0, sp",
+            "adds r0, #4",
+            "str r0, [r3, #52]",
+            "mov r0, lr",
+            "str r0, [r3, #56]",
+            "mov r0, pc",
+            "str r0, [r3, #60]",
+            "pop {{r0}}",
+            in("r3") words,
+        );
+    }
+
+    SNAPSHOT.captured.store(true, Ordering::Release);
+}
+
+/// The registers captured by the panic handler, if any
+#[cfg(cortex_m)]
+pub(crate) fn captured() -> Option<Registers> {
+    if !SNAPSHOT.captured.load(Ordering::Acquire) {
+        return None;
+    }
+
+    Some(Registers::from_words(unsafe {
+        SNAPSHOT.words.get().read_volatile()
+    }))
+}
+
+/// The registers are only captured on Cortex-M targets
+#[cfg(not(cortex_m))]
+pub(crate) fn captured() -> Option<Registers> {
+    None
+}
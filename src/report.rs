@@ -0,0 +1,172 @@
+//! Panic reports serialized with `postcard`, instead of the rendered panic message
+
+use core::fmt::{Display, Write};
+use core::panic::PanicInfo;
+
+use serde::{Deserialize, Serialize, Serializer};
+
+use crate::fields::Fields;
+use crate::sink::PanicSink;
+use crate::{take_message, RECORD_MAGIC};
+
+/// The most bytes a serialized report takes, the message is truncated to fit
+const REPORT_LEN: usize = 256;
+
+/// A panic report, as persisted with the `postcard` feature. The device and host tools share this
+/// type, so the report is deserialized with `postcard` on either side, from the bytes of the
+/// message of a record.
+///
+/// The fields are serialized in order, and new ones are only ever added at the end
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PanicReport<'a> {
+    /// The file the panic happened in, if it is known
+    pub file: Option<&'a str>,
+    /// The line of the panic, or 0 if it is unknown
+    pub line: u32,
+    /// The column of the panic, or 0 if it is unknown
+    pub column: u32,
+    /// The id of the core that panicked
+    pub core: u8,
+    /// The id of the firmware, as registered with [`set_build_id`]
+    ///
+    /// [`set_build_id`]: crate::set_build_id
+    pub build_id: Option<&'a [u8]>,
+    /// The time of the panic, as returned by the source registered with [`set_time_source`]
+    ///
+    /// [`set_time_source`]: crate::set_time_source
+    pub timestamp: Option<u64>,
+    /// The number of the exception the panic happened in, on Cortex-M targets
+    pub exception: Option<u16>,
+    /// The panic message, without its location, which is truncated for the report to fit in 256
+    /// bytes
+    pub message: &'a str,
+}
+
+impl<'a> PanicReport<'a> {
+    /// The report serialized in `bytes`, such as the message of a record, if it holds one
+    pub fn from_bytes(bytes: &'a [u8]) -> Option<Self> {
+        postcard::from_bytes(bytes).ok()
+    }
+}
+
+/// Get the panic report from the last boot, if any.
+///
+/// If a report existed, this function will only return the value once
+/// (subsequent calls will return None)
+pub fn get_panic_report() -> Option<PanicReport<'static>> {
+    let payload = take_message(crate::region::Region::last_record(), RECORD_MAGIC)?;
+    if !payload.fields.postcard {
+        return None;
+    }
+
+    // Once the report was retrieved, the region can be written again
+    #[cfg(feature = "multicore-lock")]
+    crate::lock::release();
+
+    PanicReport::from_bytes(payload.text)
+}
+
+/// The report as serialized by the panic handler, which renders the message while serializing it,
+/// in the same layout as [`PanicReport`]
+#[derive(Serialize)]
+struct Rendered<'a> {
+    file: Option<&'a str>,
+    line: u32,
+    column: u32,
+    core: u8,
+    build_id: Option<&'a [u8]>,
+    timestamp: Option<u64>,
+    exception: Option<u16>,
+    #[serde(serialize_with = "render")]
+    message: Truncated<'a>,
+}
+
+/// A message rendered to at most `max` bytes, cut at a character boundary
+struct Truncated<'a> {
+    message: &'a dyn Display,
+    max: usize,
+}
+
+/// Serialize the rendered message as a string
+fn render<S: Serializer>(message: &Truncated<'_>, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.collect_str(message)
+}
+
+impl Display for Truncated<'_> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        struct Limit<'a, 'b> {
+            out: &'a mut core::fmt::Formatter<'b>,
+            left: usize,
+        }
+
+        impl Write for Limit<'_, '_> {
+            fn write_str(&mut self, s: &str) -> core::fmt::Result {
+                let mut len = s.len().min(self.left);
+                while !s.is_char_boundary(len) {
+                    len -= 1;
+                }
+
+                self.left -= len;
+                self.out.write_str(&s[..len])
+            }
+        }
+
+        write!(
+            Limit {
+                out: f,
+                left: self.max,
+            },
+            "{}",
+            self.message
+        )
+    }
+}
+
+/// Whether the message of the current panic is a report, rather than a `defmt` frame
+pub(crate) fn pending() -> bool {
+    #[cfg(feature = "defmt-payload")]
+    let frame = crate::defmt_payload::pending();
+    #[cfg(not(feature = "defmt-payload"))]
+    let frame = false;
+
+    !frame
+}
+
+/// Serialize the report of the panic to `sink`, as its message, and tell whether it was written.
+/// It is not when the report does not fit in 256 bytes without the message
+pub(crate) fn write(sink: &mut dyn PanicSink, info: &PanicInfo) -> bool {
+    let fields = Fields::current();
+    let location = info.location();
+    let message = info.message();
+
+    let mut report = Rendered {
+        file: location.map(|location| location.file()),
+        line: location.map_or(0, |location| location.line()),
+        column: location.map_or(0, |location| location.column()),
+        core: crate::core_id::current(),
+        build_id: fields.build_id,
+        timestamp: fields.timestamp,
+        exception: fields.exception,
+        message: Truncated {
+            message: &message,
+            max: 0,
+        },
+    };
+
+    // The message comes last, and takes what is left of the buffer, less the two bytes its length
+    // takes at most, one of which is already taken by the length of the empty message
+    let mut buf = [0; REPORT_LEN];
+    let head = match postcard::to_slice(&report, &mut buf) {
+        Ok(head) => head.len(),
+        Err(_) => return false,
+    };
+    report.message.max = (REPORT_LEN + 1).saturating_sub(head + 2);
+
+    match postcard::to_slice(&report, &mut buf) {
+        Ok(bytes) => {
+            sink.write(bytes);
+            true
+        }
+        Err(_) => false,
+    }
+}
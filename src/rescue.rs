@@ -0,0 +1,66 @@
+//! Moving the panic message out of the way of bootloaders that wipe the RAM
+
+use crate::ram::RamSink;
+use crate::region::Region;
+use crate::sink::PanicSink;
+use crate::{find_message, HEADER_LEN, RECORD_MAGIC};
+
+#[cfg(feature = "rp2040")]
+compile_error!("The `rescue-region` feature can't be used with the `rp2040` feature");
+
+/// Copy the panic message of the last boot, if any, from the panic region to the rescue region
+/// given by the `_panic_rescue_start` and `_panic_rescue_end` linker symbols, where it is retrieved
+/// by `get_panic_message_bytes` once the panic region is wiped. The message is truncated if it
+/// does not fit in the rescue region.
+///
+/// The panic handler already copies the message when persisting it, so this is only needed when
+/// the panic region is wiped after the application started, such as by the startup code of a
+/// second stage bootloader. This must then be called as early as possible on boot, such as from
+/// the `#[pre_init]` function of `cortex-m-rt`. It does not use any static, so it can run before
+/// the RAM is initialized.
+pub fn rescue_panic_message() {
+    copy_message(Region::panic_dump(), Region::rescue());
+}
+
+/// Copy the message of `from`, if any, into `to`
+pub(crate) fn copy_message(from: Region, to: Region) {
+    // The rescue region must at least be able to hold the header
+    if to.len < HEADER_LEN {
+        return;
+    }
+
+    let payload = match find_message(from, RECORD_MAGIC) {
+        Some(payload) => payload,
+        None => return,
+    };
+
+    // The record is written again, as the message may be truncated, and the header is written
+    // last, so the copy is only valid once it is complete
+    let mut sink = RamSink::in_region(to);
+    match payload.location {
+        Some(location) => sink.begin(
+            location.line(),
+            location.column(),
+            location.file().as_bytes(),
+            || payload.fields,
+        ),
+        None => sink.begin(0, 0, &[], || payload.fields),
+    }
+    sink.write(payload.text);
+    sink.finalize();
+}
+
+/// Forget the copy of the message in the rescue region, once the original one was retrieved
+#[cfg(not(feature = "sticky"))]
+pub(crate) fn discard() {
+    let region = Region::rescue();
+
+    // The copy is wiped along with the original one
+    #[cfg(feature = "zeroize")]
+    crate::clear_region(region, true);
+
+    #[cfg(not(feature = "zeroize"))]
+    if region.len >= HEADER_LEN {
+        unsafe { region.start.cast::<u32>().write_unaligned(0x00000000) };
+    }
+}
@@ -0,0 +1,66 @@
+//! Diagnosis of the last reset, from its cause and the presence of a panic record
+
+/// The cause of the last reset, as reported by the reset controller of the MCU, such as the RCC_CSR
+/// register of the STM32s or the RESETREAS register of the nRF52s
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ResetCause {
+    /// The device was powered on
+    PowerOn,
+    /// The supply voltage dropped below the brownout threshold
+    Brownout,
+    /// A watchdog expired
+    Watchdog,
+    /// The firmware requested a reset, such as the panic handler does
+    Software,
+    /// The reset pin was asserted
+    Pin,
+    /// The core locked up, such as on a fault within the HardFault handler
+    Lockup,
+    /// Any other cause, or a cause the reset controller does not report
+    Other,
+}
+
+/// What happened before the current boot, as returned by [`diagnose_reset`]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ResetDiagnosis {
+    /// The firmware panicked, and the record is waiting to be retrieved
+    PanicReset,
+    /// A watchdog reset the device without a panic, so the firmware most likely hung
+    WatchdogNoRecord,
+    /// The firmware reset the device without a panic
+    SoftwareNoRecord,
+    /// The core locked up without a panic, which often means a fault
+    LockupNoRecord,
+    /// The device was powered on
+    PowerOn,
+    /// The supply voltage dropped too low
+    Brownout,
+    /// The reset pin was asserted
+    Pin,
+    /// The reset has another cause, and the firmware did not panic
+    Other,
+}
+
+/// Tell what happened before the current boot, from the cause of the reset, as returned by
+/// `read_cause` from the reset controller, and whether a panic record is waiting to be retrieved.
+///
+/// A panic record wins over the cause of the reset, as the panic handler resets the device either
+/// way it is configured, so this must be called before the record is retrieved with
+/// `get_panic_message_bytes`, which clears it. The record is left in place
+pub fn diagnose_reset(read_cause: impl FnOnce() -> ResetCause) -> ResetDiagnosis {
+    let cause = read_cause();
+
+    if crate::record_pending() {
+        return ResetDiagnosis::PanicReset;
+    }
+
+    match cause {
+        ResetCause::PowerOn => ResetDiagnosis::PowerOn,
+        ResetCause::Brownout => ResetDiagnosis::Brownout,
+        ResetCause::Watchdog => ResetDiagnosis::WatchdogNoRecord,
+        ResetCause::Software => ResetDiagnosis::SoftwareNoRecord,
+        ResetCause::Pin => ResetDiagnosis::Pin,
+        ResetCause::Lockup => ResetDiagnosis::LockupNoRecord,
+        ResetCause::Other => ResetDiagnosis::Other,
+    }
+}
@@ -0,0 +1,129 @@
+//! Ring of the records of the last panics, which the panic region is split into
+
+use crate::region::Region;
+use crate::{find_message, RECORD_MAGIC};
+
+#[cfg(feature = "rp2040")]
+compile_error!("The `panic-ring` feature can't be used with the `rp2040` feature");
+
+/// The number of records the ring holds
+pub(crate) const SLOTS: usize = 4;
+
+/// The slot at `index` of the panic region
+fn slot(index: usize) -> Region {
+    slot_of(Region::panic_dump(), index)
+}
+
+/// The slot at `index` of `region`. Each slot is a multiple of 4 bytes, to keep the headers aligned
+pub(crate) fn slot_of(region: Region, index: usize) -> Region {
+    let len = (region.len / SLOTS) & !3;
+
+    Region {
+        start: region.start.wrapping_add(index * len),
+        len,
+    }
+}
+
+/// The sequence number of the record held by `slot`, if it holds one
+fn sequence(slot: Region) -> Option<u32> {
+    find_message(slot, RECORD_MAGIC)?.fields.sequence
+}
+
+/// The index and the sequence number of the slot holding the newest record, if any
+fn newest_slot() -> Option<(usize, u32)> {
+    (0..SLOTS)
+        .filter_map(|index| Some((index, sequence(slot(index))?)))
+        .max_by_key(|(_, sequence)| *sequence)
+}
+
+/// The slot holding the newest record, if any
+#[cfg(any(
+    feature = "dedupe",
+    feature = "postcard",
+    feature = "rescue-region",
+    feature = "watermark"
+))]
+pub(crate) fn newest() -> Option<Region> {
+    Some(slot(newest_slot()?.0))
+}
+
+/// The slot the next record is written to: the one after the slot holding the newest record, which
+/// holds the oldest one once the ring is full
+pub(crate) fn next_slot() -> Region {
+    slot(newest_slot().map_or(0, |(index, _)| (index + 1) % SLOTS))
+}
+
+/// The sequence number of the next record, which is stored along with it
+pub(crate) fn next_sequence() -> Option<u32> {
+    Some(newest_slot().map_or(0, |(_, sequence)| sequence.wrapping_add(1)))
+}
+
+/// The slots of the ring, starting with the one holding the oldest record
+pub(crate) fn slots() -> impl Iterator<Item = Region> {
+    let oldest = newest_slot().map_or(0, |(index, _)| index + 1);
+
+    (0..SLOTS).map(move |index| slot((oldest + index) % SLOTS))
+}
+
+#[cfg(all(test, feature = "runtime-region"))]
+mod tests {
+    use super::*;
+    use crate::region::with_test_region;
+    use crate::{panic_records, persist_message};
+
+    /// Persist the records numbered `numbers`
+    fn persist(numbers: core::ops::Range<u32>) {
+        for number in numbers {
+            assert!(!persist_message(format_args!("record {}", number)).failed);
+        }
+    }
+
+    #[test]
+    fn records_go_to_the_slots_in_turn() {
+        with_test_region(2048, |_| {
+            assert_eq!(next_sequence(), Some(0));
+            assert_eq!(next_slot().start, slot(0).start);
+
+            persist(0..2);
+            assert_eq!(next_sequence(), Some(2));
+            assert_eq!(next_slot().start, slot(2).start);
+
+            let messages = panic_records().map(|record| record.message());
+            assert!(messages.eq([&b"record 0\n"[..], b"record 1\n"].iter().copied()));
+        });
+    }
+
+    #[test]
+    fn oldest_record_is_overwritten_once_full() {
+        with_test_region(2048, |_| {
+            persist(0..6);
+            assert_eq!(next_sequence(), Some(6));
+            assert_eq!(next_slot().start, slot(2).start);
+
+            // The records are retrieved oldest first
+            let expected = [
+                &b"record 2\n"[..],
+                b"record 3\n",
+                b"record 4\n",
+                b"record 5\n",
+            ];
+            let messages = panic_records().map(|record| record.message());
+            assert!(messages.eq(expected.iter().copied()));
+            assert!(panic_records().next().is_none());
+        });
+    }
+
+    #[test]
+    fn slots_keep_the_headers_aligned() {
+        let region = Region {
+            start: 0x2000_0000 as *mut u8,
+            len: 1030,
+        };
+
+        for index in 0..SLOTS {
+            let slot = slot_of(region, index);
+            assert_eq!(slot.len, 256);
+            assert_eq!(slot.start as usize, 0x2000_0000 + index * 256);
+        }
+    }
+}
@@ -0,0 +1,117 @@
+//! RP2040 (and RP2350) specific support
+
+#[cfg(feature = "rp2040-scratch")]
+use core::panic::Location;
+
+/// Number of cores, each of which gets its own half of the panic region
+#[cfg(feature = "rp2040")]
+pub(crate) const CORES: usize = 2;
+
+/// Index of the core this code is running on, read from `SIO.CPUID`
+#[cfg(all(feature = "rp2040", target_os = "none"))]
+pub(crate) fn core_id() -> usize {
+    const SIO_CPUID: *const u32 = 0xD000_0000 as *const u32;
+
+    unsafe { SIO_CPUID.read_volatile() as usize }
+}
+
+/// There is only a single "core" on hosted targets
+#[cfg(all(feature = "rp2040", not(target_os = "none")))]
+pub(crate) fn core_id() -> usize {
+    0
+}
+
+/// Marks a summary that has not been read yet
+#[cfg(feature = "rp2040-scratch")]
+const SCRATCH_MAGIC: u32 = 0x0FACADE0;
+
+/// Marks a summary that has already been read, the counter is still valid
+#[cfg(feature = "rp2040-scratch")]
+const SCRATCH_READ_MAGIC: u32 = 0x0FACADE1;
+
+/// A summary of the last panic, stored in the watchdog scratch registers
+#[cfg(feature = "rp2040-scratch")]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ScratchSummary {
+    /// FNV-1a hash of the file name of the panic location, or 0 if it was unknown
+    pub location_hash: u32,
+    /// Line of the panic location, or 0 if it was unknown
+    pub line: u32,
+    /// Number of panics recorded since the scratch registers were last cleared (by a power cycle)
+    pub count: u32,
+}
+
+/// The first four watchdog scratch registers. The last four are left alone, as the bootrom uses
+/// them to reboot into a given address
+#[cfg(all(feature = "rp2040-scratch", target_os = "none"))]
+fn scratch() -> Option<*mut u32> {
+    const WATCHDOG_SCRATCH0: *mut u32 = 0x4005_800C as *mut u32;
+
+    Some(WATCHDOG_SCRATCH0)
+}
+
+/// There are no scratch registers on hosted targets
+#[cfg(all(feature = "rp2040-scratch", not(target_os = "none")))]
+fn scratch() -> Option<*mut u32> {
+    None
+}
+
+/// Store a summary of a panic at `location` in the watchdog scratch registers
+#[cfg(feature = "rp2040-scratch")]
+pub(crate) fn write_scratch_summary(location: Option<&Location>) {
+    let regs = match scratch() {
+        Some(regs) => regs,
+        None => return,
+    };
+
+    let (location_hash, line) = match location {
+        Some(location) => (fnv1a(location.file().as_bytes()), location.line()),
+        None => (0, 0),
+    };
+
+    unsafe {
+        // Keep counting if the registers already hold a summary, read or not
+        let count = match regs.read_volatile() {
+            SCRATCH_MAGIC | SCRATCH_READ_MAGIC => regs.add(3).read_volatile().wrapping_add(1),
+            _ => 1,
+        };
+
+        regs.add(1).write_volatile(location_hash);
+        regs.add(2).write_volatile(line);
+        regs.add(3).write_volatile(count);
+        regs.write_volatile(SCRATCH_MAGIC);
+    }
+}
+
+/// Get the summary of the panic from the last boot stored in the watchdog
+/// scratch registers, if any.
+///
+/// If a summary existed, this function will only return the value once
+/// (subsequent calls will return None), but the counter keeps counting
+/// across panics.
+#[cfg(feature = "rp2040-scratch")]
+pub fn get_scratch_summary() -> Option<ScratchSummary> {
+    let regs = scratch()?;
+
+    unsafe {
+        if regs.read_volatile() != SCRATCH_MAGIC {
+            return None;
+        }
+
+        regs.write_volatile(SCRATCH_READ_MAGIC);
+
+        Some(ScratchSummary {
+            location_hash: regs.add(1).read_volatile(),
+            line: regs.add(2).read_volatile(),
+            count: regs.add(3).read_volatile(),
+        })
+    }
+}
+
+/// 32 bit FNV-1a hash of `bytes`
+#[cfg(feature = "rp2040-scratch")]
+fn fnv1a(bytes: &[u8]) -> u32 {
+    bytes.iter().fold(0x811C_9DC5, |hash, byte| {
+        (hash ^ u32::from(*byte)).wrapping_mul(0x0100_0193)
+    })
+}
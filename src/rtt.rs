@@ -0,0 +1,17 @@
+//! Output of the panic message to RTT, in addition to persisting it
+
+use core::fmt::Write;
+use core::panic::PanicInfo;
+
+use rtt_target::ChannelMode;
+
+/// Write the panic message to the print channel of `rtt-target`, if it was set up.
+///
+/// The channel is switched to non blocking mode, so the device is still reset when no probe
+/// reads the channel, at the cost of trimming the message if the channel is full.
+pub(crate) fn write(info: &PanicInfo) {
+    rtt_target::with_terminal_channel(|term| {
+        term.set_mode(ChannelMode::NoBlockTrim);
+        writeln!(term.write(0), "{}", info).ok();
+    });
+}
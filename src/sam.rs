@@ -0,0 +1,41 @@
+//! Microchip SAM backup RAM support
+
+use crate::hook::Hook;
+use crate::region::Region;
+
+/// Backup RAM of the SAMD5x and SAME5x families
+#[cfg(feature = "samd5x-bkupram")]
+const BKUPRAM_START: usize = 0x4700_0000;
+
+/// Backup RAM of the SAML21 family
+#[cfg(feature = "saml21-bkupram")]
+const BKUPRAM_START: usize = 0x3000_0000;
+
+/// All supported families have 8K of backup RAM
+const BKUPRAM_LEN: usize = 8192;
+
+#[cfg(all(feature = "samd5x-bkupram", feature = "saml21-bkupram"))]
+compile_error!("only one of the `samd5x-bkupram` and `saml21-bkupram` features can be enabled");
+
+/// Makes the backup RAM accessible
+static ENABLE: Hook = Hook::new();
+
+/// Register the function making the backup RAM accessible, which is called
+/// before the panic message is written to (or read from) the backup RAM,
+/// including from panic context.
+///
+/// This is typically needed when the backup RAM is kept in a low power
+/// state, or when its clock was disabled.
+pub fn set_bkupram_enable(f: fn()) {
+    ENABLE.set(f);
+}
+
+/// The backup RAM, after making it accessible
+pub(crate) fn bkupram() -> Region {
+    ENABLE.call();
+
+    Region {
+        start: BKUPRAM_START as *mut u8,
+        len: BKUPRAM_LEN,
+    }
+}
@@ -0,0 +1,53 @@
+//! Scratch area for the application, taken from the end of the panic region
+
+use core::cmp::min;
+
+use crate::region::Region;
+
+/// The length of the scratch area, which is the value of the `_panic_scratch_len` linker symbol
+#[cfg(target_os = "none")]
+pub(crate) fn scratch_len() -> usize {
+    extern "C" {
+        static _panic_scratch_len: u8;
+    }
+
+    core::ptr::addr_of!(_panic_scratch_len) as usize
+}
+
+/// There are no linker symbols on hosted targets, so there is no scratch area either
+#[cfg(not(target_os = "none"))]
+pub(crate) fn scratch_len() -> usize {
+    0
+}
+
+/// The length of the scratch area. It is shorter than requested by the `_panic_scratch_len` linker
+/// symbol if the memory of the panic region is not long enough
+pub fn get_scratch_len() -> usize {
+    Region::scratch().len
+}
+
+/// Copy the bytes of the scratch area starting at `offset` into `buf`, and return how many bytes
+/// were copied, which is less than the length of `buf` if the end of the area is reached
+pub fn read_scratch(offset: usize, buf: &mut [u8]) -> usize {
+    let scratch = Region::scratch();
+    let len = min(scratch.len.saturating_sub(offset), buf.len());
+
+    for (i, byte) in buf[..len].iter_mut().enumerate() {
+        *byte = unsafe { scratch.start.add(offset + i).read_volatile() };
+    }
+
+    len
+}
+
+/// Copy `data` into the scratch area, starting at `offset`, and return how many bytes were
+/// copied, which is less than the length of `data` if the end of the area is reached
+pub fn write_scratch(offset: usize, data: &[u8]) -> usize {
+    let scratch = Region::scratch();
+    let len = min(scratch.len.saturating_sub(offset), data.len());
+
+    for (i, byte) in data[..len].iter().enumerate() {
+        unsafe { scratch.start.add(offset + i).write_volatile(*byte) };
+    }
+
+    len
+}
@@ -0,0 +1,131 @@
+//! Checking that the panic region survives a reset
+
+use crate::header::Header;
+use crate::region::Region;
+use crate::{has_magic, HEADER_LEN};
+
+/// Magic word marking the test pattern, which is never taken for a panic message
+const TEST_MAGIC: u32 = 0x7E57CADE;
+
+/// The outcome of the persistence self-test started before the last reset, as reported by
+/// [`persistence_self_test`]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SelfTest {
+    /// The test pattern survived the reset, so panic messages do too
+    Survived,
+    /// The test pattern was found, but part of it was overwritten, such as by a bootloader or the
+    /// startup code using some of the region
+    Corrupted {
+        /// The offset in the region of the first word that was overwritten
+        offset: usize,
+    },
+    /// No test pattern was found: either no test was started before the last reset, or the whole
+    /// region was wiped (or lost power)
+    Missing,
+}
+
+/// Start the persistence self-test: fill the panic region with a test pattern, and reset the
+/// device the same way the panic handler does. [`persistence_self_test`] then tells, on the next
+/// boot, whether the pattern survived.
+///
+/// Any panic message still held by the region is lost. Nothing is written if the region is not
+/// aligned to 4 bytes, or can't hold the header.
+#[cfg(all(not(feature = "custom-panic-handler"), target_os = "none"))]
+pub fn start_persistence_self_test() -> ! {
+    write_pattern(Region::panic_dump());
+
+    crate::arch::reset();
+}
+
+/// Start the persistence self-test: fill the panic region with a test pattern. The device must
+/// then be reset, the same way the custom panic handler does, and [`persistence_self_test`] tells,
+/// on the next boot, whether the pattern survived.
+///
+/// Any panic message still held by the region is lost. Nothing is written if the region is not
+/// aligned to 4 bytes, or can't hold the header.
+#[cfg(not(all(not(feature = "custom-panic-handler"), target_os = "none")))]
+pub fn start_persistence_self_test() {
+    write_pattern(Region::panic_dump());
+}
+
+/// Report the outcome of the persistence self-test started by [`start_persistence_self_test`]
+/// before the last reset. This is meant to be called on boot, before anything else touches the
+/// panic region.
+///
+/// The outcome is only reported once (subsequent calls will return [`SelfTest::Missing`])
+pub fn persistence_self_test() -> SelfTest {
+    let region = Region::panic_dump();
+
+    if !has_magic(region, TEST_MAGIC) {
+        return SelfTest::Missing;
+    }
+
+    // Clear the magic word, so the pattern is never mistaken for a new test
+    unsafe { region.start.cast::<u32>().write_unaligned(0x00000000) };
+
+    // The rest of the header must be intact as well
+    let words = pattern_words(region);
+    let header = unsafe { Header::read(region.start) }.to_bytes();
+    let expected = header_of(0, words).to_bytes();
+    if let Some(offset) = (0..HEADER_LEN).find(|&at| header[at] != expected[at]) {
+        return SelfTest::Corrupted { offset };
+    }
+
+    for index in 0..words {
+        let word = unsafe { word_at(region, index).read_volatile() };
+
+        if word != pattern(index) {
+            return SelfTest::Corrupted {
+                offset: HEADER_LEN + index * 4,
+            };
+        }
+    }
+
+    SelfTest::Survived
+}
+
+/// Fill the message of `region` with the test pattern, and then write its header
+fn write_pattern(region: Region) {
+    // The region must at least be able to hold the header, and the pattern is written in words
+    if region.len < HEADER_LEN || !(region.start as usize).is_multiple_of(4) {
+        return;
+    }
+
+    #[cfg(all(feature = "mpu-guard", cortex_m))]
+    crate::mpu::release();
+
+    let words = pattern_words(region);
+    for index in 0..words {
+        unsafe { word_at(region, index).write_volatile(pattern(index)) };
+    }
+
+    // The magic word is written last, so the pattern is only checked once it is complete
+    unsafe { header_of(TEST_MAGIC, words).write(region.start) };
+
+    #[cfg(feature = "dcache-clean")]
+    crate::dcache::clean(region);
+}
+
+/// The header of a pattern of `words` words, marked with `magic`
+fn header_of(magic: u32, words: usize) -> Header {
+    Header::new(magic, (words * 4) as u32, 0)
+}
+
+/// The number of whole words of the pattern the message of `region` can hold
+fn pattern_words(region: Region) -> usize {
+    (region.len - HEADER_LEN) / 4
+}
+
+/// The word of the pattern at `index`, a multiplicative hash of it, so stuck bits and addressing
+/// errors are detected as well as wiped memory
+fn pattern(index: usize) -> u32 {
+    (index as u32 ^ 0x5A5A_5A5A).wrapping_mul(0x9E37_79B9)
+}
+
+/// The word of the pattern at `index` in `region`, which is aligned as the region is
+fn word_at(region: Region, index: usize) -> *mut u32 {
+    region
+        .start
+        .wrapping_add(HEADER_LEN + index * 4)
+        .cast::<u32>()
+}
@@ -0,0 +1,122 @@
+//! Semihosting calls, handled by the attached debugger or simulator
+//!
+//! On most cores, these calls fault when no debugger is attached. On Cortex-M, they are skipped
+//! when the core reports no debugger. The host writes the result of the call to the register that
+//! held the operation
+
+#[cfg(feature = "semihosting-mirror")]
+use core::fmt::Write;
+#[cfg(feature = "semihosting-mirror")]
+use core::panic::PanicInfo;
+
+#[cfg(feature = "semihosting-mirror")]
+use crate::sink::{Formatter, PanicSink};
+
+/// Semihosting operation writing a NUL terminated string to the debug console
+#[cfg(feature = "semihosting-mirror")]
+const SYS_WRITE0: usize = 0x04;
+
+/// Size of the chunks the message is written in
+#[cfg(feature = "semihosting-mirror")]
+const CHUNK_LEN: usize = 64;
+
+/// A sink writing to the debug console, in NUL terminated chunks
+#[cfg(feature = "semihosting-mirror")]
+struct Console {
+    buf: [u8; CHUNK_LEN + 1],
+    len: usize,
+}
+
+#[cfg(feature = "semihosting-mirror")]
+impl PanicSink for Console {
+    fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.buf[self.len] = *byte;
+            self.len += 1;
+
+            if self.len == CHUNK_LEN {
+                self.finalize();
+            }
+        }
+    }
+
+    /// Write the pending chunk
+    fn finalize(&mut self) {
+        if self.len == 0 {
+            return;
+        }
+
+        self.buf[self.len] = 0;
+        unsafe { call(SYS_WRITE0, self.buf.as_ptr() as usize) };
+        self.len = 0;
+    }
+}
+
+/// Write the panic message to the debug console
+#[cfg(feature = "semihosting-mirror")]
+pub(crate) fn write(info: &PanicInfo) {
+    let mut console = Console {
+        buf: [0; CHUNK_LEN + 1],
+        len: 0,
+    };
+
+    let mut out = Formatter(&mut console);
+    writeln!(out, "{}", info).ok();
+    out.0.finalize();
+}
+
+/// Issue the semihosting call `op`, unless no debugger is attached, as told by the `C_DEBUGEN` bit
+/// of `DHCSR`: the breakpoint would then escalate to a HardFault
+#[cfg(cortex_m)]
+pub(crate) unsafe fn call(op: usize, arg: usize) {
+    if !cortex_m::peripheral::DCB::is_debugger_attached() {
+        return;
+    }
+
+    core::arch::asm!("bkpt #0xab", inout("r0") op => _, in("r1") arg, options(nostack));
+}
+
+/// Issue the semihosting call `op`
+#[cfg(all(target_arch = "arm", not(cortex_m), thumb_mode))]
+pub(crate) unsafe fn call(op: usize, arg: usize) {
+    core::arch::asm!("svc #0xab", inout("r0") op => _, in("r1") arg, options(nostack));
+}
+
+/// Issue the semihosting call `op`
+#[cfg(all(target_arch = "arm", not(cortex_m), not(thumb_mode)))]
+pub(crate) unsafe fn call(op: usize, arg: usize) {
+    core::arch::asm!("svc #0x123456", inout("r0") op => _, in("r1") arg, options(nostack));
+}
+
+/// Issue the semihosting call `op`
+#[cfg(target_arch = "aarch64")]
+pub(crate) unsafe fn call(op: usize, arg: usize) {
+    core::arch::asm!("hlt #0xf000", inout("x0") op => _, in("x1") arg, options(nostack));
+}
+
+/// Issue the semihosting call `op`, through the uncompressed `ebreak` sequence the debugger or
+/// simulator recognizes
+#[cfg(any(target_arch = "riscv32", target_arch = "riscv64"))]
+pub(crate) unsafe fn call(op: usize, arg: usize) {
+    core::arch::asm!(
+        ".option push",
+        ".option norvc",
+        ".balign 16",
+        "slli x0, x0, 0x1f",
+        "ebreak",
+        "srai x0, x0, 7",
+        ".option pop",
+        inout("a0") op => _,
+        in("a1") arg,
+        options(nostack),
+    );
+}
+
+/// There is no semihosting on the other architectures, so the call does nothing
+#[cfg(not(any(
+    target_arch = "arm",
+    target_arch = "aarch64",
+    target_arch = "riscv32",
+    target_arch = "riscv64"
+)))]
+pub(crate) unsafe fn call(_op: usize, _arg: usize) {}
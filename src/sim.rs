@@ -0,0 +1,39 @@
+//! Ending the simulation after a panic, instead of resetting the device
+
+use crate::hook::Hook;
+
+/// Called instead of the semihosting exit, when registered
+static EXIT: Hook = Hook::new();
+
+/// Semihosting operation ending the program
+#[cfg(all(not(feature = "custom-panic-handler"), target_os = "none"))]
+const SYS_EXIT: usize = 0x18;
+
+/// Semihosting exit reason reporting an error, so simulators exit with a failure status
+#[cfg(all(not(feature = "custom-panic-handler"), target_os = "none"))]
+const ADP_STOPPED_RUN_TIME_ERROR_UNKNOWN: usize = 0x20023;
+
+/// Register `exit`, which is called after the panic is persisted instead of the semihosting
+/// exit, to end the simulation in another way. If it returns, the semihosting exit is used
+pub fn set_sim_exit(exit: fn()) {
+    EXIT.set(exit);
+}
+
+/// End the simulation through the registered function, or else through semihosting
+#[cfg(all(not(feature = "custom-panic-handler"), target_os = "none"))]
+pub(crate) fn exit() -> ! {
+    EXIT.call();
+
+    // On 64 bit targets, the exit reason is passed in a parameter block
+    #[cfg(target_pointer_width = "64")]
+    let block = [ADP_STOPPED_RUN_TIME_ERROR_UNKNOWN, 0];
+    #[cfg(target_pointer_width = "64")]
+    let arg = block.as_ptr() as usize;
+    #[cfg(not(target_pointer_width = "64"))]
+    let arg = ADP_STOPPED_RUN_TIME_ERROR_UNKNOWN;
+
+    unsafe { crate::semihosting::call(SYS_EXIT, arg) };
+
+    // The simulator does not support semihosting
+    crate::arch::reset();
+}
@@ -0,0 +1,222 @@
+//! Destinations the panic message is written to
+
+use core::panic::Location;
+use core::sync::atomic::{AtomicPtr, Ordering};
+
+/// A destination for the panic message, such as the RAM region of this crate (see [`RamSink`]),
+/// a backup SRAM or an external memory.
+///
+/// The message is formatted by the panic handler (or `report_panic_info_to`), and written to the
+/// sink in several chunks, from panic context: `write` must not panic, allocate, or wait for
+/// interrupts. The location of the panic, if known, is given to `location` first, and once the
+/// whole message was written, `finalize` is called once, so the sink can commit it, for example
+/// by writing its header.
+///
+/// [`RamSink`]: crate::RamSink
+pub trait PanicSink {
+    /// Store the location of the panic, before the message is written. The location is not part of
+    /// the formatted message, so sinks must store it themselves, or write it in front of the
+    /// message, as `IoSink` does: the default implementation drops it
+    fn location(&mut self, location: &Location<'_>) {
+        let _ = location;
+    }
+
+    /// Append `bytes` to the message. Bytes that don't fit are dropped
+    fn write(&mut self, bytes: &[u8]);
+
+    /// Finish writing the message
+    fn finalize(&mut self);
+
+    /// The outcome of writing the message, once it was finalized, which is returned by
+    /// `report_panic_info`. The default implementation reports nothing written and no failure, for
+    /// sinks that can't tell
+    fn status(&self) -> WriteStatus {
+        WriteStatus::default()
+    }
+}
+
+/// The outcome of persisting the panic message to a sink, as returned by `report_panic_info`, so a
+/// custom panic handler can fall back to another sink, or signal an error, when it failed
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct WriteStatus {
+    /// The number of bytes of the payload that were stored, the location and the extra fields
+    /// included. This is zero when the sink only counted one more repeat of the record it holds,
+    /// with the `dedupe` feature
+    pub written: usize,
+    /// Whether bytes of the message were dropped, as they did not fit
+    pub truncated: bool,
+    /// Whether the sink failed to store the message, such as because of an error of the memory
+    /// holding it, or because another core owns the region with the `multicore-lock` feature
+    pub failed: bool,
+}
+
+/// Adapter formatting the panic message into a sink
+pub(crate) struct Formatter<'a>(pub(crate) &'a mut dyn PanicSink);
+
+impl core::fmt::Write for Formatter<'_> {
+    fn write_str(&mut self, s: &str) -> Result<(), core::fmt::Error> {
+        self.0.write(s.as_bytes());
+
+        Ok(())
+    }
+}
+
+/// A [`PanicSink`] mirroring the panic message to several sinks, such as the [`RamSink`] (which
+/// always works) and an external memory (which survives power loss).
+///
+/// Every sink gets the whole message, in the order they are given. The sinks handle their errors
+/// themselves, so one that fails does not prevent the others from persisting the message.
+///
+/// [`RamSink`]: crate::RamSink
+pub struct MultiSink<'a, const N: usize> {
+    sinks: [&'a mut dyn PanicSink; N],
+}
+
+impl<'a, const N: usize> MultiSink<'a, N> {
+    /// Create a sink mirroring the panic message to `sinks`
+    pub fn new(sinks: [&'a mut dyn PanicSink; N]) -> Self {
+        Self { sinks }
+    }
+}
+
+impl<const N: usize> PanicSink for MultiSink<'_, N> {
+    fn location(&mut self, location: &Location<'_>) {
+        for sink in self.sinks.iter_mut() {
+            sink.location(location);
+        }
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for sink in self.sinks.iter_mut() {
+            sink.write(bytes);
+        }
+    }
+
+    fn finalize(&mut self) {
+        for sink in self.sinks.iter_mut() {
+            sink.finalize();
+        }
+    }
+
+    /// The fewest bytes stored by a sink, and whether any sink dropped bytes or failed
+    fn status(&self) -> WriteStatus {
+        let mut statuses = self.sinks.iter().map(|sink| sink.status());
+        let first = statuses.next().unwrap_or_default();
+
+        statuses.fold(first, |status, other| WriteStatus {
+            written: status.written.min(other.written),
+            truncated: status.truncated || other.truncated,
+            failed: status.failed || other.failed,
+        })
+    }
+}
+
+/// A sink registered at runtime, as a `&'static mut` of its own type, along with the function
+/// turning it back into a trait object. The slot is only set once, so the sink is never read along
+/// with the function of another sink
+pub(crate) struct SinkSlot {
+    sink: AtomicPtr<()>,
+    cast: AtomicPtr<()>,
+}
+
+impl SinkSlot {
+    /// A slot with no sink registered
+    pub(crate) const fn new() -> Self {
+        Self {
+            sink: AtomicPtr::new(core::ptr::null_mut()),
+            cast: AtomicPtr::new(core::ptr::null_mut()),
+        }
+    }
+
+    /// Register `sink`, which `cast` turns back into a trait object, unless a sink already was.
+    /// Returns whether `sink` was registered
+    pub(crate) fn set(
+        &self,
+        sink: *mut (),
+        cast: fn(*mut ()) -> &'static mut dyn PanicSink,
+    ) -> bool {
+        // The `cast` function claims the slot, and the sink is only published once it is stored
+        if !self.claim(cast as *mut ()) {
+            return false;
+        }
+        self.sink.store(sink, Ordering::Release);
+
+        true
+    }
+
+    /// Claim the slot with `cast`, unless it already was
+    #[cfg(target_has_atomic = "ptr")]
+    fn claim(&self, cast: *mut ()) -> bool {
+        self.cast
+            .compare_exchange(
+                core::ptr::null_mut(),
+                cast,
+                Ordering::Relaxed,
+                Ordering::Relaxed,
+            )
+            .is_ok()
+    }
+
+    /// Claim the slot with `cast`, unless it already was. Without compare and swap, this is not
+    /// atomic, so the sinks must not be registered from several contexts at once
+    #[cfg(not(target_has_atomic = "ptr"))]
+    fn claim(&self, cast: *mut ()) -> bool {
+        if !self.cast.load(Ordering::Relaxed).is_null() {
+            return false;
+        }
+        self.cast.store(cast, Ordering::Relaxed);
+
+        true
+    }
+
+    /// The registered sink, if any
+    pub(crate) fn get(&self) -> Option<&'static mut dyn PanicSink> {
+        let sink = self.sink.load(Ordering::Acquire);
+
+        if sink.is_null() {
+            return None;
+        }
+
+        // The `cast` function matching the type of the sink was stored before it, and never changes
+        let cast: fn(*mut ()) -> &'static mut dyn PanicSink =
+            unsafe { core::mem::transmute(self.cast.load(Ordering::Relaxed)) };
+
+        Some(cast(sink))
+    }
+}
+
+/// The sink registered with `set_panic_sink`, if any
+static SINK: SinkSlot = SinkSlot::new();
+
+/// Register `sink`, which the panic message is written to instead of the panic region of this
+/// crate. This is meant to be called once at startup, before any panic can happen, for example
+/// with a sink created at runtime from a flash driver.
+///
+/// Only the first sink is registered: this returns `false`, and `sink` is not used, if a sink
+/// already was. The `custom-sink` feature takes precedence over the registered sink.
+pub fn set_panic_sink<S: PanicSink>(sink: &'static mut S) -> bool {
+    SINK.set((sink as *mut S).cast::<()>(), cast::<S>)
+}
+
+/// Turn `sink`, which was registered as a `S`, back into a trait object
+fn cast<S: PanicSink + 'static>(sink: *mut ()) -> &'static mut dyn PanicSink {
+    unsafe { &mut *sink.cast::<S>() }
+}
+
+/// The sink registered with `set_panic_sink`, if any. The panic handler does not use it with the
+/// `custom-sink` feature
+#[cfg_attr(feature = "custom-sink", allow(dead_code))]
+pub(crate) fn registered() -> Option<&'static mut dyn PanicSink> {
+    SINK.get()
+}
+
+#[cfg(all(feature = "custom-sink", not(feature = "custom-panic-handler")))]
+extern "Rust" {
+    fn __panic_persist_sink() -> &'static mut dyn PanicSink;
+}
+
+/// The sink the panic handler writes to, which is provided by the user
+#[cfg(all(feature = "custom-sink", not(feature = "custom-panic-handler")))]
+pub(crate) fn custom() -> &'static mut dyn PanicSink {
+    unsafe { __panic_persist_sink() }
+}
@@ -0,0 +1,166 @@
+//! `NorFlash` driver for external SPI NOR flashes, such as the Winbond W25Q series
+
+use embedded_hal::spi::{Operation, SpiDevice};
+use embedded_storage::nor_flash::{
+    check_erase, check_read, check_write, ErrorType, NorFlash, NorFlashError, NorFlashErrorKind,
+    ReadNorFlash,
+};
+
+/// Command setting the write enable latch, needed before each program or erase
+const WRITE_ENABLE: u8 = 0x06;
+
+/// Command reading the first status register
+const READ_STATUS: u8 = 0x05;
+
+/// Command reading data
+const READ: u8 = 0x03;
+
+/// Command programming (part of) a page
+const PAGE_PROGRAM: u8 = 0x02;
+
+/// Command erasing a 4K sector
+const SECTOR_ERASE: u8 = 0x20;
+
+/// Busy bit of the first status register, set while a program or erase is in progress
+const BUSY: u8 = 0x01;
+
+/// Size of a page, which a single program command can't cross
+const PAGE_SIZE: usize = 256;
+
+/// Size of a sector, the smallest area that can be erased
+const SECTOR_SIZE: usize = 4096;
+
+/// A SPI NOR flash of `capacity` bytes, using the common 24 bit address commands (so the first 16M
+/// of the flash can be used), for use with [`FlashSink`].
+///
+/// The flash is used from panic context, so the `SpiDevice` must not rely on interrupts, and the
+/// bus must not be in use by the code that panicked.
+///
+/// [`FlashSink`]: crate::FlashSink
+pub struct SpiNorFlash<SPI> {
+    spi: SPI,
+    capacity: usize,
+}
+
+/// Errors of a [`SpiNorFlash`]
+#[derive(Debug)]
+pub enum SpiNorError<E> {
+    /// The SPI transfer failed
+    Spi(E),
+    /// The arguments are out of bounds, or not aligned
+    Flash(NorFlashErrorKind),
+}
+
+impl<E: core::fmt::Debug> NorFlashError for SpiNorError<E> {
+    fn kind(&self) -> NorFlashErrorKind {
+        match self {
+            Self::Spi(_) => NorFlashErrorKind::Other,
+            Self::Flash(kind) => *kind,
+        }
+    }
+}
+
+impl<SPI: SpiDevice> SpiNorFlash<SPI> {
+    /// Create the driver of a flash of `capacity` bytes on `spi`
+    pub const fn new(spi: SPI, capacity: usize) -> Self {
+        Self { spi, capacity }
+    }
+
+    /// Give back the SPI device
+    pub fn release(self) -> SPI {
+        self.spi
+    }
+
+    /// Run `command`, which programs or erases the flash at `address`, followed by `data`, and
+    /// wait until it is done
+    fn program(
+        &mut self,
+        command: u8,
+        address: u32,
+        data: &[u8],
+    ) -> Result<(), SpiNorError<SPI::Error>> {
+        self.spi.write(&[WRITE_ENABLE]).map_err(SpiNorError::Spi)?;
+        self.spi
+            .transaction(&mut [
+                Operation::Write(&header(command, address)),
+                Operation::Write(data),
+            ])
+            .map_err(SpiNorError::Spi)?;
+
+        loop {
+            let mut status = [0];
+            self.spi
+                .transaction(&mut [
+                    Operation::Write(&[READ_STATUS]),
+                    Operation::Read(&mut status),
+                ])
+                .map_err(SpiNorError::Spi)?;
+
+            if status[0] & BUSY == 0 {
+                return Ok(());
+            }
+        }
+    }
+}
+
+/// A command with a 24 bit address
+fn header(command: u8, address: u32) -> [u8; 4] {
+    let [_, a2, a1, a0] = address.to_be_bytes();
+
+    [command, a2, a1, a0]
+}
+
+impl<SPI: SpiDevice> ErrorType for SpiNorFlash<SPI> {
+    type Error = SpiNorError<SPI::Error>;
+}
+
+impl<SPI: SpiDevice> ReadNorFlash for SpiNorFlash<SPI> {
+    const READ_SIZE: usize = 1;
+
+    fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Self::Error> {
+        check_read(self, offset, bytes.len()).map_err(SpiNorError::Flash)?;
+
+        self.spi
+            .transaction(&mut [
+                Operation::Write(&header(READ, offset)),
+                Operation::Read(bytes),
+            ])
+            .map_err(SpiNorError::Spi)
+    }
+
+    fn capacity(&self) -> usize {
+        self.capacity
+    }
+}
+
+impl<SPI: SpiDevice> NorFlash for SpiNorFlash<SPI> {
+    const WRITE_SIZE: usize = 1;
+    const ERASE_SIZE: usize = SECTOR_SIZE;
+
+    fn erase(&mut self, from: u32, to: u32) -> Result<(), Self::Error> {
+        check_erase(self, from, to).map_err(SpiNorError::Flash)?;
+
+        for sector in (from..to).step_by(SECTOR_SIZE) {
+            self.program(SECTOR_ERASE, sector, &[])?;
+        }
+
+        Ok(())
+    }
+
+    fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Self::Error> {
+        check_write(self, offset, bytes.len()).map_err(SpiNorError::Flash)?;
+
+        // Each program command stays within a page
+        let mut offset = offset as usize;
+        let mut bytes = bytes;
+        while !bytes.is_empty() {
+            let len = core::cmp::min(PAGE_SIZE - offset % PAGE_SIZE, bytes.len());
+            self.program(PAGE_PROGRAM, offset as u32, &bytes[..len])?;
+
+            offset += len;
+            bytes = &bytes[len..];
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,128 @@
+//! Dump of the stack of the panic, kept in its own area of the region
+
+#[cfg(cortex_m)]
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+#[cfg(cortex_m)]
+use crate::fields::Fields;
+#[cfg(target_os = "none")]
+use crate::location::padded_len;
+use crate::location::LOCATION_LEN;
+#[cfg(cortex_m)]
+use crate::ram::RamSink;
+use crate::region::Region;
+#[cfg(cortex_m)]
+use crate::sink::PanicSink;
+use crate::{take_message, HEADER_LEN, RECORD_MAGIC};
+
+#[cfg(all(feature = "stack-dump", target_os = "none", not(cortex_m)))]
+compile_error!("The `stack-dump` feature is only supported on Cortex-M targets");
+
+/// The stack pointer on entry of the panic handler, or 0 until then
+#[cfg(cortex_m)]
+static PANIC_SP: AtomicUsize = AtomicUsize::new(0);
+
+/// The number of bytes of the stack to dump, which is the value of the `_panic_stack_dump_len`
+/// linker symbol, rounded up to a multiple of 4
+#[cfg(target_os = "none")]
+fn dump_len() -> usize {
+    extern "C" {
+        static _panic_stack_dump_len: u8;
+    }
+
+    padded_len(core::ptr::addr_of!(_panic_stack_dump_len) as usize)
+}
+
+/// There are no linker symbols on hosted targets, so there is no stack dump either
+#[cfg(not(target_os = "none"))]
+fn dump_len() -> usize {
+    0
+}
+
+/// The length of the area holding the dump: it is a record of its own, whose message is the stack
+/// pointer and the bytes of the stack
+pub(crate) fn area_len() -> usize {
+    match dump_len() {
+        0 => 0,
+        len => HEADER_LEN + LOCATION_LEN + 4 + len,
+    }
+}
+
+/// The stack of the panic, as returned by [`get_panic_stack_dump`]
+#[derive(Clone, Copy, Debug)]
+pub struct StackDump<'a> {
+    sp: u32,
+    bytes: &'a [u8],
+}
+
+impl<'a> StackDump<'a> {
+    /// The stack pointer on entry of the panic handler, which is the address of the first byte of
+    /// the dump
+    pub fn sp(&self) -> u32 {
+        self.sp
+    }
+
+    /// The bytes of the stack, from the stack pointer up. The dump is shorter than requested by
+    /// the `_panic_stack_dump_len` linker symbol when the top of the stack was reached
+    pub fn bytes(&self) -> &'a [u8] {
+        self.bytes
+    }
+}
+
+/// Get the dump of the stack of the panic from the last boot, if any.
+///
+/// If a dump existed, this function will only return the value once
+/// (subsequent calls will return None)
+pub fn get_panic_stack_dump() -> Option<StackDump<'static>> {
+    let text = take_message(Region::stack_dump(), RECORD_MAGIC)?.text;
+    if text.len() < 4 {
+        return None;
+    }
+
+    Some(StackDump {
+        sp: u32::from_le_bytes([text[0], text[1], text[2], text[3]]),
+        bytes: &text[4..],
+    })
+}
+
+/// Save the stack pointer. This is inlined into the panic handler, so the stack pointer is the one
+/// on its entry
+#[cfg(cortex_m)]
+#[inline(always)]
+pub(crate) fn capture() {
+    let sp: usize;
+    unsafe {
+        core::arch::asm!("mov {}, sp", out(reg) sp, options(nomem, nostack, preserves_flags))
+    };
+
+    PANIC_SP.store(sp, Ordering::Release);
+}
+
+/// Dump the stack from the saved stack pointer up, to the top of the stack, as given by the
+/// `_stack_start` symbol of `cortex-m-rt`, at most
+#[cfg(cortex_m)]
+pub(crate) fn write_dump() {
+    extern "C" {
+        static _stack_start: u8;
+    }
+
+    let area = Region::stack_dump();
+    let sp = PANIC_SP.load(Ordering::Acquire);
+    let top = core::ptr::addr_of!(_stack_start) as usize;
+
+    // The stack pointer of a process stack can be outside of the main stack, but it is then below
+    // it, with the rest of the RAM
+    if area.len < area_len() || sp == 0 || sp > top {
+        return;
+    }
+
+    let len = core::cmp::min(dump_len(), top - sp);
+    let stack = unsafe { core::slice::from_raw_parts(sp as *const u8, len) };
+
+    // The dump is written as a record, without the location of the panic, nor any extra field
+    let mut sink = RamSink::in_region(area);
+    sink.begin(0, 0, &[], Fields::default);
+    sink.write(&(sp as u32).to_le_bytes());
+    sink.write(stack);
+    sink.finalize();
+}
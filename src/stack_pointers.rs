@@ -0,0 +1,94 @@
+//! Stack pointers and CONTROL register at the time of the panic
+
+#[cfg(all(feature = "stack-pointers", target_os = "none", not(cortex_m)))]
+compile_error!("The `stack-pointers` feature is only supported on Cortex-M targets");
+
+/// Size of the stack pointers, as stored in a record
+pub(crate) const STACK_POINTERS_LEN: usize = 3 * 4;
+
+/// The Cortex-M stack pointers and CONTROL register, as read by the panic handler and returned by
+/// [`Record::stack_pointers`].
+///
+/// The stack pointer in use holds the frames of the panic handler on top of the ones of the code
+/// that panicked, while the other one is the value it had when the code that panicked was entered.
+///
+/// [`Record::stack_pointers`]: crate::Record::stack_pointers
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct StackPointers {
+    /// The main stack pointer
+    pub msp: u32,
+    /// The process stack pointer
+    pub psp: u32,
+    /// The CONTROL register
+    pub control: u32,
+}
+
+impl StackPointers {
+    /// Whether the panic happened on the process stack, such as in a task of an RTOS, rather than
+    /// on the main stack. This is the SPSEL bit of CONTROL, which always reads as zero in handler
+    /// mode, where the main stack is used
+    pub fn on_process_stack(&self) -> bool {
+        self.control & 0b10 != 0
+    }
+
+    /// The stack pointer in use when the panic happened
+    pub fn active(&self) -> u32 {
+        match self.on_process_stack() {
+            true => self.psp,
+            false => self.msp,
+        }
+    }
+
+    /// The stack pointers as stored in a record, in little endian
+    pub(crate) fn to_bytes(self) -> [u8; STACK_POINTERS_LEN] {
+        let mut bytes = [0; STACK_POINTERS_LEN];
+
+        for (chunk, word) in bytes
+            .chunks_exact_mut(4)
+            .zip(&[self.msp, self.psp, self.control])
+        {
+            chunk.copy_from_slice(&word.to_le_bytes());
+        }
+
+        bytes
+    }
+
+    /// The stack pointers stored in a record as `bytes`
+    pub(crate) fn from_bytes(bytes: &[u8; STACK_POINTERS_LEN]) -> Self {
+        let word =
+            |i: usize| u32::from_le_bytes([bytes[i], bytes[i + 1], bytes[i + 2], bytes[i + 3]]);
+
+        Self {
+            msp: word(0),
+            psp: word(4),
+            control: word(8),
+        }
+    }
+}
+
+/// Read the stack pointers and CONTROL. The panic handler runs in the mode of the code that
+/// panicked, so CONTROL tells which stack it was on
+#[cfg(cortex_m)]
+pub(crate) fn read() -> Option<StackPointers> {
+    let (msp, psp, control): (u32, u32, u32);
+    unsafe {
+        core::arch::asm!(
+            "mrs {}, msp",
+            "mrs {}, psp",
+            "mrs {}, control",
+            out(reg) msp,
+            out(reg) psp,
+            out(reg) control,
+            options(nomem, nostack, preserves_flags),
+        )
+    };
+
+    Some(StackPointers { msp, psp, control })
+}
+
+/// The stack pointers are only read on Cortex-M targets
+#[cfg(not(cortex_m))]
+pub(crate) fn read() -> Option<StackPointers> {
+    None
+}
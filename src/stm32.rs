@@ -0,0 +1,41 @@
+//! STM32 backup SRAM support
+
+use crate::hook::Hook;
+use crate::region::Region;
+
+/// Backup SRAM of the STM32F2, STM32F4 and STM32F7 families
+#[cfg(feature = "stm32f4-bkpsram")]
+const BKPSRAM_START: usize = 0x4002_4000;
+
+/// Backup SRAM of the STM32H7 family
+#[cfg(feature = "stm32h7-bkpsram")]
+const BKPSRAM_START: usize = 0x3880_0000;
+
+/// All supported families have 4K of backup SRAM
+const BKPSRAM_LEN: usize = 4096;
+
+#[cfg(all(feature = "stm32f4-bkpsram", feature = "stm32h7-bkpsram"))]
+compile_error!("only one of the `stm32f4-bkpsram` and `stm32h7-bkpsram` features can be enabled");
+
+/// Makes the backup SRAM accessible
+static ENABLE: Hook = Hook::new();
+
+/// Register the function making the backup SRAM accessible, which is called
+/// before the panic message is written to (or read from) the backup SRAM,
+/// including from panic context.
+///
+/// This typically enables the backup SRAM clock, and sets `PWR_CR.DBP` to
+/// disable the write protection of the backup domain.
+pub fn set_bkpsram_enable(f: fn()) {
+    ENABLE.set(f);
+}
+
+/// The backup SRAM, after making it accessible
+pub(crate) fn bkpsram() -> Region {
+    ENABLE.call();
+
+    Region {
+        start: BKPSRAM_START as *mut u8,
+        len: BKPSRAM_LEN,
+    }
+}
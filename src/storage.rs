@@ -0,0 +1,357 @@
+//! Sink persisting the panic message to a byte addressable `Storage`, such as an EEPROM or a FRAM
+
+use core::cmp::min;
+use core::panic::Location;
+
+use embedded_storage::Storage;
+
+use crate::crc::Crc32;
+use crate::fields::Fields;
+use crate::header::{Header, FORMAT_VERSION};
+use crate::location::{write_prefix, Block, LOCATION_LEN};
+use crate::record::{decode, Record};
+use crate::sink::{PanicSink, WriteStatus};
+use crate::{HEADER_LEN, RECORD_MAGIC};
+
+/// A [`PanicSink`] writing the panic message to the area of `len` bytes (header included) of
+/// `storage` starting at `offset`, with the same header as in the RAM region.
+///
+/// The message is written as it comes, and the header last, so a message that was only partly
+/// written is not mistaken for a complete one. It is retrieved on boot with
+/// [`get_storage_panic_message`].
+pub struct StorageSink<S> {
+    storage: S,
+    offset: u32,
+    len: usize,
+    written: usize,
+    crc: Crc32,
+    failed: bool,
+    /// Whether the location block was written
+    started: bool,
+    /// Whether bytes of the message were dropped, as they did not fit in the area
+    truncated: bool,
+}
+
+impl<S: Storage> StorageSink<S> {
+    /// Create a sink writing to the area of `len` bytes of `storage` starting at `offset`
+    pub const fn new(storage: S, offset: u32, len: usize) -> Self {
+        Self {
+            storage,
+            offset,
+            len,
+            written: 0,
+            crc: Crc32::new(),
+            failed: false,
+            started: false,
+            truncated: false,
+        }
+    }
+
+    /// Give back the storage
+    pub fn release(self) -> S {
+        self.storage
+    }
+
+    /// Start the payload with the location block of a panic at `line` and `column` of `file`, and
+    /// with the fields returned by `fields`, unless it was already started. The fields and the file
+    /// name are left out if they do not fit in the area
+    fn begin<'f>(
+        &mut self,
+        line: u32,
+        column: u32,
+        file: &[u8],
+        fields: impl FnOnce() -> Fields<'f>,
+    ) {
+        if self.started {
+            return;
+        }
+        self.started = true;
+
+        let max_len = self.len.saturating_sub(HEADER_LEN);
+        let fields = fields();
+        write_prefix(line, column, file, &fields, max_len, |bytes| {
+            self.append(bytes)
+        });
+    }
+
+    /// Append `bytes` to the payload. Bytes that don't fit are dropped
+    fn append(&mut self, bytes: &[u8]) {
+        // The area must at least be able to hold the header
+        if self.failed || self.len < HEADER_LEN {
+            return;
+        }
+
+        // Invalidate the previous message first, it is being overwritten
+        if self.written == 0 {
+            self.failed = self.storage.write(self.offset, &[0; 4]).is_err();
+        }
+
+        let len = min(self.len - HEADER_LEN - self.written, bytes.len());
+        self.truncated |= len < bytes.len();
+        if self.failed || len == 0 {
+            return;
+        }
+
+        let start = self.offset + (HEADER_LEN + self.written) as u32;
+        self.failed = self.storage.write(start, &bytes[..len]).is_err();
+        self.written += len;
+        self.crc.update(&bytes[..len]);
+    }
+}
+
+impl<S: Storage> PanicSink for StorageSink<S> {
+    fn location(&mut self, location: &Location<'_>) {
+        self.begin(
+            location.line(),
+            location.column(),
+            location.file().as_bytes(),
+            Fields::current,
+        );
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        self.begin(0, 0, &[], Fields::current);
+        self.append(bytes);
+    }
+
+    fn finalize(&mut self) {
+        self.begin(0, 0, &[], Fields::current);
+        if self.failed || self.len < HEADER_LEN {
+            return;
+        }
+
+        let header = Header::new(RECORD_MAGIC, self.written as u32, self.crc.finish())
+            .truncated(self.truncated)
+            .to_bytes();
+
+        // The rest of the header is written first, and the magic word last. Errors are only
+        // reported by the status of the sink
+        self.failed = self.storage.write(self.offset + 4, &header[4..]).is_err()
+            || self.storage.write(self.offset, &header[..4]).is_err();
+    }
+
+    fn status(&self) -> WriteStatus {
+        WriteStatus {
+            written: self.written,
+            truncated: self.truncated,
+            failed: self.failed || self.len < HEADER_LEN,
+        }
+    }
+}
+
+/// Get the panic message persisted by a [`StorageSink`] to the area of `storage` starting at
+/// `offset`, if any, by copying it into `buf`. Returns the bytes that were copied, which are
+/// truncated to the length of `buf` if the message does not fit. The location of the panic is
+/// returned along with the message by [`get_storage_panic_record`].
+///
+/// If a message existed, this function will only return the value once
+/// (subsequent calls will return None)
+pub fn get_storage_panic_message<'a, S: Storage>(
+    storage: &mut S,
+    offset: u32,
+    buf: &'a mut [u8],
+) -> Option<&'a [u8]> {
+    let header = take_header(storage, offset)?;
+    let len = header.len as usize;
+    let start = offset as usize + HEADER_LEN;
+
+    // The message is read in chunks, so it is checked even if it does not fit in `buf`
+    let mut chunk = [0; 16];
+    let mut crc = Crc32::new();
+    for at in (0..len).step_by(chunk.len()) {
+        let chunk_len = min(len - at, chunk.len());
+        storage
+            .read((start + at) as u32, &mut chunk[..chunk_len])
+            .ok()?;
+        crc.update(&chunk[..chunk_len]);
+    }
+    if crc.finish() != header.crc {
+        return None;
+    }
+
+    // The text follows the location block, the file name and the extra fields
+    let mut block = [0; LOCATION_LEN];
+    storage.read(start as u32, &mut block).ok()?;
+    let text_start = Block::parse(block)?.text_offset()?;
+    let len = len.checked_sub(text_start)?;
+
+    let copied = min(len, buf.len());
+    storage
+        .read((start + text_start) as u32, &mut buf[..copied])
+        .ok()?;
+
+    Some(&buf[..copied])
+}
+
+/// Get the panic record persisted by a [`StorageSink`] to the area of `storage` starting at
+/// `offset`, if any, with the location of the panic and the extra fields, by copying it into
+/// `buf`. The whole record, header included, must fit in `buf`, which is at most the length of the
+/// area.
+///
+/// If a record existed, this function will only return it once (subsequent calls will return
+/// None), even if it did not fit in `buf`
+pub fn get_storage_panic_record<'a, S: Storage>(
+    storage: &mut S,
+    offset: u32,
+    buf: &'a mut [u8],
+) -> Option<Record<'a>> {
+    let header = take_header(storage, offset)?;
+    let bytes = buf.get_mut(..HEADER_LEN + header.len as usize)?;
+
+    // The magic word was cleared, so the header is copied as it was read
+    bytes[..HEADER_LEN].copy_from_slice(&header.to_bytes());
+    storage
+        .read(offset + HEADER_LEN as u32, &mut bytes[HEADER_LEN..])
+        .ok()?;
+
+    decode(bytes, RECORD_MAGIC).ok()
+}
+
+/// Read the header of the record at `offset`, if it holds one, and clear its magic word to
+/// prevent the record from "sticking" across multiple boots. Returns None if the record was written
+/// with another layout, or its length does not fit in the storage
+fn take_header<S: Storage>(storage: &mut S, offset: u32) -> Option<Header> {
+    let mut bytes = [0; HEADER_LEN];
+    storage.read(offset, &mut bytes).ok()?;
+
+    let header = Header::from_bytes(bytes);
+    if header.magic != RECORD_MAGIC {
+        return None;
+    }
+    storage.write(offset, &[0; 4]).ok()?;
+
+    // The record was written with another layout, which can't be parsed
+    if header.version != FORMAT_VERSION {
+        return None;
+    }
+
+    let start = offset as usize + HEADER_LEN;
+    if header.len as usize > storage.capacity().saturating_sub(start) {
+        return None;
+    }
+
+    Some(header)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use embedded_storage::ReadStorage;
+
+    /// A byte addressable storage held in memory
+    struct TestStorage {
+        bytes: [u8; 256],
+    }
+
+    impl ReadStorage for TestStorage {
+        type Error = ();
+
+        fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), ()> {
+            let offset = offset as usize;
+            bytes.copy_from_slice(self.bytes.get(offset..offset + bytes.len()).ok_or(())?);
+
+            Ok(())
+        }
+
+        fn capacity(&self) -> usize {
+            self.bytes.len()
+        }
+    }
+
+    impl Storage for TestStorage {
+        fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), ()> {
+            let offset = offset as usize;
+            self.bytes
+                .get_mut(offset..offset + bytes.len())
+                .ok_or(())?
+                .copy_from_slice(bytes);
+
+            Ok(())
+        }
+    }
+
+    /// A sink writing to the area of `len` bytes at offset 16 of a storage with random looking
+    /// content
+    fn sink(len: usize) -> StorageSink<TestStorage> {
+        StorageSink::new(TestStorage { bytes: [0xA5; 256] }, 16, len)
+    }
+
+    /// Persist a panic at the location of the caller with `message` to `sink`
+    #[track_caller]
+    fn persist(sink: &mut StorageSink<TestStorage>, message: &[u8]) {
+        sink.location(Location::caller());
+        sink.write(message);
+        sink.finalize();
+    }
+
+    #[test]
+    fn record_round_trip() {
+        let mut sink = sink(128);
+        let line = line!() + 1;
+        persist(&mut sink, b"index out of bounds");
+        let status = sink.status();
+        assert!(!status.failed && !status.truncated);
+
+        let mut storage = sink.release();
+        let mut buf = [0; 128];
+        let record = get_storage_panic_record(&mut storage, 16, &mut buf).unwrap();
+        let location = record.location().unwrap();
+        assert_eq!(location.file(), file!());
+        assert_eq!(location.line(), line);
+        assert_eq!(record.message(), b"index out of bounds");
+        assert!(!record.was_truncated());
+
+        // The record is only returned once
+        assert!(get_storage_panic_record(&mut storage, 16, &mut buf).is_none());
+    }
+
+    #[test]
+    fn message_round_trip() {
+        let mut sink = sink(128);
+        persist(&mut sink, b"index out of bounds");
+
+        let mut storage = sink.release();
+        let mut buf = [0; 5];
+        assert_eq!(
+            get_storage_panic_message(&mut storage, 16, &mut buf),
+            Some(&b"index"[..])
+        );
+        assert_eq!(get_storage_panic_message(&mut storage, 16, &mut buf), None);
+    }
+
+    #[test]
+    fn long_message_is_truncated() {
+        let mut sink = sink(64);
+        persist(&mut sink, &[b'x'; 64]);
+        assert!(sink.status().truncated);
+
+        let mut buf = [0; 64];
+        let record = get_storage_panic_record(&mut sink.release(), 16, &mut buf).unwrap();
+        assert!(record.was_truncated());
+        assert!(record.message().iter().all(|&byte| byte == b'x'));
+    }
+
+    #[test]
+    fn partly_written_message_is_ignored() {
+        let mut sink = sink(128);
+        persist(&mut sink, b"index out of bounds");
+
+        // A second panic stops before the header was written
+        let mut sink = StorageSink::new(sink.release(), 16, 128);
+        sink.write(b"attempt to divide by zero");
+
+        let mut buf = [0; 128];
+        assert!(get_storage_panic_record(&mut sink.release(), 16, &mut buf).is_none());
+    }
+
+    #[test]
+    fn corrupted_record_is_ignored() {
+        let mut sink = sink(128);
+        persist(&mut sink, b"index out of bounds");
+
+        let mut storage = sink.release();
+        storage.bytes[16 + HEADER_LEN + 1] = 0;
+        let mut buf = [0; 128];
+        assert!(get_storage_panic_message(&mut storage, 16, &mut buf).is_none());
+    }
+}
@@ -0,0 +1,111 @@
+//! Usage of the panic region by the persisted records, kept in front of the panic region
+
+use crate::header::{Header, TRUNCATED};
+use crate::region::Region;
+use crate::{has_magic, HEADER_LEN, RECORD_MAGIC};
+
+/// Size of the usage block: three values, each followed by its complement, so random memory
+/// content after a power cycle is not mistaken for a value
+pub(crate) const USAGE_LEN: usize = 24;
+
+/// Index of the bytes used by the last record in the usage block
+const LAST: usize = 0;
+
+/// Index of the most bytes used by a record in the usage block
+const HIGH_WATER: usize = 1;
+
+/// Index of whether a record was truncated in the usage block
+const TRUNCATED_ONCE: usize = 2;
+
+/// How much of the panic region the persisted records used, as returned by [`region_usage`]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct RegionUsage {
+    /// The bytes of the region used by the last record, header included
+    pub last: usize,
+    /// The most bytes of the region used by a record, header included
+    pub high_water: usize,
+    /// The bytes of the region a record can use, header included
+    pub capacity: usize,
+    /// Whether the message of a record was truncated, as it did not fit in the region
+    pub truncated: bool,
+}
+
+/// The words of the usage block, if the memory of the region can hold it
+fn words() -> Option<*mut u32> {
+    let block = Region::usage()?;
+
+    // The block is accessed with aligned 32 bit reads and writes
+    if !(block as usize).is_multiple_of(4) {
+        return None;
+    }
+
+    Some(block.cast::<u32>())
+}
+
+/// The value at `index` in the usage block, or 0 if it is not valid
+fn read(index: usize) -> u32 {
+    let words = match words() {
+        Some(words) => words,
+        None => return 0,
+    };
+
+    let (value, check) = unsafe {
+        (
+            words.add(2 * index).read_volatile(),
+            words.add(2 * index + 1).read_volatile(),
+        )
+    };
+    match check == !value {
+        true => value,
+        false => 0,
+    }
+}
+
+/// Store `value` at `index` in the usage block
+fn write(index: usize, value: u32) {
+    if let Some(words) = words() {
+        unsafe {
+            words.add(2 * index).write_volatile(value);
+            words.add(2 * index + 1).write_volatile(!value);
+        }
+    }
+}
+
+/// How much of the panic region the records persisted since the usage was last reset with
+/// [`reset_region_usage`] (or since the region lost power) used, and whether one of them was
+/// truncated, to size the region. Unlike the panic message, the usage is not cleared when it is
+/// read.
+///
+/// As the panic message, it must be read before the region is guarded with `enable_mpu_guard`
+pub fn region_usage() -> RegionUsage {
+    RegionUsage {
+        last: read(LAST) as usize,
+        high_water: read(HIGH_WATER) as usize,
+        capacity: Region::last_record().len,
+        truncated: read(TRUNCATED_ONCE) != 0,
+    }
+}
+
+/// Reset the usage of the region, as returned by [`region_usage`]
+pub fn reset_region_usage() {
+    for index in [LAST, HIGH_WATER, TRUNCATED_ONCE] {
+        write(index, 0);
+    }
+}
+
+/// Account for the record persisted last
+pub(crate) fn update() {
+    let region = Region::last_record();
+    if !has_magic(region, RECORD_MAGIC) {
+        return;
+    }
+
+    let header = unsafe { Header::read(region.start) };
+    let used = (HEADER_LEN as u32).saturating_add(header.len);
+
+    write(LAST, used);
+    write(HIGH_WATER, read(HIGH_WATER).max(used));
+    if header.flags & TRUNCATED != 0 {
+        write(TRUNCATED_ONCE, 1);
+    }
+}
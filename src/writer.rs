@@ -0,0 +1,135 @@
+//! Writer streaming a message of the application into the panic region
+
+use core::fmt::Write;
+use core::panic::Location;
+
+use crate::ram::RamSink;
+use crate::sink::{PanicSink, WriteStatus};
+
+/// A `core::fmt::Write` streaming a message into the panic region, with the same header and the
+/// same fields as a panic message, so it is retrieved by the `get_panic_message_*` functions on
+/// the next boot. This lets a custom panic handler (or the application) write the message in
+/// several parts, such as the message, then the registers, then extra context;
+///
+/// ``` ignore
+/// let mut writer = panic_persist::PersistWriter::new();
+/// writer.location(info.location().unwrap());
+/// write!(writer, "{}", info.message()).ok();
+/// write!(writer, " r0={:#x}", r0).ok();
+/// let status = writer.finish();
+/// ```
+///
+/// The message is committed by [`PersistWriter::finish`], or when the writer is dropped. Like a
+/// panic, it overwrites the message held by the region, unless the region is claimed with the
+/// `multicore-lock` feature. The MPU region guarding the panic region with `enable_mpu_guard` is
+/// disabled, and the function registered with `set_before_write` is called, when the writer is
+/// created, and the MPU region is enabled again once the message is committed. Only one writer is
+/// meant to exist at a time
+pub struct PersistWriter {
+    /// The sink writing to the region, unless another core owns it
+    sink: Option<RamSink>,
+    /// Whether the message was committed
+    finished: bool,
+}
+
+impl PersistWriter {
+    /// Create a writer streaming a new message into the panic region
+    pub fn new() -> Self {
+        #[cfg(all(feature = "mpu-guard", cortex_m))]
+        crate::mpu::release();
+
+        #[cfg(feature = "before-write")]
+        crate::before_write::call();
+
+        // Another core already owns the region
+        #[cfg(feature = "multicore-lock")]
+        let sink = match crate::lock::claim() {
+            true => Some(RamSink::new()),
+            false => {
+                #[cfg(all(feature = "mpu-guard", cortex_m))]
+                crate::mpu::restore();
+
+                None
+            }
+        };
+        #[cfg(not(feature = "multicore-lock"))]
+        let sink = Some(RamSink::new());
+
+        Self {
+            sink,
+            finished: false,
+        }
+    }
+
+    /// Store `location` as the location of the message, as for a panic. This must be called before
+    /// anything is written, or it is ignored
+    pub fn location(&mut self, location: &Location<'_>) {
+        if let Some(sink) = &mut self.sink {
+            sink.location(location);
+        }
+    }
+
+    /// Write `bytes` to the message, which need not be UTF-8. Bytes that don't fit are dropped
+    pub fn write_bytes(&mut self, bytes: &[u8]) {
+        if let Some(sink) = &mut self.sink {
+            sink.write(bytes);
+        }
+    }
+
+    /// Commit the message, and return the outcome of writing it, as with `report_panic_info`
+    pub fn finish(mut self) -> WriteStatus {
+        self.commit()
+    }
+
+    /// Commit the message, unless it already was
+    fn commit(&mut self) -> WriteStatus {
+        let sink = match &mut self.sink {
+            Some(sink) => sink,
+            None => {
+                return WriteStatus {
+                    failed: true,
+                    ..WriteStatus::default()
+                }
+            }
+        };
+
+        if !self.finished {
+            self.finished = true;
+            sink.finalize();
+
+            #[cfg(feature = "watermark")]
+            crate::watermark::update();
+
+            #[cfg(feature = "multicore-lock")]
+            crate::lock::release();
+
+            #[cfg(feature = "dcache-clean")]
+            crate::dcache::clean(crate::region::Region::current());
+
+            #[cfg(all(feature = "mpu-guard", cortex_m))]
+            crate::mpu::restore();
+        }
+
+        sink.status()
+    }
+}
+
+impl Default for PersistWriter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Write for PersistWriter {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        self.write_bytes(s.as_bytes());
+
+        Ok(())
+    }
+}
+
+impl Drop for PersistWriter {
+    fn drop(&mut self) {
+        self.commit();
+    }
+}